@@ -0,0 +1,60 @@
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use game_boy_rust::GameBoy;
+use game_boy_rust::link::LinkConfig;
+use game_boy_rust::screenshot;
+use game_boy_rust::trace::TraceConfig;
+
+// ウィンドウも音声デバイスも使わずにROMを指定フレーム数だけ動かし、最後の画面を画像として保存する
+// usage: headless <ROM> <フレーム数> <出力先(.png / .ppm)> [--link-listen <port> | --link-connect <host:port> | --printer]
+//        [--trace <file> [--trace-start <addr>] [--trace-stop <addr>] [--trace-frames <first>-<last>]]
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 {
+        bail!(
+            "usage: {} <ROM> <frames> <output.png|output.ppm> [--link-listen <port> | --link-connect <host:port> | --printer] [--trace <file> ...]",
+            args[0]
+        );
+    }
+
+    let rom_path = &args[1];
+    let frames: usize = args[2].parse().with_context(|| format!("invalid frame count: {}", args[2]))?;
+    let output_path = Path::new(&args[3]);
+
+    let mut reader = BufReader::new(File::open(rom_path).with_context(|| format!("failed to open {}", rom_path))?);
+
+    // 音声は出力しないので、バッファが埋まった後のサンプルは捨てられる
+    let mut game_boy = GameBoy::new(&mut reader, 48000, 2000)?;
+
+    // 命令ごとのCPUの状態を書き出す。他のエミュレータのログと比べるのに使う
+    let mut options = args[4..].to_vec();
+    if let Some((path, config)) = TraceConfig::take_options(&mut options)? {
+        game_boy.start_trace(path, config)?;
+    }
+
+    // プリンタの印刷結果は出力先の画像と同じディレクトリに保存する
+    if let Some(link) = LinkConfig::from_args(&options)? {
+        let printer_dir = output_path.parent().unwrap_or(Path::new("."));
+        game_boy.set_serial_endpoint(link.connect(printer_dir)?);
+    }
+
+    for _ in 0..frames {
+        game_boy.run_frame()?;
+        for printout in game_boy.take_printouts() {
+            match printout {
+                Ok(path) => println!("printed to {}", path.display()),
+                Err(e) => eprintln!("failed to save printout: {}", e)
+            }
+        }
+    }
+
+    game_boy.stop_trace()?;
+    screenshot::save(output_path, game_boy.frame_buffer(), 160, 144)?;
+
+    Ok(())
+}
@@ -2,7 +2,10 @@ use std::{io::BufReader, fs::File, io::Read, io::Seek};
 
 use anyhow::{Result, bail};
 
-use crate::{mbc::{Mbc, NoMbc, Mbc1, Mbc5}, ppu::Ppu, joypad::Joypad, timer::Timer, rom::Rom, sound::Sound};
+use crate::{mbc::{Mbc, NoMbc, Mbc1, Mbc2, Mbc3, Mbc5}, ppu::Ppu, joypad::Joypad, timer::Timer, rom::{CartridgeType, Rom}, sound::Sound, serial::Serial};
+use crate::debugger::Watcher;
+use crate::error::EmuError;
+use crate::state::{StateReader, StateWriter};
 
 pub struct Bus {
     pub ram: [u8; 0x8192],
@@ -13,23 +16,29 @@ pub struct Bus {
     pub timer: Timer,
     pub joypad: Joypad,
     pub sound: Sound,
+    pub serial: Serial,
+    // CGBとして動いているか。CGBには未対応なので今は常にfalse
+    pub cgb_mode: bool,
+    // KEY1(CGBの速度切り替え)。bit 7が現在の速度、bit 0が切り替えの準備
+    pub key1: u8,
     // interrupt enable
     pub ie_flag: u8,
     // interrupt flag
-    pub int_flag: u8
+    pub int_flag: u8,
+    // デバッガのウォッチポイント
+    pub watcher: Watcher
 }
 
 impl Bus {
-    pub fn new<T>(reader: &mut T, sample_rate: usize, buffer_size: usize) -> Self 
+    pub fn new<T>(reader: &mut T, sample_rate: usize, buffer_size: usize) -> Result<Self>
         where T: Read + Seek
     {
-        let rom = Rom::new(reader).unwrap();
+        let rom = Rom::new(reader).map_err(|e| EmuError::classify(e, EmuError::InvalidHeader))?;
         let rom_type = rom.cartridge_type;
-        let rom_size = rom.rom_size;
-        let ram_size = rom.ram_size;
+        let ram_bytes = rom.ram_bytes();
 
         let mbc: Box<dyn Mbc + Send> = match rom_type {
-            0x00 => {
+            CartridgeType::RomOnly => {
                 Box::new(
                     NoMbc {
                         rom,
@@ -37,22 +46,56 @@ impl Bus {
                     }
                 )
             },
-            0x01 | 0x02 | 0x03 => {
+            CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
                 Box::new(
                     Mbc1 {
                         rom,
                         mbc_type: rom_type,
-                        rom_size,
-                        ram_size,
                         is_external_ram_enable: Default::default(),
                         rom_bank_number: Default::default(),
                         ram_bank_number: Default::default(),
                         mode_flag: Default::default(),
-                        ram: [0; 0x8000]
+                        ram: vec![0; ram_bytes],
+                        dirty: false
                     }
                 )
             },
-            0x19..=0x1E | _ => {
+            CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => {
+                Box::new(
+                    Mbc2 {
+                        rom,
+                        mbc_type: rom_type,
+                        is_external_ram_enable: Default::default(),
+                        rom_bank_number: Default::default(),
+                        ram: vec![0; 0x200],
+                        dirty: false
+                    }
+                )
+            },
+            CartridgeType::Mbc3TimerBattery
+                | CartridgeType::Mbc3TimerRamBattery
+                | CartridgeType::Mbc3
+                | CartridgeType::Mbc3Ram
+                | CartridgeType::Mbc3RamBattery => {
+                Box::new(
+                    Mbc3 {
+                        rom,
+                        mbc_type: rom_type,
+                        is_external_ram_enable: Default::default(),
+                        rom_bank_number: Default::default(),
+                        ram_bank_number: Default::default(),
+                        ram: vec![0; ram_bytes],
+                        rtc: Default::default(),
+                        dirty: false
+                    }
+                )
+            },
+            CartridgeType::Mbc5
+                | CartridgeType::Mbc5Ram
+                | CartridgeType::Mbc5RamBattery
+                | CartridgeType::Mbc5Rumble
+                | CartridgeType::Mbc5RumbleRam
+                | CartridgeType::Mbc5RumbleRamBattery => {
                 Box::new(
                     Mbc5 {
                         rom,
@@ -61,16 +104,18 @@ impl Bus {
                         rom_bank_number_low: Default::default(),
                         rom_bank_number_high: Default::default(),
                         ram_bank_number: Default::default(),
-                        ram: [0; 0x20000]
+                        ram: vec![0; ram_bytes],
+                        dirty: false
                     }
                 )
             },
+            unsupported => bail!(EmuError::UnsupportedMapper(unsupported))
         };
 
         let ppu = Ppu::new();
-        let sound = Sound::new(sample_rate, buffer_size).unwrap();
+        let sound = Sound::new(sample_rate, buffer_size)?;
 
-        Self { 
+        Ok(Self {
             ram: [0; 0x8192],
             hram: [0; 0x127],
             ppu,
@@ -79,12 +124,24 @@ impl Bus {
             dma: Default::default(),
             joypad: Default::default(),
             sound,
+            serial: Default::default(),
+            cgb_mode: false,
+            key1: Default::default(),
             ie_flag: Default::default(),
-            int_flag: Default::default()
-        }
+            int_flag: Default::default(),
+            watcher: Default::default()
+        })
     }
 
+    // CPUからの読み込み。ウォッチポイントの範囲内なら記録する
     pub fn read(&self, address: u16) -> Result<u8> {
+        let data = self.peek(address)?;
+        self.watcher.on_read(address, data);
+        Ok(data)
+    }
+
+    // ウォッチポイントに引っかからない読み込み。デバッガやDMA、命令の読み込みで使う
+    pub fn peek(&self, address: u16) -> Result<u8> {
         match address {
             0x0000..=0x7FFF => self.mbc.read_rom(address),
             0x8000..=0x9FFF => self.ppu.read(address-0x8000),
@@ -94,7 +151,8 @@ impl Bus {
             0xFE00..=0xFE9F => self.ppu.read_OAM(address-0xFE00),
             0xFEA0..=0xFEFF => Ok(0),
             0xFF00 => Ok(self.joypad.read()),
-            0xFF01..=0xFF03 => Ok(0),
+            0xFF01 => Ok(self.serial.read_sb()),
+            0xFF02 => Ok(self.serial.read_sc()),
             0xFF04 => Ok(self.timer.read_div()),
             0xFF05 => Ok(self.timer.read_tima()),
             0xFF06 => Ok(self.timer.read_tma()),
@@ -112,7 +170,8 @@ impl Bus {
             0xFF48..=0xFF49 => self.ppu.read_obp(address),
             0xFF4A => self.ppu.wy_read(),
             0xFF4B => self.ppu.wx_read(),
-            0xFF4C..=0xFF4E => Ok(0),
+            0xFF4C | 0xFF4E => Ok(0),
+            0xFF4D => Ok(self.read_key1()),
             0xFF80..=0xFFFE => Ok(self.hram[(address-0xFF80) as usize]),
             0xFFFF => Ok(self.ie_flag),
             _ => Ok(0xFF)
@@ -127,7 +186,17 @@ impl Bus {
         Ok(data)
     }
 
+    // CPUからの書き込み。ウォッチポイントの範囲内なら書き込む前の値と一緒に記録する
     pub fn write(&mut self, address: u16, data: u8) -> Result<()> {
+        if self.watcher.is_watching(address) {
+            let old = self.peek(address)?;
+            self.watcher.on_write(address, old, data);
+        }
+        self.poke(address, data)
+    }
+
+    // ウォッチポイントに引っかからない書き込み。デバッガから使う
+    pub fn poke(&mut self, address: u16, data: u8) -> Result<()> {
         match address {
             0x0000..=0x7FFF => self.mbc.write_registers(address, data),
             0x8000..=0x9FFF => self.ppu.write(address-0x8000, data),
@@ -146,7 +215,14 @@ impl Bus {
                 self.joypad.write(data);
                 Ok(())
             },
-            0xFF01..=0xFF03 => Ok(()),
+            0xFF01 => {
+                self.serial.write_sb(data);
+                Ok(())
+            },
+            0xFF02 => {
+                self.serial.write_sc(data);
+                Ok(())
+            },
             0xFF04 => {
                 self.timer.write_div(data);
                 Ok(())
@@ -179,7 +255,11 @@ impl Bus {
             0xFF48..=0xFF49 => self.ppu.write_obp(address, data),
             0xFF4A => self.ppu.wy_write(data),
             0xFF4B => self.ppu.wx_write(data),
-            0xFF4C..=0xFF4E => Ok(()),
+            0xFF4C | 0xFF4E => Ok(()),
+            0xFF4D => {
+                self.write_key1(data);
+                Ok(())
+            },
             0xFF80..=0xFFFE => {
                 self.hram[(address-0xFF80) as usize] = data;
                 Ok(())
@@ -207,11 +287,72 @@ impl Bus {
         let source: u16 = (data as u16) << 8;
         for i in 0..0xA0_u16 {
             let src_address = source + i;
-            let data = self.read(src_address)?;
+            let data = self.peek(src_address)?;
             let dest_address = i;
             self.ppu.write_OAM(dest_address, data, true)?;
         }
 
         Ok(())
     }
+
+    // DMGではKEY1は存在しない
+    fn read_key1(&self) -> u8 {
+        if self.cgb_mode {
+            self.key1 | 0x7E
+        }
+        else {
+            0
+        }
+    }
+
+    fn write_key1(&mut self, data: u8) {
+        // 書き込めるのは切り替えの準備のbit 0だけ
+        if self.cgb_mode {
+            self.key1 = (self.key1 & 0x80) | (data & 0x01);
+        }
+    }
+
+    // STOPで速度を切り替える準備ができているか
+    pub fn is_speed_switch_armed(&self) -> bool {
+        self.cgb_mode && (self.key1 & 0x01) == 0x01
+    }
+
+    // 通常速度と倍速を切り替え、準備のbitを下ろす
+    pub fn switch_speed(&mut self) {
+        self.key1 = (self.key1 ^ 0x80) & 0x80;
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        (self.key1 & 0x80) == 0x80
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        writer.write_bytes(&self.hram);
+        writer.write_u8(self.dma);
+        writer.write_u8(self.ie_flag);
+        writer.write_u8(self.int_flag);
+        writer.write_u8(self.key1);
+        self.ppu.save_state(writer);
+        self.timer.save_state(writer);
+        self.joypad.save_state(writer);
+        self.sound.save_state(writer);
+        self.serial.save_state(writer);
+        self.mbc.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.read_bytes(&mut self.ram)?;
+        reader.read_bytes(&mut self.hram)?;
+        self.dma = reader.read_u8()?;
+        self.ie_flag = reader.read_u8()?;
+        self.int_flag = reader.read_u8()?;
+        self.key1 = reader.read_u8()?;
+        self.ppu.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.joypad.load_state(reader)?;
+        self.sound.load_state(reader)?;
+        self.serial.load_state(reader)?;
+        self.mbc.load_state(reader)
+    }
 }
@@ -1,8 +1,29 @@
-use std::io;
+use std::fmt;
 
 use anyhow::{bail, Result};
 
 use crate::{bus::Bus};
+use crate::debugger::{Debugger, Register, Registers, WatchHit};
+use crate::instruction::{decode, Condition, Decoded, Instruction, Operand, Reg16, Reg8};
+use crate::state::{StateReader, StateWriter};
+use crate::rewind::{Rewind, Snapshot};
+use crate::trace::Tracer;
+
+// 未定義の命令を実行してCPUがロックした
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFault {
+    pub pc: u16,
+    pub opcode: u8
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CPU locked up: illegal opcode {:#04X} at PC {:#06X}", self.opcode, self.pc)
+    }
+}
+
+impl std::error::Error for CpuFault {}
+
 pub struct Cpu {
     A: u8,
     B: u8,
@@ -16,20 +37,31 @@ pub struct Cpu {
     PC: u16,
     pub bus: Bus,
     halt: bool,
+    // STOPによる低消費電力モード
+    stop: bool,
     ime: bool,
-    pub step_flag: bool,
+    // EIを実行した。次の命令の実行後にIMEが有効になる
+    ime_scheduled: bool,
+    // HALTバグで、次の命令の読み込み時にPCが進まない
+    halt_bug: bool,
+    // 未定義の命令でCPUがロックしている
+    fault: Option<CpuFault>,
     pub debug_flag: bool,
-    break_points: Vec<u16>,
-    jmp_flag: bool,
+    pub debugger: Debugger,
+    // 命令ごとの状態をファイルに書き出す
+    tracer: Option<Tracer>,
+    pub rewind: Rewind,
+    // 電源を入れてから実行したステップ数。巻き戻しで戻る位置に使う
+    steps: u64,
+    // 巻き戻しのために実行し直している。ブレークポイントやトレースは無視する
+    replaying: bool,
+    // 実行し直している間のウォッチポイントへのアクセス
+    replay_hits: Vec<WatchHit>,
+    // 実行中の命令がメモリアクセスなどですでに進めたサイクル数
+    cycles: u8,
     pub sleep: bool
 }
 
-#[derive(Default)]
-pub struct Opcode {
-    pub cb_prefix: bool,
-    pub code: u8
-}
-
 impl Cpu {
     pub fn new(bus: Bus) -> Self {
         Self {
@@ -45,11 +77,19 @@ impl Cpu {
             PC: 0x100,
             bus,
             halt: Default::default(),
+            stop: Default::default(),
             ime: Default::default(),
-            step_flag: Default::default(),
+            ime_scheduled: Default::default(),
+            halt_bug: Default::default(),
+            fault: Default::default(),
             debug_flag: Default::default(),
-            break_points: Default::default(),
-            jmp_flag: false,
+            debugger: Default::default(),
+            tracer: Default::default(),
+            rewind: Default::default(),
+            steps: Default::default(),
+            replaying: Default::default(),
+            replay_hits: Default::default(),
+            cycles: Default::default(),
             sleep: Default::default()
         }
     }
@@ -62,6 +102,12 @@ impl Cpu {
         self.set_hl(0x014D);
         self.SP = 0xFFFE;
         self.PC = 0x0100;
+        self.fault = None;
+    }
+
+    // CPUがロックしている場合は、その原因
+    pub fn fault(&self) -> Option<CpuFault> {
+        self.fault
     }
 
     // メインループ
@@ -69,59 +115,204 @@ impl Cpu {
         let max_cycle: usize = 70224;
         let mut current_cycle: usize = 0;
         self.sleep = false;
-        // self.step_flag = true;
         // self.debug_flag = true;
 
-        while current_cycle < max_cycle {
-            // 現在のPCにブレークポイントが張られていないか確認
-            self.check_break_points();
-            // halt時は4サイクルずつPPUなどを進める
-            let mut op_cycle = 4;
-
-            if !self.halt {
-                // 命令コードを取得
-                let opcode: Opcode = self.read_inst()?;
-    
-                if self.debug_flag {
-                    self.debug_output(&opcode);
+        let mut result = Ok(());
+        // デバッガで止まった場合はフレームの途中でも抜ける
+        while current_cycle < max_cycle && !self.debugger.is_stopped() {
+            // 現在のサイクル数を更新
+            match self.step() {
+                Ok(cycles) => current_cycle += cycles as usize,
+                Err(e) => {
+                    result = Err(e);
+                    break;
                 }
-    
-                // 命令コードを実行
-                op_cycle = self.excute_op(&opcode)?;
+            }
+        }
 
-                // ステップ実行が有効化されていた場合はステップ実行に
-                if self.step_flag {
-                    self.stepping(&opcode);
-                }
-                
-                // PCをインクリメント
-                if !self.jmp_flag {
-                    self.increment_pc();
+        if current_cycle >= max_cycle {
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.end_frame();
+            }
+            if self.rewind.end_frame() {
+                let snapshot = self.snapshot();
+                self.rewind.push(snapshot);
+            }
+        }
+
+        // エラーで止まった場合も、それまでの画面は描画できるようにする
+        self.sleep = true;
+        result
+    }
+
+    // 1命令(halt中は4サイクル)と割り込みを実行し、経過したサイクル数を返す
+    // デバッガで止まって何も実行しなかった場合は0を返し、ステップ数も数えない
+    pub fn step(&mut self) -> Result<u8> {
+        let result = self.execute_step();
+        // 実行し直すときに同じデータを受信できるように記録する
+        if let Some(data) = self.bus.serial.take_received() {
+            if !self.replaying {
+                self.rewind.record_received(self.steps, data);
+            }
+        }
+        if !matches!(result, Ok(0)) {
+            self.steps += 1;
+        }
+        result
+    }
+
+    fn execute_step(&mut self) -> Result<u8> {
+        // STOP中はクロックが止まっているので、ボタンが押されるのを待つだけ
+        if self.stop {
+            if self.bus.joypad.is_line_low() {
+                self.stop = false;
+            }
+            return Ok(4);
+        }
+
+        // CPUがロックしている間は命令も割り込みも実行されず、他のコンポーネントだけが動く
+        if self.fault.is_some() {
+            self.cycles = 0;
+            return Ok(self.finish_cycles(4));
+        }
+
+        // ブレークポイントなどで止まる場合は、命令を実行せずに戻る
+        if !self.halt && !self.replaying && self.debugger.check(&self.registers(), &self.bus) {
+            return Ok(0);
+        }
+
+        // halt時は4サイクルずつPPUなどを進める
+        let mut op_cycle = 4;
+        self.cycles = 0;
+        // この命令の前にEIが実行されていたか
+        let enable_ime = self.ime_scheduled;
+
+        if !self.halt {
+            if self.tracer.is_some() && !self.replaying {
+                let registers = self.registers();
+                if let Some(tracer) = self.tracer.as_mut() {
+                    tracer.log(&registers, &self.bus)?;
                 }
-                else {
-                    self.jmp_flag = false;
+            }
+
+            let pc = self.PC;
+            // 命令を読み込んでデコードする。PCは次の命令を指す
+            let decoded = self.fetch_inst()?;
+
+            if self.debug_flag {
+                self.debug_output(pc, &decoded);
+            }
+
+            // 未定義の命令は実機と同じようにCPUをロックし、呼び出し元にエラーとして知らせる
+            if let Instruction::Illegal(opcode) = decoded.instruction {
+                let fault = CpuFault { pc, opcode };
+                self.fault = Some(fault);
+                self.finish_cycles(4);
+
+                // デバッガを止めて、ロックした場所を表示させる
+                if !self.replaying {
+                    self.debugger.stop_on_fault(fault);
                 }
+                return Err(fault.into());
             }
 
-            // PPUをサイクル分動かす
-            self.bus.ppu.tick(op_cycle);
-            
-            // Timerをサイクル分動かす
-            // 一つずつ動かさないとbit操作が壊れるため、for文で動かす
-            for _ in 0..op_cycle {
-                self.bus.timer.tick();
-                let div = self.bus.timer.read_div();
-                self.bus.sound.tick(div);
+            // 命令を実行
+            let sp = self.SP;
+            op_cycle = self.excute_op(&decoded)?;
+
+            // CALLとRETをコールスタックに反映する
+            self.debugger.track(pc, &decoded.instruction, decoded.length, sp, self.PC, self.SP);
+        }
+
+        // メモリアクセスの時点までは命令の実行中に進めているので、残りのサイクル分だけ進める
+        let mut elapsed = self.finish_cycles(op_cycle);
+
+        // EIの効果は次の命令の実行後に反映される
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+
+        // 割り込みを実行する
+        let int_cycle = self.interrupt()?;
+        elapsed += self.finish_cycles(int_cycle);
+
+        // ウォッチポイントに引っかかった場合は、この命令の実行後で止まる
+        // 巻き戻しの再実行中のアクセスも取り出しておき、後の命令で止まらないようにする
+        if self.bus.watcher.has_hits() {
+            let mut hits = self.bus.watcher.take_hits();
+            if self.replaying {
+                self.replay_hits.append(&mut hits);
             }
+            else {
+                self.debugger.check_watch(&hits, &self.registers(), &self.bus);
+            }
+        }
 
-            // 割り込みを実行する
-            op_cycle += self.interrupt();
+        Ok(elapsed)
+    }
 
-            // 現在のサイクル数を更新
-            current_cycle += op_cycle as usize;
+    // PPUやタイマーなど、CPU以外のコンポーネントをサイクル分動かす
+    fn tick(&mut self, cycles: u8) {
+        // PPUをサイクル分動かす
+        self.bus.ppu.tick(cycles);
+
+        // カートリッジ(MBC3のRTCなど)をサイクル分動かす
+        self.bus.mbc.tick(cycles);
+            
+        // Timerをサイクル分動かす
+        // 一つずつ動かさないとbit操作が壊れるため、for文で動かす
+        for _ in 0..cycles {
+            self.bus.timer.tick();
+            self.bus.serial.tick();
+            let div = self.bus.timer.read_div();
+            self.bus.sound.tick(div);
         }
 
-        self.sleep = true;
+        // 命令の途中でIFを読んだときにも反映されているように、割り込み要求をすぐにIFへ移す
+        self.update_interrupt();
+    }
+
+    // 1マシンサイクル(4クロック)分進める
+    fn step_cycle(&mut self) {
+        self.tick(4);
+        self.cycles += 4;
+    }
+
+    // 命令やメモリアクセスで進めた分を除いた、残りのサイクルを進める
+    // 実際に経過したサイクル数を返す
+    fn finish_cycles(&mut self, total: u8) -> u8 {
+        let rest = total.saturating_sub(self.cycles);
+        self.tick(rest);
+        let elapsed = self.cycles + rest;
+        self.cycles = 0;
+        elapsed
+    }
+
+    // メモリアクセスは1マシンサイクルかかるので、他のコンポーネントを進めてからアクセスする
+    fn read(&mut self, address: u16) -> Result<u8> {
+        self.step_cycle();
+        self.bus.read(address)
+    }
+
+    // 命令の読み込み。ウォッチポイントの対象にしない
+    fn read_code(&mut self, address: u16) -> Result<u8> {
+        self.step_cycle();
+        self.bus.peek(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<()> {
+        self.step_cycle();
+        self.bus.write(address, data)
+    }
+
+    fn write_16(&mut self, address: u16, data: u16) -> Result<()> {
+        let low: u8 = (data & 0x00FF) as u8;
+        let high: u8 = (data >> 8) as u8;
+
+        self.write(address, low)?;
+        self.write(address.wrapping_add(1), high)?;
+
         Ok(())
     }
 
@@ -141,160 +332,386 @@ impl Cpu {
             self.bus.int_flag |= 1 << 2;
         }
 
+        if self.bus.serial.int_serial_flag {
+            self.bus.serial.int_serial_flag = false;
+            self.bus.int_flag |= 1 << 3;
+        }
+
         if self.bus.joypad.int_flag {
             self.bus.joypad.int_flag = false;
             self.bus.int_flag |= 1 << 4;
         }
     }
 
-    fn check_interrupt(&mut self) -> u8 {
-        let interrupt_flags = self.bus.int_flag & self.bus.ie_flag;
+    // IEとIFの両方が立っている割り込み
+    fn pending_interrupts(&self) -> u8 {
+        self.bus.int_flag & self.bus.ie_flag & 0x1F
+    }
 
-        if interrupt_flags != 0 {
-            for bit in 0..5_u8 {
-                if (interrupt_flags & (1 << bit)) == (1 << bit) {
-                    let interrupt_idx = bit;
-                    return self.handle_interrupt(interrupt_idx)
-                }
-            }
+    fn check_interrupt(&mut self) -> Result<u8> {
+        let interrupt_flags = self.pending_interrupts();
+
+        if interrupt_flags == 0 {
+            return Ok(0);
         }
 
-        return 0;
-    }
+        // HALTからの復帰には4サイクルかかる
+        // IME=0の場合は割り込みを処理せず、HALTの次の命令から再開する
+        let mut wake_cycle = 0;
+        if self.halt {
+            self.halt = false;
+            self.step_cycle();
+            wake_cycle = 4;
+        }
 
-    fn handle_interrupt(&mut self, interrupt_idx: u8) -> u8 {
-        if self.ime {
-            self.bus.int_flag &= !(1 << interrupt_idx);
+        if !self.ime {
+            return Ok(wake_cycle);
         }
-        self.halt = false;
-
-        let address = match interrupt_idx {
-            0 => 0x40,
-            1 => 0x48,
-            2 => 0x50,
-            3 => 0x58,
-            4 | _ => {
-                self.bus.timer.is_stop = false;
-                0x60
-            }
-        };
 
-        if self.ime {
-            self.ime = false;
-            return self.int_call(address).unwrap();
+        self.ime = false;
+        self.ime_scheduled = false;
+        Ok(wake_cycle + self.dispatch_interrupt()?)
+    }
+
+    // 割り込みの呼び出しは5マシンサイクル(20サイクル)
+    // 2マシンサイクル待った後、PCをスタックに積んでから割り込みベクタにジャンプする
+    fn dispatch_interrupt(&mut self) -> Result<u8> {
+        self.step_cycle();
+        self.step_cycle();
+
+        // EIの直後のHALTでHALTバグが起きた場合は、HALT自身のアドレスに戻る
+        let pc = if self.halt_bug { self.PC.wrapping_sub(1) } else { self.PC };
+        self.halt_bug = false;
+        self.decrement_sp();
+        self.write(self.SP, (pc >> 8) as u8)?;
+
+        // SPが0x0000の場合は上位バイトの書き込みでIEが書き換わるので、どの割り込みを処理するかはここで決める
+        let interrupt_flags = self.pending_interrupts();
+
+        self.decrement_sp();
+        self.write(self.SP, pc as u8)?;
+
+        // 処理する割り込みがなくなった場合は0x0000にジャンプする
+        if interrupt_flags == 0 {
+            self.PC = 0x0000;
         }
+        else {
+            let interrupt_idx = interrupt_flags.trailing_zeros() as u16;
+            self.bus.int_flag &= !(1 << interrupt_idx);
+            self.PC = 0x40 + interrupt_idx * 8;
+        }
+        self.debugger.enter_interrupt(pc, self.PC, self.SP);
 
-        return 0;
+        Ok(20)
     }
 
-    fn interrupt(&mut self) -> u8 {
+    fn interrupt(&mut self) -> Result<u8> {
         self.update_interrupt();
-        return self.check_interrupt();
+        self.check_interrupt()
     }
 
     pub fn render(&mut self, frame: &mut [u8]) {
         self.bus.ppu.render(frame).unwrap();
     }
 
-    // 現在のPCにブレークポイントが張られていた場合はステップ実行をON
-    fn check_break_points(&mut self) {
-        if self.break_points.contains(&self.PC) {
-            self.step_flag = true;
+    // レジスタとCPUの状態を書き出したあと、バス以下の全コンポーネントを書き出す
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for r in [self.A, self.B, self.C, self.D, self.E, self.F, self.H, self.L] {
+            writer.write_u8(r);
         }
+        writer.write_u16(self.SP);
+        writer.write_u16(self.PC);
+        writer.write_bool(self.halt);
+        writer.write_bool(self.stop);
+        writer.write_bool(self.ime);
+        writer.write_bool(self.ime_scheduled);
+        writer.write_bool(self.halt_bug);
+        writer.write_bool(self.fault.is_some());
+        if let Some(fault) = self.fault {
+            writer.write_u16(fault.pc);
+            writer.write_u8(fault.opcode);
+        }
+        self.bus.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        for r in [&mut self.A, &mut self.B, &mut self.C, &mut self.D, &mut self.E, &mut self.F, &mut self.H, &mut self.L] {
+            *r = reader.read_u8()?;
+        }
+        self.SP = reader.read_u16()?;
+        self.PC = reader.read_u16()?;
+        // 読み込む前のコールスタックは意味がなくなる
+        self.debugger.clear_call_stack();
+        self.halt = reader.read_bool()?;
+        self.stop = reader.read_bool()?;
+        self.ime = reader.read_bool()?;
+        self.ime_scheduled = reader.read_bool()?;
+        self.halt_bug = reader.read_bool()?;
+        self.fault = None;
+        if reader.read_bool()? {
+            let pc = reader.read_u16()?;
+            let opcode = reader.read_u8()?;
+            self.fault = Some(CpuFault { pc, opcode });
+        }
+        self.bus.load_state(reader)
+    }
+
+    // デバッガのステップ実行。止まっている場所のブレークポイントを無視して1命令だけ実行する
+    pub fn debug_step(&mut self) -> Result<u8> {
+        self.debugger.resume(self.PC);
+        let result = self.step();
+        self.debugger.stop();
+        result
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let mut writer = StateWriter::new();
+        self.save_state(&mut writer);
+        Snapshot {
+            steps: self.steps,
+            buttons: self.bus.joypad.buttons(),
+            state: writer.into_inner()
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.load_state(&mut StateReader::new(&snapshot.state))?;
+        self.steps = snapshot.steps;
+        self.bus.joypad.set_buttons(snapshot.buttons);
+        Ok(())
+    }
+
+    // 記録したボタン入力とシリアル通信の受信データを再現しながら1ステップ実行し、ウォッチポイントへのアクセスを返す
+    // シリアル通信の相手とは実際にはやりとりしない
+    fn replay_step(&mut self) -> Result<Vec<WatchHit>> {
+        for (button, pressed) in self.rewind.inputs_at(self.steps) {
+            if pressed {
+                self.bus.joypad.press(button);
+            }
+            else {
+                self.bus.joypad.release(button);
+            }
+        }
+
+        self.bus.serial.start_replay(self.rewind.received_at(self.steps));
+        self.replaying = true;
+        let result = self.step();
+        self.replaying = false;
+        self.bus.serial.finish_replay();
+        // 途中で止まった場合も含めて、このステップのアクセスは次に持ち越さない
+        self.bus.watcher.take_hits();
+        let hits = std::mem::take(&mut self.replay_hits);
+        // 元の実行でも未定義の命令でロックしているので、そのまま実行し直す
+        if let Err(e) = result {
+            if self.fault.is_none() {
+                return Err(e);
+            }
+        }
+        Ok(hits)
+    }
+
+    // 指定したステップ数の時点まで戻る。巻き戻しのバッファより前には戻れずfalseを返す
+    // ボタンは今押されている状態に戻すので、戻った後は今の入力で実行を続ける
+    pub fn rewind_to(&mut self, target: u64) -> Result<bool> {
+        let snapshot = match self.rewind.find(target)? {
+            Some(snapshot) => snapshot,
+            None => return Ok(false)
+        };
+
+        let buttons = self.bus.joypad.buttons();
+        self.restore(&snapshot)?;
+        while self.steps < target {
+            self.replay_step()?;
+        }
+        self.bus.joypad.set_buttons(buttons);
+        self.rewind.truncate(target)?;
+        Ok(true)
     }
 
-    pub fn set_break_point(&mut self, bp: u16) {
-        self.break_points.push(bp);
+    // 1つ前のスナップショットまで戻る。キーを押し続けて巻き戻すときに使う
+    pub fn rewind_snapshot(&mut self) -> Result<bool> {
+        match self.rewind.previous(self.steps) {
+            Some(target) => self.rewind_to(target),
+            None => Ok(false)
+        }
     }
 
-    // ステップ実行
-    fn stepping(&mut self, opcode: &Opcode) {
-        // 現状を出力
-        println!("Current Data:");
-        self.debug_output(opcode);
+    // 1ステップ前に戻って止まる
+    pub fn reverse_step(&mut self) -> Result<bool> {
+        if self.steps == 0 || !self.rewind_to(self.steps - 1)? {
+            return Ok(false);
+        }
+        self.debugger.stop();
+        Ok(true)
+    }
 
-        loop {
-            let mut raw_command = String::new();
-            io::stdin().read_line(&mut raw_command).expect("Failed to read");
-            let command = raw_command.trim_end();
+    // 直前にブレークポイントかウォッチポイントに引っかかった所まで戻って止まる
+    // スナップショットを新しい方から順に実行し直して探し、見つからなければ最も古いスナップショットまで戻る
+    // ヒット数と無視する回数は変えない
+    pub fn reverse_continue(&mut self) -> Result<bool> {
+        let current = self.steps;
+        let buttons = self.bus.joypad.buttons();
+        let mut end = current;
+        let mut oldest = None;
+        let mut cursor = self.rewind.cursor();
 
-            match command {
-                "n" => break,
-                "debug" => {
-                    self.debug_flag = !self.debug_flag;
-                },
-                "dump" => {
-                    self.bus.ppu.dump();
+        while let Some(snapshot) = self.rewind.next_back(&mut cursor)? {
+            if snapshot.steps >= end {
+                continue;
+            }
+            self.restore(&snapshot)?;
+
+            // この区間で最後に止まる位置
+            let mut found = None;
+            while self.steps < end {
+                if !self.halt && !self.stop && self.fault.is_none() {
+                    if let Some(reason) = self.debugger.find_break(&self.registers(), &self.bus) {
+                        found = Some((self.steps, reason));
+                    }
                 }
-                "go" => {
-                    self.step_flag = false;
-                    break;
-                },
-                "set bp" => {
-                    print!("set break point => ");
-                    let mut raw_bp = String::new();
-                    io::stdin().read_line(&mut raw_bp).expect("Failed to read");
-                    let str_bp = raw_bp.trim_start_matches("0x").trim_end();
-                    let bp = u16::from_str_radix(str_bp, 16).unwrap();
-                    self.break_points.push(bp);
-                },
-                "rm bp" => {
-                    print!("remove break point => ");
-                    let mut raw_bp = String::new();
-                    io::stdin().read_line(&mut raw_bp).expect("Failed to read");
-                    let str_bp = raw_bp.trim_start_matches("0x").trim_end();
-                    println!("str_bp = {}", str_bp);
-                    let bp = u16::from_str_radix(str_bp, 16).unwrap();
-                    if let Some(idx) = self.break_points.iter().position(|x| *x == bp) {
-                        self.break_points.remove(idx);
-                        self.step_flag = false;
+                let hits = self.replay_step()?;
+                if self.steps < current {
+                    if let Some(reason) = self.debugger.find_watch(&hits, &self.registers(), &self.bus) {
+                        found = Some((self.steps, reason));
                     }
-                },
-                _ => println!("unknown command")
+                }
+            }
+
+            if let Some((target, reason)) = found {
+                self.bus.joypad.set_buttons(buttons);
+                self.rewind_to(target)?;
+                self.debugger.stop_with(reason);
+                return Ok(true);
             }
+            oldest = Some(snapshot.steps);
+            end = snapshot.steps;
+        }
+
+        self.bus.joypad.set_buttons(buttons);
+        match oldest {
+            Some(target) => {
+                self.rewind_to(target)?;
+                self.debugger.stop_with("reached the oldest rewind point".to_string());
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    // デバッガからレジスタを読み書きする
+    pub fn registers(&self) -> Registers {
+        Registers {
+            af: self.get_af(),
+            bc: self.get_bc(),
+            de: self.get_de(),
+            hl: self.get_hl(),
+            sp: self.SP,
+            pc: self.PC
+        }
+    }
+
+    pub fn register(&self, reg: Register) -> u16 {
+        self.registers().get(reg)
+    }
+
+    pub fn set_register(&mut self, reg: Register, data: u16) {
+        match reg {
+            Register::R8(reg) => self.set_reg8(reg, data as u8),
+            // Fの下位4bitは常に0
+            Register::F => self.F = data as u8 & 0xF0,
+            Register::R16(reg) => self.set_reg16(reg, data),
+            Register::PC => self.PC = data
         }
     }
 
+    // 実行した命令のトレースを書き出し始める
+    pub fn start_trace(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    // トレースを書き出し終える
+    pub fn stop_trace(&mut self) -> Result<()> {
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.flush(),
+            None => Ok(())
+        }
+    }
+
+    // リセットするときに、トレースを新しいCPUに引き継ぐ
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
     // デバッグ情報を出力
-    fn debug_output(&self, opcode: &Opcode) {
+    fn debug_output(&self, pc: u16, decoded: &Decoded) {
         println!(
-            "PC: {:#04X}, OPECODE: {:#02X}, A: {:#02X}, BC: {:#04X}, DE: {:#04X}, HL: {:#04X}, SP: {:#04X} FLAGS: {:#02X}",
-            self.PC, opcode.code, self.A, self.get_bc(), self.get_de(), self.get_hl(), self.SP, self.F
+            "PC: {:#06X}, {:<16} A: {:#04X}, BC: {:#06X}, DE: {:#06X}, HL: {:#06X}, SP: {:#06X} FLAGS: {:#04X}",
+            pc, decoded.instruction.to_string(), self.A, self.get_bc(), self.get_de(), self.get_hl(), self.SP, self.F
         );
     }
 
-    // 命令の読み込み
-    fn read_inst(&mut self) -> Result<Opcode> {
-        let opcode: Opcode = match self.bus.read(self.PC) {
-            Ok(0xCB) => {
-                self.increment_pc();
-                match self.bus.read(self.PC) {
-                    Ok(res) => Opcode { cb_prefix: true, code: res },
-                    Err(_err) => bail!("fail! error occured reading a opcode. {}", _err)
-                }
-            },
-            Ok(res) => Opcode { cb_prefix: false, code: res },
-            Err(_err) => bail!("fail! error occured reading a opcode. {}", _err)
-        };
+    // 指定したアドレスの命令をデコードする。バスを読むだけで、サイクルは進めない
+    pub fn decode_at(&self, address: u16) -> Decoded {
+        let mut bytes = [0; 3];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.bus.peek(address.wrapping_add(i as u16)).unwrap_or(0xFF);
+        }
 
-        Ok(opcode)
+        decode(bytes)
     }
 
-    pub fn read_next_8(&mut self) -> Result<u8> {
-        self.increment_pc();
-        let data: u8 = self.bus.read(self.PC)?;
-
-        return Ok(data)
+    // 指定したアドレスの命令を逆アセンブルする
+    pub fn disassemble(&self, address: u16) -> String {
+        self.decode_at(address).instruction.to_string()
     }
 
-    pub fn read_next_16(&mut self) -> Result<u16> {
-        self.increment_pc();
-        let lower: u16 = self.bus.read(self.PC)? as u16;
+    // PCの指す1byteを読み込んでPCを進める
+    fn fetch(&mut self) -> Result<u8> {
+        let data = self.read_code(self.PC)?;
         self.increment_pc();
-        let upper: u16 = self.bus.read(self.PC)? as u16;
+        Ok(data)
+    }
+
+    // 命令の読み込み。命令表を引いて、続く即値も読み込む
+    fn fetch_inst(&mut self) -> Result<Decoded> {
+        // HALTバグの直後は、命令を読んでもPCが進まないので同じバイトをもう一度読むことになる
+        let opcode = self.read_code(self.PC)?;
+        if self.halt_bug {
+            self.halt_bug = false;
+        }
+        else {
+            self.increment_pc();
+        }
+
+        if opcode == 0xCB {
+            let cb_opcode = self.fetch()?;
+            return Ok(Decoded::lookup_cb(cb_opcode));
+        }
+
+        let decoded = Decoded::lookup(opcode);
+        let immediate = match decoded.length {
+            2 => self.fetch()? as u16,
+            3 => {
+                let low = self.fetch()?;
+                let high = self.fetch()?;
+                u16::from_le_bytes([low, high])
+            },
+            _ => 0
+        };
 
-        let ret: u16 = upper * 256 + lower;
-        Ok(ret as u16)
+        Ok(decoded.with_immediate(immediate))
     }
 
     fn increment_pc(&mut self) {
@@ -319,26 +736,6 @@ impl Cpu {
         self.set_hl(hl.wrapping_add(1));
     }
 
-    fn increment_bc(&mut self) {
-        let bc = self.get_bc();
-        self.set_bc(bc.wrapping_add(1));
-    }
-
-    fn decrement_bc(&mut self) {
-        let bc = self.get_bc();
-        self.set_bc(bc.wrapping_sub(1));
-    }
-
-    fn increment_de(&mut self) {
-        let de = self.get_de();
-        self.set_de(de.wrapping_add(1));
-    }
-
-    fn decrement_de(&mut self) {
-        let de = self.get_de();
-        self.set_de(de.wrapping_sub(1));
-    }
-
     fn get_af(&self) -> u16 {
         let a: u16 = self.A as u16;
         let f: u16 = self.F as u16;
@@ -400,269 +797,205 @@ impl Cpu {
     }
 
     // 命令の実行。返り値に命令のサイクルを返す
-    fn excute_op(&mut self, opcode: &Opcode) -> Result<u8> {
-        // 気合で分岐
-        match opcode {
-            Opcode { cb_prefix: false, code: res } => {
-                match res {
-                    0xD9 => self.reti(),
-                    0xD8 => self.ret_c(),
-                    0xD0 => self.ret_nc(),
-                    0xC8 => self.ret_z(),
-                    0xC0 => self.ret_nz(),
-                    0xC9 => self.ret(),
-                    0xC7 | 0xCF | 
-                    0xD7 | 0xDF |
-                    0xE7 | 0xEF |
-                    0xF7 | 0xFF => self.rst(res),
-                    0xDC => self.call_c(),
-                    0xD4 => self.call_nc(),
-                    0xCC => self.call_z(),
-                    0xC4 => self.call_nz(),
-                    0xCD => self.call(),
-                    0x38 => self.jr_c(),
-                    0x30 => self.jr_nc(),
-                    0x28 => self.jr_z(),
-                    0x20 => self.jr_nz(),
-                    0x18 => self.jr(),
-                    0xE9 => self.jp_hl(),
-                    0xDA => self.jp_c(),
-                    0xD2 => self.jp_nc(),
-                    0xCA => self.jp_z(),
-                    0xC2 => self.jp_nz(),
-                    0xC3 => self.jp(),
-                    0x1F => self.rra(),
-                    0x0F => self.rrca(),
-                    0x17 => self.rla(),
-                    0x07 => self.rlca(),
-                    0xFB => self.ei(),
-                    0xF3 => self.di(),
-                    0x10 => self.stop(),
-                    0x76 => self.halt(),
-                    0x00 => self.nop(),
-                    0x37 => self.scf(),
-                    0x3F => self.ccf(),
-                    0x2F => self.cpl(),
-                    0x27 => self.decimal_adjust_accumlator(),
-                    0x3B => self.dec_3B(),
-                    0x2B => self.dec_2B(),
-                    0x1B => self.dec_1B(),
-                    0x0B => self.dec_0B(),
-                    0x33 => self.inc_33(),
-                    0x23 => self.inc_23(),
-                    0x13 => self.inc_13(),
-                    0x03 => self.inc_03(),
-                    0xE8 => self.add_E8(),
-                    0x39 => self.add_39(),
-                    0x29 => self.add_29(),
-                    0x19 => self.add_19(),
-                    0x09 => self.add_09(),
-                    0x35 => self.dec_35(),
-                    0x2D => self.dec_2D(),
-                    0x25 => self.dec_25(),
-                    0x1D => self.dec_1D(),
-                    0x15 => self.dec_15(),
-                    0x0D => self.dec_0D(),
-                    0x05 => self.dec_05(),
-                    0x3D => self.dec_3D(),
-                    0x34 => self.inc_34(),
-                    0x2C => self.inc_2C(),
-                    0x24 => self.inc_24(),
-                    0x1C => self.inc_1C(),
-                    0x14 => self.inc_14(),
-                    0x0C => self.inc_0C(),
-                    0x04 => self.inc_04(),
-                    0x3C => self.inc_3C(),
-                    0xFE => self.cp_FE(),
-                    0xBE => self.cp_BE(),
-                    0xBD => self.cp_BD(),
-                    0xBC => self.cp_BC(),
-                    0xBB => self.cp_BB(),
-                    0xBA => self.cp_BA(),
-                    0xB9 => self.cp_B9(),
-                    0xB8 => self.cp_B8(),
-                    0xBF => self.cp_BF(),
-                    0xEE => self.xor_EE(),
-                    0xAE => self.xor_AE(),
-                    0xAD => self.xor_AD(),
-                    0xAC => self.xor_AC(),
-                    0xAB => self.xor_AB(),
-                    0xAA => self.xor_AA(),
-                    0xA9 => self.xor_A9(),
-                    0xA8 => self.xor_A8(),
-                    0xAF => self.xor_AF(),
-                    0xF6 => self.or_F6(),
-                    0xB6 => self.or_B6(),
-                    0xB5 => self.or_B5(),
-                    0xB4 => self.or_B4(),
-                    0xB3 => self.or_B3(),
-                    0xB2 => self.or_B2(),
-                    0xB1 => self.or_B1(),
-                    0xB0 => self.or_B0(),
-                    0xB7 => self.or_B7(),
-                    0xE6 => self.and_E6(),
-                    0xA6 => self.and_A6(),
-                    0xA5 => self.and_A5(),
-                    0xA4 => self.and_A4(),
-                    0xA3 => self.and_A3(),
-                    0xA2 => self.and_A2(),
-                    0xA1 => self.and_A1(),
-                    0xA0 => self.and_A0(),
-                    0xA7 => self.and_A7(),
-                    0xDE => self.sbc_DE(),
-                    0x9E => self.sbc_9E(),
-                    0x9D => self.sbc_9D(),
-                    0x9C => self.sbc_9C(),
-                    0x9B => self.sbc_9B(),
-                    0x9A => self.sbc_9A(),
-                    0x99 => self.sbc_99(),
-                    0x98 => self.sbc_98(),
-                    0x9F => self.sbc_9F(),
-                    0xD6 => self.sub_D6(),
-                    0x96 => self.sub_96(),
-                    0x95 => self.sub_95(),
-                    0x94 => self.sub_94(),
-                    0x93 => self.sub_93(),
-                    0x92 => self.sub_92(),
-                    0x91 => self.sub_91(),
-                    0x90 => self.sub_90(),
-                    0x97 => self.sub_97(),
-                    0xCE => self.adc_CE(),
-                    0x8E => self.adc_8E(),
-                    0x8D => self.adc_8D(),
-                    0x8C => self.adc_8C(),
-                    0x8B => self.adc_8B(),
-                    0x8A => self.adc_8A(),
-                    0x89 => self.adc_89(),
-                    0x88 => self.adc_88(),
-                    0x8F => self.adc_8F(),
-                    0xC6 => self.add_C6(),
-                    0x86 => self.add_86(),
-                    0x85 => self.add_85(),
-                    0x84 => self.add_84(),
-                    0x83 => self.add_83(),
-                    0x82 => self.add_82(),
-                    0x81 => self.add_81(),
-                    0x80 => self.add_80(),
-                    0x87 => self.add_87(),
-                    0xE1 => self.pop_E1(),
-                    0xD1 => self.pop_D1(),
-                    0xC1 => self.pop_C1(),
-                    0xF1 => self.pop_F1(),
-                    0xE5 => self.push_E5(),
-                    0xD5 => self.push_D5(),
-                    0xC5 => self.push_C5(),
-                    0xF5 => self.push_F5(),
-                    0x08 => self.ld_08(),
-                    0xF8 => self.ld_F8(),
-                    0xF9 => self.ld_F9(),
-                    0x31 => self.ld_31(),
-                    0x21 => self.ld_21(),
-                    0x11 => self.ld_11(),
-                    0x01 => self.ld_01(),
-                    0xF0 => self.ld_F0(),
-                    0xE0 => self.ld_E0(),
-                    0x22 => self.ld_22(),
-                    0x2A => self.ld_2A(),
-                    0x32 => self.ld_32(),
-                    0x3A => self.ld_3A(),
-                    0xE2 => self.ld_E2(),
-                    0xF2 => self.ld_F2(),
-                    0xEA => self.ld_EA(),
-                    0x77 => self.ld_77(),
-                    0x12 => self.ld_12(),
-                    0x02 => self.ld_02(),
-                    0x67 => self.ld_67(),
-                    0x5F => self.ld_5F(),
-                    0x57 => self.ld_57(),
-                    0x4F => self.ld_4F(),
-                    0x47 => self.ld_47(),
-                    0x3E => self.ld_3E(),
-                    0xFA => self.ld_FA(),
-                    0x1A => self.ld_1A(),
-                    0x0A => self.ld_0A(),
-                    0x36 => self.ld_36(),
-                    0x75 => self.ld_75(),
-                    0x74 => self.ld_74(),
-                    0x73 => self.ld_73(),
-                    0x72 => self.ld_72(),
-                    0x71 => self.ld_71(),
-                    0x70 => self.ld_70(),
-                    0x6F => self.ld_6F(),
-                    0x6E => self.ld_6E(),
-                    0x6D => self.ld_6D(),
-                    0x6C => self.ld_6C(),
-                    0x6B => self.ld_6B(),
-                    0x6A => self.ld_6A(),
-                    0x69 => self.ld_69(),
-                    0x68 => self.ld_68(),
-                    0x66 => self.ld_66(),
-                    0x65 => self.ld_65(),
-                    0x64 => self.ld_64(),
-                    0x63 => self.ld_63(),
-                    0x62 => self.ld_62(),
-                    0x61 => self.ld_61(),
-                    0x60 => self.ld_60(),
-                    0x5E => self.ld_5E(),
-                    0x5D => self.ld_5D(),
-                    0x5C => self.ld_5C(),
-                    0x5B => self.ld_5B(),
-                    0x5A => self.ld_5A(),
-                    0x59 => self.ld_59(),
-                    0x58 => self.ld_58(),
-                    0x56 => self.ld_56(),
-                    0x55 => self.ld_55(),
-                    0x54 => self.ld_54(),
-                    0x53 => self.ld_53(),
-                    0x52 => self.ld_52(),
-                    0x51 => self.ld_51(),
-                    0x50 => self.ld_50(),
-                    0x4E => self.ld_4E(),
-                    0x4D => self.ld_4D(),
-                    0x4C => self.ld_4C(),
-                    0x4B => self.ld_4B(),
-                    0x4A => self.ld_4A(),
-                    0x49 => self.ld_49(),
-                    0x48 => self.ld_48(),
-                    0x46 => self.ld_46(),
-                    0x45 => self.ld_45(),
-                    0x44 => self.ld_44(),
-                    0x43 => self.ld_43(),
-                    0x42 => self.ld_42(),
-                    0x41 => self.ld_41(),
-                    0x40 => self.ld_40(),
-                    0x06 => self.ld_06(),
-                    0x0E => self.ld_0E(),
-                    0x16 => self.ld_16(),
-                    0x1E => self.ld_1E(),
-                    0x26 => self.ld_26(),
-                    0x2E => self.ld_2E(),
-                    0x7F => self.ld_7F(),
-                    0x78 => self.ld_78(),
-                    0x79 => self.ld_79(),
-                    0x7A => self.ld_7A(),
-                    0x7B => self.ld_7B(),
-                    0x7C => self.ld_7C(),
-                    0x7D => self.ld_7D(),
-                    0x7E => self.ld_7E(),
-                    _ => bail!("unknown opcode!")
+    fn excute_op(&mut self, decoded: &Decoded) -> Result<u8> {
+        // 条件付きの命令で分岐したかどうか
+        let mut branch = false;
+
+        match decoded.instruction {
+            Instruction::Nop => {},
+            Instruction::Stop => self.stop(),
+            Instruction::Halt => {
+                // IME=0で割り込みが保留されているとHALTせず、HALTバグが起きる
+                if !self.ime && self.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                }
+                else {
+                    self.halt = true;
+                }
+            },
+            Instruction::Di => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            },
+            Instruction::Ei => self.ime_scheduled = true,
+            Instruction::Ld(dest, src) => {
+                let data = self.read_operand(src)?;
+                self.write_operand(dest, data)?;
+            },
+            Instruction::Ld16(reg, data) => self.set_reg16(reg, data),
+            Instruction::LdAbsoluteSp(address) => self.write_16(address, self.SP)?,
+            Instruction::LdHlSp(offset) => {
+                let val = self.add_sp_offset(offset);
+                self.set_hl(val);
+            },
+            Instruction::LdSpHl => self.SP = self.get_hl(),
+            Instruction::Push(reg) => {
+                // 書き込みの前に1マシンサイクルの内部処理が入る
+                self.step_cycle();
+                self.push_16(self.get_reg16(reg))?;
+            },
+            Instruction::Pop(reg) => {
+                let data = self.pop_16()?;
+                self.set_reg16(reg, data);
+            },
+            Instruction::Add(operand) => {
+                let right = self.read_operand(operand)?;
+                self.A = self.add_8bit(right, false);
+            },
+            Instruction::Adc(operand) => {
+                let right = self.read_operand(operand)?;
+                self.A = self.add_8bit(right, self.get_carry_flag());
+            },
+            Instruction::Sub(operand) => {
+                let right = self.read_operand(operand)?;
+                self.A = self.sub_8bit(right, false);
+            },
+            Instruction::Sbc(operand) => {
+                let right = self.read_operand(operand)?;
+                self.A = self.sub_8bit(right, self.get_carry_flag());
+            },
+            Instruction::And(operand) => {
+                self.A &= self.read_operand(operand)?;
+                self.set_flag(self.A == 0, false, true, false);
+            },
+            Instruction::Xor(operand) => {
+                self.A ^= self.read_operand(operand)?;
+                self.set_flag(self.A == 0, false, false, false);
+            },
+            Instruction::Or(operand) => {
+                self.A |= self.read_operand(operand)?;
+                self.set_flag(self.A == 0, false, false, false);
+            },
+            Instruction::Cp(operand) => {
+                // 結果は捨ててフラグだけ更新する
+                let right = self.read_operand(operand)?;
+                self.sub_8bit(right, false);
+            },
+            Instruction::Inc(operand) => {
+                let left = self.read_operand(operand)?;
+                let val = left.wrapping_add(1);
+                self.write_operand(operand, val)?;
+
+                // Cは影響を受けない
+                let (h, _) = self.is_carry_positive(left, 1);
+                let c = self.get_carry_flag();
+                self.set_flag(val == 0, false, h, c);
+            },
+            Instruction::Dec(operand) => {
+                let left = self.read_operand(operand)?;
+                let val = left.wrapping_sub(1);
+                self.write_operand(operand, val)?;
+
+                // Cは影響を受けない
+                let (h, _) = self.is_carry_negative(left, 1);
+                let c = self.get_carry_flag();
+                self.set_flag(val == 0, true, h, c);
+            },
+            Instruction::Inc16(reg) => self.set_reg16(reg, self.get_reg16(reg).wrapping_add(1)),
+            Instruction::Dec16(reg) => self.set_reg16(reg, self.get_reg16(reg).wrapping_sub(1)),
+            Instruction::AddHl(reg) => {
+                let left = self.get_hl();
+                let right = self.get_reg16(reg);
+                self.set_hl(left.wrapping_add(right));
+
+                // Zは影響を受けない
+                let z = self.get_zero_flag();
+                let (h, c) = self.is_carry_positive_16(left, right);
+                self.set_flag(z, false, h, c);
+            },
+            Instruction::AddSp(offset) => self.SP = self.add_sp_offset(offset),
+            Instruction::Rlca => self.rotate_a(rlc),
+            Instruction::Rrca => self.rotate_a(rrc),
+            Instruction::Rla => self.rotate_a(rl),
+            Instruction::Rra => self.rotate_a(rr),
+            Instruction::Daa => self.decimal_adjust_accumlator(),
+            Instruction::Cpl => {
+                self.A ^= 0xFF;
+                let z = self.get_zero_flag();
+                let c = self.get_carry_flag();
+                self.set_flag(z, true, true, c);
+            },
+            Instruction::Scf => {
+                let z = self.get_zero_flag();
+                self.set_flag(z, false, false, true);
+            },
+            Instruction::Ccf => {
+                let z = self.get_zero_flag();
+                let c = !self.get_carry_flag();
+                self.set_flag(z, false, false, c);
+            },
+            Instruction::Jp(cond, address) => {
+                if self.check_condition(cond) {
+                    self.PC = address;
+                    branch = true;
                 }
             },
-            Opcode { cb_prefix: true, code: res } => {
-                match res {
-                    0x00..=0x07 => self.rlc_CB(res),
-                    0x08..=0x0F => self.rrc_CB(res),
-                    0x10..=0x17 => self.rl_CB(res),
-                    0x18..=0x1F => self.rr_CB(res),
-                    0x20..=0x27 => self.sla_CB(res),
-                    0x28..=0x2F => self.sra_CB(res),
-                    0x30..=0x37 => self.swap_CB(res),
-                    0x38..=0x3F => self.srl_CB(res),
-                    0x40..=0x7F => self.bit_CB(res),
-                    0x80..=0xBF => self.res_CB(res),
-                    0xC0..=0xFF => self.set_CB(res)
+            Instruction::JpHl => self.PC = self.get_hl(),
+            Instruction::Jr(cond, offset) => {
+                if self.check_condition(cond) {
+                    self.PC = self.PC.wrapping_add(offset as u16);
+                    branch = true;
                 }
-            }
+            },
+            Instruction::Call(cond, address) => {
+                if self.check_condition(cond) {
+                    // スタックに積む前に1マシンサイクルの内部処理が入る
+                    self.step_cycle();
+                    self.push_16(self.PC)?;
+                    self.PC = address;
+                    branch = true;
+                }
+            },
+            Instruction::Ret(None) => self.PC = self.pop_16()?,
+            Instruction::Ret(cond) => {
+                // 条件の判定に1マシンサイクルかかる
+                self.step_cycle();
+                if self.check_condition(cond) {
+                    self.PC = self.pop_16()?;
+                    branch = true;
+                }
+            },
+            Instruction::Reti => {
+                self.PC = self.pop_16()?;
+                // 割り込みを有効化
+                self.ime = true;
+            },
+            Instruction::Rst(vector) => {
+                self.step_cycle();
+                self.push_16(self.PC)?;
+                self.PC = vector as u16;
+            },
+            Instruction::Rlc(operand) => self.shift_operand(operand, rlc)?,
+            Instruction::Rrc(operand) => self.shift_operand(operand, rrc)?,
+            Instruction::Rl(operand) => self.shift_operand(operand, rl)?,
+            Instruction::Rr(operand) => self.shift_operand(operand, rr)?,
+            Instruction::Sla(operand) => self.shift_operand(operand, sla)?,
+            Instruction::Sra(operand) => self.shift_operand(operand, sra)?,
+            Instruction::Swap(operand) => self.shift_operand(operand, swap)?,
+            Instruction::Srl(operand) => self.shift_operand(operand, srl)?,
+            Instruction::Bit(bit, operand) => {
+                let target = self.read_operand(operand)?;
+                let z = (target & (1 << bit)) == 0;
+                let c = self.get_carry_flag();
+                self.set_flag(z, false, true, c);
+            },
+            Instruction::Res(bit, operand) => {
+                let target = self.read_operand(operand)?;
+                self.write_operand(operand, target & !(1 << bit))?;
+            },
+            Instruction::Set(bit, operand) => {
+                let target = self.read_operand(operand)?;
+                self.write_operand(operand, target | (1 << bit))?;
+            },
+            // 未定義の命令はstep()でCPUをロックするので実行されない
+            Instruction::Illegal(opcode) => bail!("unknown opcode! {:#04X}", opcode)
+        }
+
+        if branch {
+            Ok(decoded.branch_cycles)
+        }
+        else {
+            Ok(decoded.cycles)
         }
     }
 
@@ -735,844 +1068,198 @@ impl Cpu {
         return (self.F & (1 << 6)) == 1 << 6;
     }
 
-    fn swap_8bit(&self, target: u8) -> u8 {
-        let upper: u8 = target & 0xF0;
-        let lower: u8 = target & 0x0F;
-
-        let new_value: u8 = (lower << 4) + (upper >> 4);
-        return new_value;
+    // region: inst
+    fn get_reg8(&self, reg: Reg8) -> u8 {
+        match reg {
+            Reg8::A => self.A,
+            Reg8::B => self.B,
+            Reg8::C => self.C,
+            Reg8::D => self.D,
+            Reg8::E => self.E,
+            Reg8::H => self.H,
+            Reg8::L => self.L
+        }
+    }
+
+    fn set_reg8(&mut self, reg: Reg8, data: u8) {
+        match reg {
+            Reg8::A => self.A = data,
+            Reg8::B => self.B = data,
+            Reg8::C => self.C = data,
+            Reg8::D => self.D = data,
+            Reg8::E => self.E = data,
+            Reg8::H => self.H = data,
+            Reg8::L => self.L = data
+        }
+    }
+
+    fn get_reg16(&self, reg: Reg16) -> u16 {
+        match reg {
+            Reg16::AF => self.get_af(),
+            Reg16::BC => self.get_bc(),
+            Reg16::DE => self.get_de(),
+            Reg16::HL => self.get_hl(),
+            Reg16::SP => self.SP
+        }
+    }
+
+    fn set_reg16(&mut self, reg: Reg16, data: u16) {
+        match reg {
+            // Fの下位4bitは常に0
+            Reg16::AF => self.set_af(data & 0xFFF0),
+            Reg16::BC => self.set_bc(data),
+            Reg16::DE => self.set_de(data),
+            Reg16::HL => self.set_hl(data),
+            Reg16::SP => self.SP = data
+        }
+    }
+
+    fn read_operand(&mut self, operand: Operand) -> Result<u8> {
+        match operand {
+            Operand::Reg(reg) => Ok(self.get_reg8(reg)),
+            Operand::Imm(data) => Ok(data),
+            Operand::Indirect(reg) => self.read(self.get_reg16(reg)),
+            Operand::HlInc => {
+                let address = self.get_hl();
+                self.increment_hl();
+                self.read(address)
+            },
+            Operand::HlDec => {
+                let address = self.get_hl();
+                self.decrement_hl();
+                self.read(address)
+            },
+            Operand::Absolute(address) => self.read(address),
+            Operand::High(offset) => self.read(0xFF00 | offset as u16),
+            Operand::HighC => self.read(0xFF00 | self.C as u16)
+        }
     }
 
-    // (レジスタの値, u8にキャストするかどうか)を返す
-    fn opcode_to_read_registers(&self, opcode: &u8) -> (u16, bool) {
-        let lower = opcode & 0x0F;
-        let ret: (u16, bool) = match lower {
-            0x07 | 0x0F => (self.A as u16, true),
-            0x00 | 0x08 => (self.B as u16, true),
-            0x01 | 0x09 => (self.C as u16, true),
-            0x02 | 0x0A => (self.D as u16, true),
-            0x03 | 0x0B => (self.E as u16, true),
-            0x04 | 0x0C => (self.H as u16, true),
-            0x05 | 0x0D => (self.L as u16, true),
-            0x06 | 0x0E => (self.get_hl(), false),
-            _ => (0, false)
-        };
-        return ret;
-    }
-
-    fn opcode_to_write_registers(&mut self, opcode: &u8, data: u8) {
-        let lower = opcode & 0x0F;
-        match lower {
-            0x07 | 0x0F => self.A = data,
-            0x00 | 0x08 => self.B = data,
-            0x01 | 0x09 => self.C = data,
-            0x02 | 0x0A => self.D = data,
-            0x03 | 0x0B => self.E = data,
-            0x04 | 0x0C => self.H = data,
-            0x05 | 0x0D => self.L = data,
-            _ => {}
-        };
+    fn write_operand(&mut self, operand: Operand, data: u8) -> Result<()> {
+        match operand {
+            Operand::Reg(reg) => {
+                self.set_reg8(reg, data);
+                Ok(())
+            },
+            Operand::Imm(_) => bail!("cannot write to an immediate value"),
+            Operand::Indirect(reg) => self.write(self.get_reg16(reg), data),
+            Operand::HlInc => {
+                let address = self.get_hl();
+                self.increment_hl();
+                self.write(address, data)
+            },
+            Operand::HlDec => {
+                let address = self.get_hl();
+                self.decrement_hl();
+                self.write(address, data)
+            },
+            Operand::Absolute(address) => self.write(address, data),
+            Operand::High(offset) => self.write(0xFF00 | offset as u16, data),
+            Operand::HighC => self.write(0xFF00 | self.C as u16, data)
+        }
     }
 
-    fn get_bit_target(&self, opcode: &u8, first: u8) -> u8 {
-        let upper_opcode = (opcode & 0xF0) >> 4;
-        let lower_opcode = opcode & 0x0F;
-
-        let mut target = (upper_opcode - first) * 2;
-
-        if lower_opcode > 0x07 {
-            target += 1;
+    fn check_condition(&self, cond: Option<Condition>) -> bool {
+        match cond {
+            None => true,
+            Some(Condition::NZ) => !self.get_zero_flag(),
+            Some(Condition::Z) => self.get_zero_flag(),
+            Some(Condition::NC) => !self.get_carry_flag(),
+            Some(Condition::C) => self.get_carry_flag()
         }
+    }
 
-        return target;
+    // スタックに積む。上位バイトから書き込む
+    fn push_16(&mut self, data: u16) -> Result<()> {
+        self.decrement_sp();
+        self.write(self.SP, (data >> 8) as u8)?;
+        self.decrement_sp();
+        self.write(self.SP, data as u8)
     }
 
-    // region: inst
-    #[allow(dead_code)]
-    fn reti(&mut self) -> Result<u8> {
-        let stack_address = self.SP;
-        let address = self.bus.read_16(stack_address)?;
-        self.PC = address;
-        self.jmp_flag = true;
-
-        // 2byteのデータをpopするので2回インクリメント
+    fn pop_16(&mut self) -> Result<u16> {
+        let low = self.read(self.SP)?;
         self.increment_sp();
+        let high = self.read(self.SP)?;
         self.increment_sp();
 
-        // 割り込みを有効化
-        self.ime = true;
-        Ok(16)
+        Ok(u16::from_le_bytes([low, high]))
     }
 
-    #[allow(dead_code)]
-    fn ret_c(&mut self) -> Result<u8> {
-        let mut cycle = 8;
-        let c = self.get_carry_flag();
-        
-        if c {
-            let stack_address = self.SP;
-            let address = self.bus.read_16(stack_address)?;
-            self.PC = address;
-            self.jmp_flag = true;
-
-            // 2byteのデータをpopするので2回インクリメント
-            self.increment_sp();
-            self.increment_sp();
-            cycle = 20;
-        }
+    // Aにadd(carryがtrueならadc)した結果を返す
+    fn add_8bit(&mut self, right: u8, carry: bool) -> u8 {
+        let left = self.A;
+        let carry_val = carry as u8;
+        let val = left.wrapping_add(right).wrapping_add(carry_val);
+
+        let z: bool = val == 0;
+        let n: bool = false;
+        let h: bool = (left & 0x0F) + (right & 0x0F) + carry_val > 0x0F;
+        let c: bool = (left as u16) + (right as u16) + (carry_val as u16) > 0xFF;
 
-        Ok(cycle)
+        self.set_flag(z, n, h, c);
+        val
     }
 
-    #[allow(dead_code)]
-    fn ret_nc(&mut self) -> Result<u8> {
-        let mut cycle = 8;
-        let c = self.get_carry_flag();
-        
-        if !c {
-            let stack_address = self.SP;
-            let address = self.bus.read_16(stack_address)?;
-            self.PC = address;
-            self.jmp_flag = true;
+    // Aからsub(carryがtrueならsbc)した結果を返す
+    fn sub_8bit(&mut self, right: u8, carry: bool) -> u8 {
+        let left = self.A;
+        let carry_val = carry as u8;
+        let val = left.wrapping_sub(right).wrapping_sub(carry_val);
 
-            // 2byteのデータをpopするので2回インクリメント
-            self.increment_sp();
-            self.increment_sp();
-            cycle = 20;
-        }
+        let z: bool = val == 0;
+        let n: bool = true;
+        let h: bool = (left & 0x0F) < (right & 0x0F) + carry_val;
+        let c: bool = (left as u16) < (right as u16) + (carry_val as u16);
 
-        Ok(cycle)
+        self.set_flag(z, n, h, c);
+        val
     }
 
-    #[allow(dead_code)]
-    fn ret_z(&mut self) -> Result<u8> {
-        let mut cycle = 8;
-        let z = self.get_zero_flag();
-        
-        if z {
-            let stack_address = self.SP;
-            let address = self.bus.read_16(stack_address)?;
-            self.PC = address;
-            self.jmp_flag = true;
-
-            // 2byteのデータをpopするので2回インクリメント
-            self.increment_sp();
-            self.increment_sp();
-            cycle = 20;
-        }
+    // SPに符号付きの値を足した結果を返す。ADD SP, r8とLD HL, SP+r8で使う
+    fn add_sp_offset(&mut self, offset: i8) -> u16 {
+        let left = self.SP;
+        let right = offset as u16;
+
+        let h: bool = (left & 0x0F) + (right & 0x0F) > 0x0F;
+        let c: bool = (left & 0xFF) + (right & 0xFF) > 0xFF;
+        self.set_flag(false, false, h, c);
 
-        Ok(cycle)
+        left.wrapping_add(right)
     }
 
-    #[allow(dead_code)]
-    fn ret_nz(&mut self) -> Result<u8> {
-        let mut cycle = 8;
-        let z = self.get_zero_flag();
-        
-        if !z {
-            let stack_address = self.SP;
-            let address = self.bus.read_16(stack_address)?;
-            self.PC = address;
-            self.jmp_flag = true;
-
-            // 2byteのデータをpopするので2回インクリメント
-            self.increment_sp();
-            self.increment_sp();
-            cycle = 20;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn ret(&mut self) -> Result<u8> {
-        let stack_address = self.SP;
-        let address = self.bus.read_16(stack_address)?;
-        self.PC = address;
-        self.jmp_flag = true;
-
-        // 2byteのデータをpopするので2回インクリメント
-        self.increment_sp();
-        self.increment_sp();
-
-        Ok(16)
-    }
-
-    fn rst(&mut self, opcode: &u8) -> Result<u8> {
-        let address = match opcode {
-            0xC7 => 0x00,
-            0xCF => 0x08,
-            0xD7 => 0x10,
-            0xDF => 0x18,
-            0xE7 => 0x20,
-            0xEF => 0x28,
-            0xF7 => 0x30,
-            0xFF => 0x38,
-            _ => bail!("invalid rst opcode!")
-        };
-        
-        self.base_call(address)?;
-
-        Ok(16)
-    }
-
-    #[allow(dead_code)]
-    fn call_c(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let c = self.get_carry_flag();
-        
-        if c {
-            return self.base_call(address);
-        }
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn call_nc(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let c = self.get_carry_flag();
-        
-        if !c {
-            return self.base_call(address);
-        }
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn call_z(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let z = self.get_zero_flag();
-        
-        if z {
-            return self.base_call(address);
-        }
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn call_nz(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let z = self.get_zero_flag();
-        
-        if !z {
-            return self.base_call(address);
-        }
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn call(&mut self) -> Result<u8> {
-        let address = self.read_next_16()?;
-        return self.base_call(address);
-    }
-
-    fn base_call(&mut self, address: u16) -> Result<u8> {
-        // 2byteのデータを積むので2回デクリメント
-        self.decrement_sp();
-        self.decrement_sp();
-
-        let stack_address = self.SP;
-        self.bus.write_16(stack_address, self.PC.wrapping_add(1))?;
-        self.PC = address;
-        self.jmp_flag = true;
-
-        Ok(24)
-    }
-
-    fn int_call(&mut self, address: u16) -> Result<u8> {
-        // 2byteのデータを積むので2回デクリメント
-        self.decrement_sp();
-        self.decrement_sp();
-
-        let stack_address = self.SP;
-        self.bus.write_16(stack_address, self.PC)?;
-        self.PC = address;
-
-        Ok(24)
-    }
-
-    #[allow(dead_code)]
-    fn jr_c(&mut self) -> Result<u8> {
-        let address = self.read_next_8()?;
-        let mut cycle = 8;
-        let c = self.get_carry_flag();
-        let offset: i8 = address as i8;
-        let pc = self.PC as isize + offset as isize + 1;
-        
-        if c {
-            self.PC = pc as u16;
-            self.jmp_flag = true;
-            cycle = 12;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jr_nc(&mut self) -> Result<u8> {
-        let address = self.read_next_8()?;
-        let mut cycle = 8;
-        let c = self.get_carry_flag();
-        let offset: i8 = address as i8;
-        let pc = self.PC as isize + offset as isize + 1;
-        
-        if !c {
-            self.PC = pc as u16;
-            self.jmp_flag = true;
-            cycle = 12;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jr_z(&mut self) -> Result<u8> {
-        let address = self.read_next_8()?;
-        let mut cycle = 8;
-        let z = self.get_zero_flag();
-        let offset: i8 = address as i8;
-        let pc = self.PC as isize + offset as isize + 1;
-        
-        if z {
-            self.PC = pc as u16;
-            self.jmp_flag = true;
-            cycle = 12;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jr_nz(&mut self) -> Result<u8> {
-        let address = self.read_next_8()?;
-        let mut cycle = 8;
-        let z = self.get_zero_flag();
-        let offset: i8 = address as i8;
-        let pc = self.PC as isize + offset as isize + 1;
-        
-        if !z {
-            self.PC = pc as u16;
-            self.jmp_flag = true;
-            cycle = 12;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jr(&mut self) -> Result<u8> {
-        let address = self.read_next_8()?;
-        let offset: i8 = address as i8;
-        let pc = self.PC as isize + offset as isize + 1;
-        self.PC = pc as u16;
-        self.jmp_flag = true;
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn jp_hl(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        self.PC = address;
-        self.jmp_flag = true;
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn jp_c(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let mut cycle = 12;
-        let c = self.get_carry_flag();
-        
-        if c {
-            self.PC = address;
-            self.jmp_flag = true;
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jp_nc(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let mut cycle = 12;
-        let c = self.get_carry_flag();
-        
-        if !c {
-            self.PC = address;
-            self.jmp_flag = true;
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jp_z(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let mut cycle = 12;
-        let z = self.get_zero_flag();
-        
-        if z {
-            self.PC = address;
-            self.jmp_flag = true;
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jp_nz(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let mut cycle = 12;
-        let z = self.get_zero_flag();
-        
-        if !z {
-            self.PC = address;
-            self.jmp_flag = true;
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn jp(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        self.PC = address;
-        self.jmp_flag = true;
-
-        Ok(16)
-    }
-
-    #[allow(dead_code)]
-    fn res_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-        let target_bit: u8 = self.get_bit_target(opcode, 8);
-
-        if is_cast {
-            let target = register_val as u8;
-            let data = target & !(1 << target_bit);
-            self.opcode_to_write_registers(opcode, data);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let data = target & !(1 << target_bit);
-            self.bus.write(address, data)?;
-            
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn set_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-        let target_bit: u8 = self.get_bit_target(opcode, 0x0C);
-
-        if is_cast {
-            let target = register_val as u8;
-            let data = target | (1 << target_bit);
-            self.opcode_to_write_registers(opcode, data);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let data = target | (1 << target_bit);
-            self.bus.write(address, data)?;
-            
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn bit_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-        let target_bit: u8 = self.get_bit_target(opcode, 4);
-        let c = self.get_carry_flag();
-
-        if is_cast {
-            let target = register_val as u8;
-            let z = !((target & (1 << target_bit)) == 1 << target_bit);
-            
-            self.set_flag(z, false, true, c);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let z = !((target & (1 << target_bit)) == 1 << target_bit);
-            
-            self.set_flag(z, false, true, c);
-            cycle = 12;
-        }
-
-        Ok(cycle)
-    }
-
-    // 論理シフト
-    #[allow(dead_code)]
-    fn srl_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let c = (target & (1 << 0)) == 1 << 0;
-            let val = target >> 1;
-            
-            self.opcode_to_write_registers(opcode, val);
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let c = (target & (1 << 0)) == 1 << 0;
-            let val = target >> 1;
-
-            self.bus.write(address, val)?;
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    // 算術シフト
-    #[allow(dead_code)]
-    fn sra_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let c = (target & (1 << 0)) == 1 << 0;
-            let msb = (target & (1 << 7)) == 1 << 7;
-            let mut val = target >> 1;
-            
-            if msb {
-                val |= 1 << 7;
-            }
-            
-            self.opcode_to_write_registers(opcode, val);
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let c = (target & (1 << 0)) == 1 << 0;
-            let msb = (target & (1 << 7)) == 1 << 7;
-            let mut val = target >> 1;
-
-            if msb {
-                val |= 1 << 7;
-            }
-            
-            self.bus.write(address, val)?;
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn sla_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let c = (target & (1 << 7)) == 1 << 7;
-            let val = target << 1;
-            
-            self.opcode_to_write_registers(opcode, val);
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let c = (target & (1 << 7)) == 1 << 7;
-            let val = target << 1;
-            
-            self.bus.write(address, val)?;
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-    
-    #[allow(dead_code)]
-    fn rr_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let c = self.get_carry_flag();
-            let c_new_flag = (target & (1 << 0)) == 1 << 0;
-    
-            let mut old_val = target;
-            if c != c_new_flag {
-                old_val = target ^ (1 << 0);
-            }
-            let val = old_val.rotate_right(1);
-            
-            self.opcode_to_write_registers(opcode, val);
-            let z = val == 0;
-            self.set_flag(z, false, false, c_new_flag);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let c = self.get_carry_flag();
-            let c_new_flag = (target & (1 << 0)) == 1 << 0;
-    
-            let mut old_val = target;
-            if c != c_new_flag {
-                old_val = target ^ (1 << 0);
-            }
-            let val = old_val.rotate_right(1);
-            
-            self.bus.write(address, val)?;
-            let z = val == 0;
-            self.set_flag(z, false, false, c_new_flag);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn rrc_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let c = (target & (1 << 0)) == 1 << 0;
-            let val = target.rotate_right(1);
-            
-            self.opcode_to_write_registers(opcode, val);
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let c = (target & (1 << 0)) == 1 << 0;
-            let val = target.rotate_right(1);
-            
-            self.bus.write(address, val)?;
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn rl_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let c = self.get_carry_flag();
-            let c_new_flag = (target & (1 << 7)) == 1 << 7;
-    
-            let mut old_val = target;
-            if c != c_new_flag {
-                old_val = target ^ (1 << 7);
-            }
-            let val = old_val.rotate_left(1);
-            
-            self.opcode_to_write_registers(opcode, val);
-            let z = val == 0;
-            self.set_flag(z, false, false, c_new_flag);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let c = self.get_carry_flag();
-            let c_new_flag = (target & (1 << 7)) == 1 << 7;
-    
-            let mut old_val = target;
-            if c != c_new_flag {
-                old_val = target ^ (1 << 7);
-            }
-            let val = old_val.rotate_left(1);
-            
-            self.bus.write(address, val)?;
-            let z = val == 0;
-            self.set_flag(z, false, false, c_new_flag);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn rlc_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let c = (target & (1 << 7)) == 1 << 7;
-            let val = target.rotate_left(1);
-            
-            self.opcode_to_write_registers(opcode, val);
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let c = (target & (1 << 7)) == 1 << 7;
-            let val = target.rotate_left(1);
-            
-            self.bus.write(address, val)?;
-            let z = val == 0;
-            self.set_flag(z, false, false, c);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn rra(&mut self) -> Result<u8> {
-        let c = self.get_carry_flag();
-        let c_new_flag = self.A & 0x01 > 0;
-
-        let val = (self.A >> 1) | (c as u8) << 7;
-        self.A = val;
-
-        self.set_flag(false, false, false, c_new_flag);
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn rrca(&mut self) -> Result<u8> {
-        let c = self.A & 0x01 > 0;
-        let val = self.A.rotate_right(1);
+    // RLCAなどのAに対する回転。Zは常に0になる
+    fn rotate_a(&mut self, op: fn(u8, bool) -> (u8, bool)) {
+        let (val, c) = op(self.A, self.get_carry_flag());
         self.A = val;
         self.set_flag(false, false, false, c);
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn rla(&mut self) -> Result<u8> {
-        let c = self.get_carry_flag();
-        let c_new_flag = (self.A & (1 << 7)) == 1 << 7;
-
-        let val = (self.A << 1) | c as u8;
-        self.A = val;
-
-        self.set_flag(false, false, false, c_new_flag);
-
-        Ok(4)
     }
 
-    #[allow(dead_code)]
-    fn rlca(&mut self) -> Result<u8> {
-        let target = self.A;
-        let c = (target >> 7) & 1;
-        let val = target.rotate_left(1);
-        
-        self.A = val;
-        self.set_flag(false, false, false, c == 1);
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ei(&mut self) -> Result<u8> {
-        self.ime = true;
-        Ok(4)
-    }
-    
-    #[allow(dead_code)]
-    fn di(&mut self) -> Result<u8> {
-        self.ime = false;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn stop(&mut self) -> Result<u8> {
-        // self.halt = true;
-        // self.bus.timer.is_stop = true;
-        // TODO: LCDディスプレイも止める実装をする
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn halt(&mut self) -> Result<u8> {
-        self.halt = true;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn nop(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn scf(&mut self) -> Result<u8> {
-        let z = self.get_zero_flag();
-        self.set_flag(z, false, false, true);
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ccf(&mut self) -> Result<u8> {
-        let z = self.get_zero_flag();
-        let c = self.get_carry_flag() ^ true;
-        self.set_flag(z, false, false, c);
-
-        Ok(4)
+    // CBプレフィックスの回転・シフト
+    fn shift_operand(&mut self, operand: Operand, op: fn(u8, bool) -> (u8, bool)) -> Result<()> {
+        let target = self.read_operand(operand)?;
+        let (val, c) = op(target, self.get_carry_flag());
+        self.write_operand(operand, val)?;
+        self.set_flag(val == 0, false, false, c);
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    fn cpl(&mut self) -> Result<u8> {
-        let val = self.A;
-        let fliped_val = val ^ 0xFF;
-        self.A = fliped_val;
+    fn stop(&mut self) {
+        // STOPでDIVはリセットされる
+        self.bus.timer.write_div(0);
 
-        let z = self.get_zero_flag();
-        let c = self.get_carry_flag();
-        self.set_flag(z, true, true, c);
+        // CGBで速度の切り替えが準備されている場合は、低消費電力モードには入らずに切り替える
+        if self.bus.is_speed_switch_armed() {
+            self.bus.switch_speed();
+            return;
+        }
 
-        Ok(4)
+        // LCDも止まり、画面は白くなる
+        self.stop = true;
+        self.bus.ppu.clear_screen();
     }
 
-    #[allow(dead_code)]
-    fn decimal_adjust_accumlator(&mut self) -> Result<u8> {
+    fn decimal_adjust_accumlator(&mut self) {
         let mut val = self.A;
         let n_flag = self.get_n_flag();
         let c_flag = self.get_carry_flag();
@@ -1601,2308 +1288,300 @@ impl Cpu {
         self.A = val;
         let z_new_flag = self.A == 0;
         self.set_flag(z_new_flag, n_flag, false, c_new_flag);
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn swap_CB(&mut self, opcode: &u8) -> Result<u8> {
-        let (register_val, is_cast) = self.opcode_to_read_registers(opcode);
-        let mut cycle: u8 = 8;
-
-        if is_cast {
-            let target = register_val as u8;
-            let swaped_val = self.swap_8bit(target);
-            self.opcode_to_write_registers(opcode, swaped_val);
-            let z: bool = swaped_val == 0;
-            let (n, h, c) = (false, false, false);
-            self.set_flag(z, n, h, c);
-        }
-        else {
-            let address = register_val;
-            let target = self.bus.read(address)?;
-            let swaped_val = self.swap_8bit(target);
-            self.bus.write(address, swaped_val)?;
-            let z: bool = swaped_val == 0;
-            let (n, h, c) = (false, false, false);
-            self.set_flag(z, n, h, c);
-            cycle = 16;
-        }
-
-        Ok(cycle)
-    }
-
-    #[allow(dead_code)]
-    fn dec_3B(&mut self) -> Result<u8> {
-        self.decrement_sp();
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn dec_2B(&mut self) -> Result<u8> {
-        self.decrement_hl();
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn dec_1B(&mut self) -> Result<u8> {
-        self.decrement_de();
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn dec_0B(&mut self) -> Result<u8> {
-        self.decrement_bc();
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn inc_33(&mut self) -> Result<u8> {
-        self.increment_sp();
-        Ok(8)
     }
 
-    #[allow(dead_code)]
-    fn inc_23(&mut self) -> Result<u8> {
-        self.increment_hl();
-        Ok(8)
-    }
+    // endregion: inst
+}
 
-    #[allow(dead_code)]
-    fn inc_13(&mut self) -> Result<u8> {
-        self.increment_de();
-        Ok(8)
-    }
+// 回転・シフトの演算。(値, キャリー)を受け取って(結果, 新しいキャリー)を返す
+fn rlc(target: u8, _carry: bool) -> (u8, bool) {
+    (target.rotate_left(1), (target & 0x80) == 0x80)
+}
 
-    #[allow(dead_code)]
-    fn inc_03(&mut self) -> Result<u8> {
-        self.increment_bc();
-        Ok(8)
-    }
+fn rrc(target: u8, _carry: bool) -> (u8, bool) {
+    (target.rotate_right(1), (target & 0x01) == 0x01)
+}
 
-    #[allow(dead_code)]
-    fn add_E8(&mut self) -> Result<u8> {
-        let left = self.SP;
-        let right = self.read_next_8()? as i8;
-        let val = left as isize + right as isize;
+fn rl(target: u8, carry: bool) -> (u8, bool) {
+    ((target << 1) | carry as u8, (target & 0x80) == 0x80)
+}
 
-        self.SP = val as u16;
+fn rr(target: u8, carry: bool) -> (u8, bool) {
+    ((target >> 1) | (carry as u8) << 7, (target & 0x01) == 0x01)
+}
 
-        let z: bool = false;
-        let n: bool = false;
-         
-        let h: bool = (left & 0x0F) + (right as u16 & 0x0F) > 0x0F;
-        let c: bool = (left & 0xFF) + (right as u16 & 0xFF) > 0xFF;
-        
-        self.set_flag(z, n, h, c);
-        Ok(16)
-    }
+fn sla(target: u8, _carry: bool) -> (u8, bool) {
+    (target << 1, (target & 0x80) == 0x80)
+}
 
-    #[allow(dead_code)]
-    fn add_39(&mut self) -> Result<u8> {
-        let left = self.get_hl();
-        let right = self.SP;
-        let val = left.wrapping_add(right);
+// 算術シフト。最上位bitはそのまま残す
+fn sra(target: u8, _carry: bool) -> (u8, bool) {
+    ((target >> 1) | (target & 0x80), (target & 0x01) == 0x01)
+}
 
-        self.set_hl(val);
+fn swap(target: u8, _carry: bool) -> (u8, bool) {
+    (target.rotate_left(4), false)
+}
 
-        let z: bool = self.get_zero_flag();
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive_16(left, right);
+// 論理シフト
+fn srl(target: u8, _carry: bool) -> (u8, bool) {
+    (target >> 1, (target & 0x01) == 0x01)
+}
 
-        self.set_flag(z, n, h, c);
-        Ok(8)
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::rom::build_test_rom;
+
+    // 0x0150からプログラムを、0x0050(タイマー割り込み)に LD C, 0x99; RETI を置いたROMでCPUを作る
+    fn build_cpu(program: &[u8]) -> Cpu {
+        let rom = build_test_rom(b"HALTTEST", 0x00, &[(0x50, &[0x0E, 0x99, 0xD9]), (0x150, program)]);
+        let bus = Bus::new(&mut Cursor::new(rom), 48000, 2000).unwrap();
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu
+    }
+
+    fn run_steps(cpu: &mut Cpu, steps: usize) {
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    // タイマー割り込みだけを有効にし、TIMAがしばらくしてからオーバーフローするようにしてHALTする
+    // HALTの後は INC B を1回実行して止まる
+    fn halt_program(ei_or_di: u8) -> Vec<u8> {
+        vec![
+            ei_or_di,   // EI or DI
+            0x3E, 0x04, // LD A, 0x04
+            0xE0, 0xFF, // LDH (IE), A
+            0x3E, 0xF0, // LD A, 0xF0
+            0xE0, 0x05, // LDH (TIMA), A
+            0x3E, 0x05, // LD A, 0x05
+            0xE0, 0x07, // LDH (TAC), A
+            0x76,       // HALT
+            0x04,       // INC B
+            0x18, 0xFE  // JR -2
+        ]
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        let mut cpu = build_cpu(&[
+            0xF3,       // DI
+            0x3E, 0x04, // LD A, 0x04
+            0xE0, 0xFF, // LDH (IE), A
+            0xE0, 0x0F, // LDH (IF), A
+            0x76,       // HALT
+            0x04,       // INC B
+            0x18, 0xFE  // JR -2
+        ]);
+        run_steps(&mut cpu, 20);
+
+        // HALTせずに、INC Bが2回実行される
+        assert!(!cpu.halt);
+        assert_eq!(cpu.B, 2);
+        // IME=0なので割り込みは処理されない
+        assert_eq!(cpu.C, 0x13);
+        assert_eq!(cpu.bus.int_flag & 0x04, 0x04);
+    }
+
+    #[test]
+    fn halt_bug_after_ei_returns_to_halt() {
+        let mut cpu = build_cpu(&[
+            0xF3,       // DI
+            0x3E, 0x04, // LD A, 0x04
+            0xE0, 0xFF, // LDH (IE), A
+            0xE0, 0x0F, // LDH (IF), A
+            0xFB,       // EI
+            0x76,       // HALT
+            0x04,       // INC B
+            0x18, 0xFE  // JR -2
+        ]);
+        run_steps(&mut cpu, 20);
+
+        // 割り込みから戻るとHALTをもう一度実行し、今度は割り込みがないのでHALTする
+        assert_eq!(cpu.C, 0x99);
+        assert!(cpu.halt);
+        assert!(!cpu.halt_bug);
+        assert_eq!(cpu.B, 0);
+        assert_eq!(cpu.PC, 0x0159);
+    }
+
+    #[test]
+    fn halt_wakes_without_dispatch_when_ime_is_off() {
+        let mut cpu = build_cpu(&halt_program(0xF3));
+        // 起動時のNOPとJPの後、HALTまでの8命令
+        run_steps(&mut cpu, 10);
+        assert!(cpu.halt);
+
+        run_steps(&mut cpu, 200);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.B, 1);
+        assert_eq!(cpu.C, 0x13);
+        // 割り込みは処理されていないのでIFは立ったまま
+        assert_eq!(cpu.bus.int_flag & 0x04, 0x04);
+    }
+
+    #[test]
+    fn halt_dispatches_when_ime_is_on() {
+        let mut cpu = build_cpu(&halt_program(0xFB));
+        run_steps(&mut cpu, 200);
+
+        assert!(!cpu.halt);
+        assert_eq!(cpu.B, 1);
+        assert_eq!(cpu.C, 0x99);
+        assert_eq!(cpu.bus.int_flag & 0x04, 0x00);
+    }
+
+    #[test]
+    fn halt_wakeup_takes_four_cycles() {
+        let mut cpu = build_cpu(&[0x00]);
+        cpu.halt = true;
+        cpu.bus.ie_flag = 0x04;
+
+        // 割り込みがなければ4サイクルずつHALTしたまま
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert!(cpu.halt);
+
+        // 割り込みが来ると、HALT中の4サイクルに加えて復帰に4サイクルかかる
+        cpu.bus.int_flag = 0x04;
+        assert_eq!(cpu.step().unwrap(), 8);
+        assert!(!cpu.halt);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_next_instruction() {
+        let mut cpu = build_cpu(&[
+            0x3E, 0x04, // LD A, 0x04
+            0xE0, 0xFF, // LDH (IE), A
+            0xE0, 0x0F, // LDH (IF), A
+            0xFB,       // EI
+            0x04,       // INC B
+            0x04,       // INC B
+            0x18, 0xFE  // JR -2
+        ]);
+        // 起動時のNOPとJP、LDとLDH 2つ、EI
+        run_steps(&mut cpu, 6);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.PC, 0x0157);
+
+        // EIの次の命令を実行してから割り込みが処理される
+        cpu.step().unwrap();
+        assert_eq!(cpu.B, 1);
+        assert_eq!(cpu.PC, 0x0050);
+        assert_eq!(cpu.pop_16().unwrap(), 0x0158);
+    }
+
+    #[test]
+    fn di_after_ei_keeps_interrupts_disabled() {
+        let mut cpu = build_cpu(&[
+            0xFB,       // EI
+            0xF3,       // DI
+            0x18, 0xFE  // JR -2
+        ]);
+        run_steps(&mut cpu, 10);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn dispatch_takes_twenty_cycles() {
+        let mut cpu = build_cpu(&[]);
+        cpu.ime = true;
+        cpu.bus.ie_flag = 0x04;
+        cpu.bus.int_flag = 0x04;
+
+        // NOPの4サイクルと割り込みの20サイクル
+        assert_eq!(cpu.step().unwrap(), 24);
+        assert_eq!(cpu.PC, 0x0050);
+        assert_eq!(cpu.bus.int_flag & 0x04, 0x00);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn ie_write_during_push_cancels_dispatch() {
+        let mut cpu = build_cpu(&[]);
+        cpu.ime = true;
+        cpu.SP = 0x0000;
+        cpu.bus.ie_flag = 0x04;
+        cpu.bus.int_flag = 0x04;
+
+        // PCの上位バイト(0x01)がIEに書き込まれてタイマー割り込みが無効になるので、0x0000にジャンプする
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.ie_flag, 0x01);
+        assert_eq!(cpu.PC, 0x0000);
+        assert_eq!(cpu.bus.int_flag & 0x04, 0x04);
+    }
+
+    #[test]
+    fn stop_resets_div_and_waits_for_joypad() {
+        let mut cpu = build_cpu(&[
+            0x3E, 0x10, // LD A, 0x10
+            0xE0, 0x00, // LDH (P1), A
+            0x10, 0x00, // STOP
+            0x04,       // INC B
+            0x18, 0xFE  // JR -2
+        ]);
+        run_steps(&mut cpu, 5);
+        assert!(cpu.stop);
+        assert_eq!(cpu.bus.timer.read_div(), 0);
+
+        // ボタンが押されるまではタイマーも命令も止まったまま
+        run_steps(&mut cpu, 1000);
+        assert!(cpu.stop);
+        assert_eq!(cpu.bus.timer.read_div(), 0);
+        assert_eq!(cpu.B, 0);
+
+        cpu.bus.joypad.press(crate::joypad::Button::A);
+        run_steps(&mut cpu, 2);
+        assert!(!cpu.stop);
+        assert_eq!(cpu.B, 1);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let mut cpu = build_cpu(&[
+            0x3E, 0x01, // LD A, 0x01
+            0xE0, 0x4D, // LDH (KEY1), A
+            0x10, 0x00, // STOP
+            0x18, 0xFE  // JR -2
+        ]);
+        cpu.bus.cgb_mode = true;
+        run_steps(&mut cpu, 5);
+
+        // 低消費電力モードには入らず、倍速に切り替わる
+        assert!(!cpu.stop);
+        assert!(cpu.bus.is_double_speed());
+        assert_eq!(cpu.bus.read(0xFF4D).unwrap(), 0xFE);
+    }
+
+    #[test]
+    fn illegal_opcode_locks_cpu() {
+        let mut cpu = build_cpu(&[
+            0xFB,       // EI
+            0xD3,       // 未定義
+            0x04,       // INC B
+            0x18, 0xFE  // JR -2
+        ]);
+        run_steps(&mut cpu, 3);
+
+        let err = cpu.step().unwrap_err();
+        let fault = *err.downcast_ref::<CpuFault>().unwrap();
+        assert_eq!(fault, CpuFault { pc: 0x0151, opcode: 0xD3 });
+        assert_eq!(cpu.fault(), Some(fault));
+
+        // ロックした後は命令も割り込みも実行されないが、タイマーなどは動き続ける
+        cpu.bus.ie_flag = 0x04;
+        cpu.bus.int_flag = 0x04;
+        let div = cpu.bus.timer.read_div();
+        for _ in 0..1000 {
+            assert_eq!(cpu.step().unwrap(), 4);
+        }
+        assert_eq!(cpu.B, 0);
+        assert_eq!(cpu.PC, 0x0152);
+        assert_eq!(cpu.bus.int_flag & 0x04, 0x04);
+        assert_ne!(cpu.bus.timer.read_div(), div);
     }
-
-    #[allow(dead_code)]
-    fn add_29(&mut self) -> Result<u8> {
-        let left = self.get_hl();
-        let right = self.get_hl();
-        let val = left.wrapping_add(right);
-
-        self.set_hl(val);
-
-        let z: bool = self.get_zero_flag();
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive_16(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn add_19(&mut self) -> Result<u8> {
-        let left = self.get_hl();
-        let right = self.get_de();
-        let val = left.wrapping_add(right);
-
-        self.set_hl(val);
-
-        let z: bool = self.get_zero_flag();
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive_16(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn add_09(&mut self) -> Result<u8> {
-        let left = self.get_hl();
-        let right = self.get_bc();
-        let val = left.wrapping_add(right);
-
-        self.set_hl(val);
-
-        let z: bool = self.get_zero_flag();
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive_16(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn dec_35(&mut self) -> Result<u8> {
-        let address = self.get_hl();
-        let left = self.bus.read(address)?;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.bus.write(address, val)?;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(12)
-    }
-    
-    #[allow(dead_code)]
-    fn dec_2D(&mut self) -> Result<u8> {
-        let left = self.L;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.L = val;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn dec_25(&mut self) -> Result<u8> {
-        let left = self.H;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.H = val;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn dec_1D(&mut self) -> Result<u8> {
-        let left = self.E;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.E = val;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn dec_15(&mut self) -> Result<u8> {
-        let left = self.D;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.D = val;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn dec_0D(&mut self) -> Result<u8> {
-        let left = self.C;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.C = val;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn dec_05(&mut self) -> Result<u8> {
-        let left = self.B;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.B = val;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn dec_3D(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = 1;
-        let val = left.wrapping_sub(right);
-
-        self.A = val;
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_negative(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn inc_34(&mut self) -> Result<u8> {
-        let address = self.get_hl();
-        let left = self.bus.read(address)?;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.bus.write(address, val)?;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn inc_2C(&mut self) -> Result<u8> {
-        let left = self.L;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.L = val;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-    
-    #[allow(dead_code)]
-    fn inc_24(&mut self) -> Result<u8> {
-        let left = self.H;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.H = val;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn inc_1C(&mut self) -> Result<u8> {
-        let left = self.E;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.E = val;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.E = val;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn inc_14(&mut self) -> Result<u8> {
-        let left = self.D;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.D = val;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn inc_0C(&mut self) -> Result<u8> {
-        let left = self.C;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.C = val;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn inc_04(&mut self) -> Result<u8> {
-        let left = self.B;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.B = val;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn inc_3C(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = 1;
-        let val = left.wrapping_add(right);
-
-        self.A = val;
-
-        let z: bool = val == 0;
-        let n: bool = false;
-        // Cは影響を受けない
-        let (h, _) = self.is_carry_positive(left, right);
-        let c = self.get_carry_flag();
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn cp_FE(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.read_next_8()?;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn cp_BE(&mut self) -> Result<u8> {
-        let address = self.get_hl();
-        let left = self.A;
-        let right = self.bus.read(address)?;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn cp_BD(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.L;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn cp_BC(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.H;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn cp_BB(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.E;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn cp_BA(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.D;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn cp_B9(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.C;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn cp_B8(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.B;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn cp_BF(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right = self.A;
-        let val = left.wrapping_sub(right);
-
-        let z: bool = val == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn xor_EE(&mut self) -> Result<u8> {
-        let data = self.read_next_8()?;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn xor_AE(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        let data = self.bus.read(address)?;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn xor_AD(&mut self) -> Result<u8> {
-        let data = self.L;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn xor_AC(&mut self) -> Result<u8> {
-        let data = self.H;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn xor_AB(&mut self) -> Result<u8> {
-        let data = self.E;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn xor_AA(&mut self) -> Result<u8> {
-        let data = self.D;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn xor_A9(&mut self) -> Result<u8> {
-        let data = self.C;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn xor_A8(&mut self) -> Result<u8> {
-        let data = self.B;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn xor_AF(&mut self) -> Result<u8> {
-        let data = self.A;
-        let val = self.A ^ data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn or_F6(&mut self) -> Result<u8> {
-        let data: u8 = self.read_next_8()?;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn or_B6(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        let data: u8 = self.bus.read(address)?;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn or_B5(&mut self) -> Result<u8> {
-        let data: u8 = self.L;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn or_B4(&mut self) -> Result<u8> {
-        let data: u8 = self.H;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn or_B3(&mut self) -> Result<u8> {
-        let data: u8 = self.E;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn or_B2(&mut self) -> Result<u8> {
-        let data: u8 = self.D;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn or_B1(&mut self) -> Result<u8> {
-        let data: u8 = self.C;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn or_B0(&mut self) -> Result<u8> {
-        let data: u8 = self.B;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn or_B7(&mut self) -> Result<u8> {
-        let data: u8 = self.A;
-        let val = self.A | data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = false;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn and_E6(&mut self) -> Result<u8> {
-        let data: u8 = self.read_next_8()?;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn and_A6(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        let data: u8 = self.bus.read(address)?;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn and_A5(&mut self) -> Result<u8> {
-        let data: u8 = self.L;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn and_A4(&mut self) -> Result<u8> {
-        let data: u8 = self.H;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn and_A3(&mut self) -> Result<u8> {
-        let data: u8 = self.E;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn and_A2(&mut self) -> Result<u8> {
-        let data: u8 = self.D;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn and_A1(&mut self) -> Result<u8> {
-        let data: u8 = self.C;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn and_A0(&mut self) -> Result<u8> {
-        let data: u8 = self.B;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn and_A7(&mut self) -> Result<u8> {
-        let data: u8 = self.A;
-        let val = self.A & data;
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = true;
-        let c: bool = false;
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    fn base_sbc(&mut self, left: u8, carry_val: u8, right: u8, is_hl: bool) -> Result<u8> {
-        let val = left.wrapping_sub(carry_val).wrapping_sub(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let h: bool = (left & 0x0F) < (right & 0x0F) + carry_val;
-        let c: bool = (left as u16) < (right as u16) + (carry_val as u16);
-
-        self.set_flag(z, n, h, c);
-
-        if is_hl {
-            Ok(8)
-        }
-        else {
-            Ok(4)
-        }
-    }
-
-    fn sbc_DE(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.read_next_8()?;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, true)
-    }
-
-    fn sbc_9E(&mut self) -> Result<u8> {
-        let left = self.A;
-        let address: u16 = self.get_hl();
-        let data: u8 = self.bus.read(address)?;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, true)
-    }
-
-    #[allow(dead_code)]
-    fn sbc_9D(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.L;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, false)
-    }
-
-    #[allow(dead_code)]
-    fn sbc_9C(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.H;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, false)
-    }
-
-    #[allow(dead_code)]
-    fn sbc_9B(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.E;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, false)
-    }
-
-    #[allow(dead_code)]
-    fn sbc_9A(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.D;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, false)
-    }
-
-    #[allow(dead_code)]
-    fn sbc_99(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.C;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, false)
-    }
-
-    #[allow(dead_code)]
-    fn sbc_98(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.B;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, false)
-    }
-
-    #[allow(dead_code)]
-    fn sbc_9F(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.A;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_sbc(left, carry_val, data, false)
-    }
-
-    #[allow(dead_code)]
-    fn sub_D6(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.read_next_8()?;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn sub_96(&mut self) -> Result<u8> {
-        let left = self.A;
-        let address = self.get_hl();
-        let data: u8 = self.bus.read(address)?;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn sub_95(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.L;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn sub_94(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.H;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn sub_93(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.E;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn sub_92(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.D;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-    
-    #[allow(dead_code)]
-    fn sub_91(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.C;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn sub_90(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.B;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn sub_97(&mut self) -> Result<u8> {
-        let left = self.A;
-        let data: u8 = self.A;
-        let val = left.wrapping_sub(data);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = true;
-        let (h, c) = self.is_carry_negative(left, data);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    fn base_adc(&mut self, left: u8, right: u8, carry_val: u8) {
-        let val = left.wrapping_add(right).wrapping_add(carry_val);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let h: bool = (left & 0x0F) + (right & 0x0F) + carry_val > 0x0F;
-        let c: bool = (left as u16) + (right as u16) + (carry_val as u16) > 0xFF;
-
-        self.set_flag(z, n, h, c);
-    }
-
-    #[allow(dead_code)]
-    fn adc_CE(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.read_next_8()?;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn adc_8E(&mut self) -> Result<u8> {
-        let left = self.A;
-        let address: u16 = self.get_hl();
-        let right: u8 = self.bus.read(address)?;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn adc_8D(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.L;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn adc_8C(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.H;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn adc_8B(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.E;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn adc_8A(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.D;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn adc_89(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.C;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn adc_88(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.B;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn adc_8F(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.A;
-        let cf: bool = self.get_carry_flag();
-        let carry_val: u8 = if cf { 1 } else { 0 };
-
-        self.base_adc(left, right, carry_val);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn add_C6(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.read_next_8()?;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn add_86(&mut self) -> Result<u8> {
-        let left = self.A;
-        let hl = self.get_hl();
-        let right: u8 = self.bus.read(hl)?;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn add_85(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.L;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn add_84(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.H;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn add_83(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.E;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn add_82(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.D;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn add_81(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.C;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn add_80(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.B;
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn add_87(&mut self) -> Result<u8> {
-        let left = self.A;
-        let right: u8 = self.A;
-
-        let val = left.wrapping_add(right);
-        self.A = val;
-
-        let z: bool = self.A == 0;
-        let n: bool = false;
-        let (h, c) = self.is_carry_positive(left, right);
-
-        self.set_flag(z, n, h, c);
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn pop_E1(&mut self) -> Result<u8> {
-        let address: u16 = self.SP;
-        let data: u16 = self.bus.read_16(address)?;
-        self.set_hl(data);
-        
-        // 二回インクリメントする
-        self.increment_sp();
-        self.increment_sp();
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn pop_D1(&mut self) -> Result<u8> {
-        let address: u16 = self.SP;
-        let data: u16 = self.bus.read_16(address)?;
-        self.set_de(data);
-        
-        // 二回インクリメントする
-        self.increment_sp();
-        self.increment_sp();
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn pop_C1(&mut self) -> Result<u8> {
-        let address: u16 = self.SP;
-        let data: u16 = self.bus.read_16(address)?;
-        self.set_bc(data);
-        
-        // 二回インクリメントする
-        self.increment_sp();
-        self.increment_sp();
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn pop_F1(&mut self) -> Result<u8> {
-        let address: u16 = self.SP;
-        let data: u16 = self.bus.read_16(address)?;
-        self.set_af(data & 0xFFF0);
-        
-        // 二回インクリメントする
-        self.increment_sp();
-        self.increment_sp();
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn push_E5(&mut self) -> Result<u8> {
-        // 二回デクリメントする
-        self.decrement_sp();
-        self.decrement_sp();
-
-        let data: u16 = self.get_hl();
-        let address: u16 = self.SP;
-        self.bus.write_16(address, data)?;
-
-        Ok(16)
-    }
-
-    #[allow(dead_code)]
-    fn push_D5(&mut self) -> Result<u8> {
-        // 二回デクリメントする
-        self.decrement_sp();
-        self.decrement_sp();
-
-        let data: u16 = self.get_de();
-        let address: u16 = self.SP;
-        self.bus.write_16(address, data)?;
-
-        Ok(16)
-    }
-
-    #[allow(dead_code)]
-    fn push_C5(&mut self) -> Result<u8> {
-        // 二回デクリメントする
-        self.decrement_sp();
-        self.decrement_sp();
-
-        let data: u16 = self.get_bc();
-        let address: u16 = self.SP;
-        self.bus.write_16(address, data)?;
-
-        Ok(16)
-    }
-
-    #[allow(dead_code)]
-    fn push_F5(&mut self) -> Result<u8> {
-        // 二回デクリメントする
-        self.decrement_sp();
-        self.decrement_sp();
-
-        let data: u16 = self.get_af();
-        let address: u16 = self.SP;
-        self.bus.write_16(address, data)?;
-
-        Ok(16)
-    }
-
-    #[allow(dead_code)]
-    fn ld_08(&mut self) -> Result<u8> {
-        let address: u16 = self.read_next_16()?;
-        let data: u16 = self.SP;
-        self.bus.write_16(address, data)?;
-
-        Ok(20)
-    }
-
-    #[allow(dead_code)]
-    fn ld_F8(&mut self) -> Result<u8> {
-        let input = self.read_next_8()? as i8;
-        let address = self.SP as isize + input as isize;
-        self.set_hl(address as u16);
-
-        let z: bool = false;
-        let n: bool = false;
-        let h: bool = (self.SP & 0x0F) + (input as u16 & 0x0F) > 0x0F;
-        let c: bool = (self.SP & 0xFF) + (input as u16 & 0xFF) > 0xFF;
-
-        self.set_flag(z, n, h, c);
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_F9(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        self.SP = hl;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_31(&mut self) -> Result<u8> {
-        let data: u16 = self.read_next_16()?;
-        self.SP = data;
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_21(&mut self) -> Result<u8> {
-        let data: u16 = self.read_next_16()?;
-        self.set_hl(data);
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_11(&mut self) -> Result<u8> {
-        let data: u16 = self.read_next_16()?;
-        self.set_de(data);
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_01(&mut self) -> Result<u8> {
-        let data: u16 = self.read_next_16()?;
-        self.set_bc(data);
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_F0(&mut self) -> Result<u8> {
-        let input: u8 = self.read_next_8()?;
-        let address = (input as u16) + (0xFF00);
-        let data = self.bus.read(address)?;
-        
-        self.A = data;
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_E0(&mut self) -> Result<u8> {
-        let input: u8 = self.read_next_8()?;
-        let address = (input as u16) + (0xFF00);
-        let data = self.A;
-        self.bus.write(address, data)?;
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_22(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        let data = self.A;
-        self.bus.write(address, data)?;
-        self.increment_hl();
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_2A(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        let data = self.bus.read(address)?;
-        self.A = data;
-        self.increment_hl();
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_32(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        let data = self.A;
-        self.bus.write(address, data)?;
-        self.decrement_hl();
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_3A(&mut self) -> Result<u8> {
-        let address: u16 = self.get_hl();
-        let data = self.bus.read(address)?;
-        self.A = data;
-        self.decrement_hl();
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_E2(&mut self) -> Result<u8> {
-        let address: u16 = (0xFF00 as u16) + (self.C as u16);
-        let data: u8 = self.A;
-        self.bus.write(address, data)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_F2(&mut self) -> Result<u8> {
-        let address: u16 = (0xFF00 as u16) + (self.C as u16);
-        let data: u8 = self.bus.read(address)?;
-        self.A = data;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_EA(&mut self) -> Result<u8> {
-        let address = self.read_next_16()?;
-        self.bus.write(address, self.A)?;
-
-        Ok(16)
-    }
-
-    #[allow(dead_code)]
-    fn ld_77(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        self.bus.write(hl, self.A)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_12(&mut self) -> Result<u8> {
-        let de = self.get_de();
-        self.bus.write(de, self.A)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_02(&mut self) -> Result<u8> {
-        let bc = self.get_bc();
-        self.bus.write(bc, self.A)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_6F(&mut self) -> Result<u8> {
-        self.L = self.A;
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_67(&mut self) -> Result<u8> {
-        self.H = self.A;
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_5F(&mut self) -> Result<u8> {
-        self.E = self.A;
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_57(&mut self) -> Result<u8> {
-        self.D = self.A;
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_4F(&mut self) -> Result<u8> {
-        self.C = self.A;
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_47(&mut self) -> Result<u8> {
-        self.B = self.A;
-
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_3E(&mut self) -> Result<u8> {
-        let data = self.read_next_8()?;
-        self.A = data;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_FA(&mut self) -> Result<u8> {
-        // 16bitを読み込む
-        // read_16内でPCはインクリメントされる
-        let address = self.read_next_16()?;
-
-        let data = self.bus.read(address)?;
-        self.A = data;
-
-        Ok(16)
-    }
-    
-    #[allow(dead_code)]
-    fn ld_1A(&mut self) -> Result<u8> {
-        let de = self.get_de();
-        let data = self.bus.read(de)?;
-        self.A = data;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_0A(&mut self) -> Result<u8> {
-        let bc = self.get_bc();
-        let data = self.bus.read(bc)?;
-        self.A = data;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_36(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-
-        self.increment_pc();
-        let data = self.bus.read(self.PC)?;
-
-        self.bus.write(hl, data)?;
-
-        Ok(12)
-    }
-
-    #[allow(dead_code)]
-    fn ld_75(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        let data = self.L;
-
-        self.bus.write(hl, data)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_74(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        let data = self.H;
-
-        self.bus.write(hl, data)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_73(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        let data = self.E;
-
-        self.bus.write(hl, data)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_72(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        let data = self.D;
-
-        self.bus.write(hl, data)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_71(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        let data = self.C;
-
-        self.bus.write(hl, data)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_70(&mut self) -> Result<u8> {
-        let hl = self.get_hl();
-        let data = self.B;
-
-        self.bus.write(hl, data)?;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_6E(&mut self) -> Result<u8> {
-        let hl: u16 = self.get_hl();
-        if let Ok(res) = self.bus.read(hl) {
-            self.L = res;
-        }
-        else {
-            bail!("fail! error occured in ld_6E")
-        }
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_6D(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_6C(&mut self) -> Result<u8> {
-        self.L = self.H;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_6B(&mut self) -> Result<u8> {
-        self.L = self.E;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_6A(&mut self) -> Result<u8> {
-        self.L = self.D;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_69(&mut self) -> Result<u8> {
-        self.L = self.C;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_68(&mut self) -> Result<u8> {
-        self.L = self.B;
-        Ok(4)
-    }
-    
-    #[allow(dead_code)]
-    fn ld_66(&mut self) -> Result<u8> {
-        let hl: u16 = self.get_hl();
-        if let Ok(res) = self.bus.read(hl) {
-            self.H = res;
-        }
-        else {
-            bail!("fail! error occured in ld_66")
-        }
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_65(&mut self) -> Result<u8> {
-        self.H = self.L;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_64(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_63(&mut self) -> Result<u8> {
-        self.H = self.E;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_62(&mut self) -> Result<u8> {
-        self.H = self.D;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_61(&mut self) -> Result<u8> {
-        self.H = self.C;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_60(&mut self) -> Result<u8> {
-        self.H = self.B;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_5E(&mut self) -> Result<u8> {
-        let hl: u16 = self.get_hl();
-        if let Ok(res) = self.bus.read(hl) {
-            self.E = res;
-        }
-        else {
-            bail!("fail! error occured in ld_5E")
-        }
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_5D(&mut self) -> Result<u8> {
-        self.E = self.L;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_5C(&mut self) -> Result<u8> {
-        self.E = self.H;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_5B(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_5A(&mut self) -> Result<u8> {
-        self.E = self.D;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_59(&mut self) -> Result<u8> {
-        self.E = self.C;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_58(&mut self) -> Result<u8> {
-        self.E = self.B;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_56(&mut self) -> Result<u8> {
-        let hl: u16 = self.get_hl();
-        if let Ok(res) = self.bus.read(hl) {
-            self.D = res;
-        }
-        else {
-            bail!("fail! error occured in ld_56")
-        }
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_55(&mut self) -> Result<u8> {
-        self.D = self.L;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_54(&mut self) -> Result<u8> {
-        self.D = self.H;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_53(&mut self) -> Result<u8> {
-        self.D = self.E;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_52(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_51(&mut self) -> Result<u8> {
-        self.D = self.C;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_50(&mut self) -> Result<u8> {
-        self.D = self.B;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_4E(&mut self) -> Result<u8> {
-        let hl: u16 = self.get_hl();
-        if let Ok(res) = self.bus.read(hl) {
-            self.C = res;
-        }
-        else {
-            bail!("fail! error occured in ld_4E")
-        }
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_4D(&mut self) -> Result<u8> {
-        self.C = self.L;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_4C(&mut self) -> Result<u8> {
-        self.C = self.H;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_4B(&mut self) -> Result<u8> {
-        self.C = self.E;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_4A(&mut self) -> Result<u8> {
-        self.C = self.D;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_49(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_48(&mut self) -> Result<u8> {
-        self.C = self.B;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_46(&mut self) -> Result<u8> {
-        let hl: u16 = self.get_hl();
-        if let Ok(res) = self.bus.read(hl) {
-            self.B = res;
-        }
-        else {
-            bail!("fail! error occured in ld_46")
-        }
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_45(&mut self) -> Result<u8> {
-        self.B = self.L;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_44(&mut self) -> Result<u8> {
-        self.B = self.H;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_43(&mut self) -> Result<u8> {
-        self.B = self.E;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_42(&mut self) -> Result<u8> {
-        self.B = self.D;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_41(&mut self) -> Result<u8> {
-        self.B = self.C;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_40(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_06(&mut self) -> Result<u8> {
-        let val = self.read_next_8()?;
-        self.B = val;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_0E(&mut self) -> Result<u8> {
-        let val = self.read_next_8()?;
-        self.C = val;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_16(&mut self) -> Result<u8> {
-        let val = self.read_next_8()?;
-        self.D = val;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_1E(&mut self) -> Result<u8> {
-        let val = self.read_next_8()?;
-        self.E = val;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_26(&mut self) -> Result<u8> {
-        let val = self.read_next_8()?;
-        self.H = val;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_2E(&mut self) -> Result<u8> {
-        let val = self.read_next_8()?;
-        self.L = val;
-
-        Ok(8)
-    }
-
-    #[allow(dead_code)]
-    fn ld_7F(&mut self) -> Result<u8> {
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_78(&mut self) -> Result<u8> {
-        self.A = self.B;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_79(&mut self) -> Result<u8> {
-        self.A = self.C;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_7A(&mut self) -> Result<u8> {
-        self.A = self.D;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_7B(&mut self) -> Result<u8> {
-        self.A = self.E;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_7C(&mut self) -> Result<u8> {
-        self.A = self.H;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_7D(&mut self) -> Result<u8> {
-        self.A = self.L;
-        Ok(4)
-    }
-
-    #[allow(dead_code)]
-    fn ld_7E(&mut self) -> Result<u8> {
-        let hl: u16 = self.get_hl();
-        if let Ok(res) = self.bus.read(hl) {
-            self.A = res;
-        }
-        else {
-            bail!("fail! error occured in ld_7E")
-        }
-
-        Ok(8)
-    }
-
-    // endregion: inst
-}
\ No newline at end of file
+}
@@ -0,0 +1,1294 @@
+use std::cell::RefCell;
+use std::fmt::{self, Write as _};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, BufRead, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{self, RecvTimeoutError};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::{self, JoinHandle};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuFault};
+use crate::instruction::{Instruction, Reg16, Reg8};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::GameBoy;
+
+// コールスタックの深さの上限。RETで戻らずにスタックを捨てるソフトでも増え続けないようにする
+const MAX_CALL_STACK: usize = 256;
+// disasで表示する、PCより前の命令の数
+const DISASSEMBLE_BEFORE: usize = 3;
+// 1行に表示するバイト数
+const BYTES_PER_LINE: usize = 16;
+
+const PROMPT: &str = "(gbdb) ";
+// 入力を待つ間に、実行中に止まったかを確認する間隔
+#[cfg(not(target_arch = "wasm32"))]
+const REPORT_INTERVAL: Duration = Duration::from_millis(50);
+
+const HELP: &str = "\
+s, step [n]            execute n instructions
+n, next                step over CALL and RST
+finish                 run until the current function returns
+c, continue            resume execution
+rs, reverse-step [n]   step back n instructions (needs rewind)
+rc, reverse-continue   run backwards to the previous breakpoint or watchpoint hit
+regs                   show all registers
+p, print <reg>         show a register
+set <reg> <value>      set a register (a, f, b, ..., af, bc, de, hl, sp, pc)
+x/<n><x|d|i><b|h> addr examine memory (e.g. x/16xb $C000, x/4i $0150)
+w, write addr v...     write bytes to memory
+disas [addr] [n]       disassemble around PC or from addr
+bt, backtrace          show the call stack
+b, break addr[-end]    set a breakpoint on an address or a range
+watch addr[-end]       stop after a write to the range
+rwatch addr[-end]      stop after a read from the range
+awatch addr[-end]      stop after a read or write
+  append \"if <cond>\" to stop only when the condition holds,
+  e.g. break $0150 if A == $3C && [HL] != 0
+  (values are hex; write $A for the number because A is the register)
+condition id [cond]    set or remove the condition of a breakpoint
+ignore id n            ignore the next n hits of a breakpoint
+info break             list breakpoints and watchpoints
+enable id, disable id  enable or disable a breakpoint
+delete id              delete a breakpoint
+trace                  toggle tracing of every instruction
+dump                   dump the PPU state
+An empty line repeats the previous command.";
+
+// デバッガから読み書きするレジスタ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    R8(Reg8),
+    F,
+    R16(Reg16),
+    PC
+}
+
+impl Register {
+    pub fn parse(name: &str) -> Option<Self> {
+        let reg = match name.to_ascii_lowercase().as_str() {
+            "a" => Register::R8(Reg8::A),
+            "b" => Register::R8(Reg8::B),
+            "c" => Register::R8(Reg8::C),
+            "d" => Register::R8(Reg8::D),
+            "e" => Register::R8(Reg8::E),
+            "h" => Register::R8(Reg8::H),
+            "l" => Register::R8(Reg8::L),
+            "f" => Register::F,
+            "af" => Register::R16(Reg16::AF),
+            "bc" => Register::R16(Reg16::BC),
+            "de" => Register::R16(Reg16::DE),
+            "hl" => Register::R16(Reg16::HL),
+            "sp" => Register::R16(Reg16::SP),
+            "pc" => Register::PC,
+            _ => return None
+        };
+        Some(reg)
+    }
+
+    fn is_8bit(&self) -> bool {
+        matches!(self, Register::R8(_) | Register::F)
+    }
+}
+
+// CPUのレジスタの値。ブレークポイントの条件を評価するときに使う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16
+}
+
+impl Registers {
+    pub fn get(&self, reg: Register) -> u16 {
+        match reg {
+            Register::R8(Reg8::A) => self.af >> 8,
+            Register::F => self.af & 0xFF,
+            Register::R8(Reg8::B) => self.bc >> 8,
+            Register::R8(Reg8::C) => self.bc & 0xFF,
+            Register::R8(Reg8::D) => self.de >> 8,
+            Register::R8(Reg8::E) => self.de & 0xFF,
+            Register::R8(Reg8::H) => self.hl >> 8,
+            Register::R8(Reg8::L) => self.hl & 0xFF,
+            Register::R16(Reg16::AF) => self.af,
+            Register::R16(Reg16::BC) => self.bc,
+            Register::R16(Reg16::DE) => self.de,
+            Register::R16(Reg16::HL) => self.hl,
+            Register::R16(Reg16::SP) => self.sp,
+            Register::PC => self.pc
+        }
+    }
+}
+
+// 何で止まるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakKind {
+    // 範囲内の命令を実行する前
+    Execute,
+    // 範囲内を読み込んだ命令の後
+    Read,
+    // 範囲内に書き込んだ命令の後
+    Write,
+    // 読み込みか書き込み
+    Access
+}
+
+impl BreakKind {
+    fn matches(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (BreakKind::Read, Access::Read) | (BreakKind::Write, Access::Write) | (BreakKind::Access, _)
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            BreakKind::Execute => "breakpoint",
+            BreakKind::Read => "read watchpoint",
+            BreakKind::Write => "watchpoint",
+            BreakKind::Access => "access watchpoint"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write
+}
+
+// ブレークポイントの条件。表示用に入力された文字列も持っておく
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakCondition {
+    pub source: String,
+    expr: Expr
+}
+
+impl BreakCondition {
+    pub fn parse(source: &str) -> Result<Self> {
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr: Expr::parse(source)?
+        })
+    }
+
+    pub fn eval(&self, regs: &Registers, bus: &Bus) -> bool {
+        self.expr.eval(regs, bus) != 0
+    }
+}
+
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: BreakKind,
+    pub start: u16,
+    pub end: u16,
+    pub condition: Option<BreakCondition>,
+    pub enabled: bool,
+    // 条件を満たして止まった回数
+    pub hits: usize,
+    // 残りこの回数だけは条件を満たしても止まらない
+    pub ignore: usize
+}
+
+impl Breakpoint {
+    fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+
+    fn matches(&self, regs: &Registers, bus: &Bus) -> bool {
+        match &self.condition {
+            Some(condition) => condition.eval(regs, bus),
+            None => true
+        }
+    }
+
+    // 条件と無視する回数を確認して、止まる場合はtrueを返す
+    fn hit(&mut self, regs: &Registers, bus: &Bus) -> bool {
+        if !self.matches(regs, bus) {
+            return false;
+        }
+
+        self.hits += 1;
+        if self.ignore > 0 {
+            self.ignore -= 1;
+            return false;
+        }
+        true
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.kind.name();
+        let mut name = name[..1].to_uppercase() + &name[1..];
+        write!(name, " {}", self.id)?;
+
+        if self.start == self.end {
+            write!(f, "{} at ${:04X}", name, self.start)?;
+        }
+        else {
+            write!(f, "{} at ${:04X}-${:04X}", name, self.start, self.end)?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition.source)?;
+        }
+        Ok(())
+    }
+}
+
+// ウォッチポイントで止まった理由
+fn watch_reason(breakpoint: &Breakpoint, watch_hit: &WatchHit) -> String {
+    match watch_hit.access {
+        Access::Read => format!("{} {}: read ${:04X} = ${:02X}", breakpoint.kind.name(), breakpoint.id, watch_hit.address, watch_hit.value),
+        Access::Write => format!(
+            "{} {}: write ${:04X} = ${:02X} (was ${:02X})",
+            breakpoint.kind.name(), breakpoint.id, watch_hit.address, watch_hit.value, watch_hit.old
+        )
+    }
+}
+
+// 範囲内へのアクセス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: usize,
+    pub access: Access,
+    pub address: u16,
+    pub value: u8,
+    // 書き込む前の値
+    pub old: u8
+}
+
+// バスに置いて、CPUからのメモリアクセスがウォッチポイントの範囲に入ったら記録する
+// 読み込みは&selfで行われるので、記録はRefCellに溜めておき命令の実行後にデバッガが取り出す
+#[derive(Default)]
+pub struct Watcher {
+    ranges: Vec<(usize, BreakKind, u16, u16)>,
+    hits: RefCell<Vec<WatchHit>>
+}
+
+impl Watcher {
+    pub fn on_read(&self, address: u16, value: u8) {
+        self.record(Access::Read, address, value, value);
+    }
+
+    pub fn on_write(&self, address: u16, old: u8, value: u8) {
+        self.record(Access::Write, address, value, old);
+    }
+
+    pub fn is_watching(&self, address: u16) -> bool {
+        self.ranges.iter().any(|(_, _, start, end)| (*start..=*end).contains(&address))
+    }
+
+    fn record(&self, access: Access, address: u16, value: u8, old: u8) {
+        for (id, kind, start, end) in &self.ranges {
+            if kind.matches(access) && (*start..=*end).contains(&address) {
+                self.hits.borrow_mut().push(WatchHit { id: *id, access, address, value, old });
+            }
+        }
+    }
+
+    pub fn has_hits(&self) -> bool {
+        !self.hits.borrow().is_empty()
+    }
+
+    pub fn take_hits(&self) -> Vec<WatchHit> {
+        self.hits.take()
+    }
+}
+
+// CALL、RST、割り込みで積まれたコールスタックの1段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    // 呼び出した命令のアドレス
+    pub call_site: u16,
+    // 呼び出された先
+    pub target: u16,
+    pub return_address: u16,
+    // 戻りアドレスを積んだ後のSP
+    pub sp: u16,
+    pub interrupt: bool
+}
+
+// 実行を再開した後、どこで止まるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    // ブレークポイントまで止まらない
+    Continue,
+    // 次の命令の前で止まる
+    Break,
+    // CALLから戻ってきたところで止まる
+    Over { return_address: u16, sp: u16 },
+    // 今の関数から戻ったところで止まる
+    Finish { sp: u16 }
+}
+
+// CPUの実行を止めたり、コールスタックを追いかけたりする
+pub struct Debugger {
+    // ブレークポイントとウォッチポイント。番号は共通
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    call_stack: Vec<Frame>,
+    mode: RunMode,
+    stopped: bool,
+    // 止まった理由
+    reason: String,
+    // 実行中にブレークポイントなどで止まり、まだ知らせていない
+    report: bool,
+    // ウォッチポイントで止まった場合は、その種類とアクセスしたアドレス
+    watch_hit: Option<(BreakKind, u16)>,
+    // 再開した直後は、止まっていた場所のブレークポイントを無視する
+    resume_pc: Option<u16>
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self {
+            breakpoints: Default::default(),
+            next_id: 1,
+            call_stack: Default::default(),
+            mode: RunMode::Continue,
+            stopped: Default::default(),
+            reason: Default::default(),
+            report: Default::default(),
+            watch_hit: Default::default(),
+            resume_pc: Default::default()
+        }
+    }
+}
+
+impl Debugger {
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    // 実行中に止まった場合に一度だけtrueを返す。理由はreason()で取得する
+    pub fn take_report(&mut self) -> bool {
+        std::mem::take(&mut self.report)
+    }
+
+    pub fn watch_hit(&self) -> Option<(BreakKind, u16)> {
+        self.watch_hit
+    }
+
+    // 未定義の命令でCPUがロックしたときに止まる
+    pub fn stop_on_fault(&mut self, fault: CpuFault) {
+        self.stop_with(fault.to_string());
+        self.report = true;
+    }
+
+    // 実行中のCPUを次の命令の前で止める
+    pub fn request_break(&mut self) {
+        self.mode = RunMode::Break;
+    }
+
+    // すぐに止まった状態にする
+    pub fn stop(&mut self) {
+        self.stop_with("stopped".to_string());
+        self.resume_pc = None;
+        self.report = false;
+    }
+
+    pub(crate) fn stop_with(&mut self, reason: String) {
+        self.stopped = true;
+        self.mode = RunMode::Continue;
+        self.reason = reason;
+        self.watch_hit = None;
+    }
+
+    // 止まっている場所から実行を再開する
+    pub fn resume(&mut self, pc: u16) {
+        self.resume_with(pc, RunMode::Continue);
+    }
+
+    fn resume_with(&mut self, pc: u16, mode: RunMode) {
+        self.stopped = false;
+        self.mode = mode;
+        self.resume_pc = Some(pc);
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) -> usize {
+        self.add(BreakKind::Execute, address, address, None)
+    }
+
+    // ブレークポイントかウォッチポイントを追加して、その番号を返す
+    pub fn add(&mut self, kind: BreakKind, start: u16, end: u16, condition: Option<BreakCondition>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            kind,
+            start,
+            end,
+            condition,
+            enabled: true,
+            hits: 0,
+            ignore: 0
+        });
+        id
+    }
+
+    pub fn breakpoint_mut(&mut self, id: usize) -> Result<&mut Breakpoint> {
+        match self.breakpoints.iter_mut().find(|b| b.id == id) {
+            Some(breakpoint) => Ok(breakpoint),
+            None => bail!("no breakpoint number {}", id)
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> Result<()> {
+        match self.breakpoints.iter().position(|b| b.id == id) {
+            Some(idx) => {
+                self.breakpoints.remove(idx);
+                Ok(())
+            },
+            None => bail!("no breakpoint number {}", id)
+        }
+    }
+
+    // 種類と範囲が一致するものを1つ削除する。削除できた場合はtrueを返す
+    pub fn remove_matching(&mut self, kind: BreakKind, start: u16, end: u16) -> bool {
+        match self.breakpoints.iter().position(|b| b.kind == kind && b.start == start && b.end == end) {
+            Some(idx) => {
+                self.breakpoints.remove(idx);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) -> Result<()> {
+        self.breakpoint_mut(id)?.enabled = enabled;
+        Ok(())
+    }
+
+    // 有効なウォッチポイントの範囲をバスに設定する
+    pub fn sync_watcher(&self, watcher: &mut Watcher) {
+        watcher.ranges = self.breakpoints.iter()
+            .filter(|b| b.enabled && b.kind != BreakKind::Execute)
+            .map(|b| (b.id, b.kind, b.start, b.end))
+            .collect();
+        watcher.hits.borrow_mut().clear();
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // 一番外側が先頭
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    pub fn clear_call_stack(&mut self) {
+        self.call_stack.clear();
+    }
+
+    // 命令を実行する前に呼ぶ。止まる場合はtrueを返す
+    pub fn check(&mut self, regs: &Registers, bus: &Bus) -> bool {
+        let (pc, sp) = (regs.pc, regs.sp);
+        if self.resume_pc.take() == Some(pc) {
+            return false;
+        }
+
+        // 条件を満たしたものは全てヒット数を数える
+        let mut hit_id = None;
+        for breakpoint in self.breakpoints.iter_mut() {
+            if breakpoint.enabled && breakpoint.kind == BreakKind::Execute && breakpoint.contains(pc) && breakpoint.hit(regs, bus) {
+                hit_id = hit_id.or(Some(breakpoint.id));
+            }
+        }
+        if let Some(id) = hit_id {
+            self.stop_with(format!("breakpoint {}", id));
+            self.report = true;
+            return true;
+        }
+
+        let hit = match self.mode {
+            RunMode::Continue => false,
+            RunMode::Break => true,
+            RunMode::Over { return_address, sp: call_sp } => pc == return_address && sp >= call_sp,
+            RunMode::Finish { sp: frame_sp } => sp > frame_sp
+        };
+        if hit {
+            self.stop_with("stopped".to_string());
+            self.report = true;
+        }
+        hit
+    }
+
+    // 命令を実行した後に、その命令によるウォッチポイントへのアクセスを確認する。止まる場合はtrueを返す
+    pub fn check_watch(&mut self, hits: &[WatchHit], regs: &Registers, bus: &Bus) -> bool {
+        for watch_hit in hits {
+            let breakpoint = match self.breakpoints.iter_mut().find(|b| b.id == watch_hit.id) {
+                Some(breakpoint) => breakpoint,
+                None => continue
+            };
+            if !breakpoint.hit(regs, bus) {
+                continue;
+            }
+
+            let reason = watch_reason(breakpoint, watch_hit);
+            let kind = breakpoint.kind;
+            self.stop_with(reason);
+            self.watch_hit = Some((kind, watch_hit.address));
+            self.report = true;
+            return true;
+        }
+        false
+    }
+
+    // 逆方向に実行するときに使う。ヒット数や無視する回数は変えずに、止まるかどうかだけを調べる
+    pub fn find_break(&self, regs: &Registers, bus: &Bus) -> Option<String> {
+        self.breakpoints.iter()
+            .find(|b| b.enabled && b.kind == BreakKind::Execute && b.contains(regs.pc) && b.matches(regs, bus))
+            .map(|b| format!("breakpoint {}", b.id))
+    }
+
+    pub fn find_watch(&self, hits: &[WatchHit], regs: &Registers, bus: &Bus) -> Option<String> {
+        hits.iter().find_map(|watch_hit| {
+            self.breakpoints.iter()
+                .find(|b| b.id == watch_hit.id && b.matches(regs, bus))
+                .map(|b| watch_reason(b, watch_hit))
+        })
+    }
+
+    // 命令を実行した後に呼び、CALLとRETに合わせてコールスタックを更新する
+    pub fn track(&mut self, pc: u16, instruction: &Instruction, length: u8, sp_before: u16, new_pc: u16, sp: u16) {
+        match instruction {
+            // 条件付きのCALLは、戻りアドレスを積んだ場合だけ
+            Instruction::Call(..) | Instruction::Rst(_) if sp == sp_before.wrapping_sub(2) => {
+                self.push_frame(Frame {
+                    call_site: pc,
+                    target: new_pc,
+                    return_address: pc.wrapping_add(length as u16),
+                    sp,
+                    interrupt: false
+                });
+            },
+            Instruction::Ret(_) | Instruction::Reti => self.pop_frames(sp),
+            _ => {}
+        }
+    }
+
+    // 割り込みの呼び出しをコールスタックに積む
+    pub fn enter_interrupt(&mut self, return_address: u16, vector: u16, sp: u16) {
+        self.push_frame(Frame {
+            call_site: return_address,
+            target: vector,
+            return_address,
+            sp,
+            interrupt: true
+        });
+    }
+
+    fn push_frame(&mut self, frame: Frame) {
+        if self.call_stack.len() >= MAX_CALL_STACK {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(frame);
+    }
+
+    // 戻りアドレスがスタックから取り除かれた段を捨てる
+    // RETを使わずにスタックを捨てるソフトがあっても、SPで判断すればずれない
+    fn pop_frames(&mut self, sp: u16) {
+        while matches!(self.call_stack.last(), Some(frame) if frame.sp < sp) {
+            self.call_stack.pop();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+// ブレークポイントの条件式。A == $3C && [HL] != 0 のように書く
+// 値は16進数で、[...]はそのアドレスの1byteを読む
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Value(u16),
+    Register(Register),
+    Memory(Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>)
+}
+
+impl Expr {
+    fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = ExprParser { tokens: &tokens, position: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected \"{}\" in condition", token);
+        }
+        Ok(expr)
+    }
+
+    fn eval(&self, regs: &Registers, bus: &Bus) -> u16 {
+        match self {
+            Expr::Value(value) => *value,
+            Expr::Register(reg) => regs.get(*reg),
+            Expr::Memory(address) => bus.peek(address.eval(regs, bus)).unwrap_or(0xFF) as u16,
+            Expr::Compare(left, op, right) => {
+                let (left, right) = (left.eval(regs, bus), right.eval(regs, bus));
+                let result = match op {
+                    CompareOp::Eq => left == right,
+                    CompareOp::Ne => left != right,
+                    CompareOp::Lt => left < right,
+                    CompareOp::Le => left <= right,
+                    CompareOp::Gt => left > right,
+                    CompareOp::Ge => left >= right
+                };
+                result as u16
+            },
+            Expr::And(left, right) => (left.eval(regs, bus) != 0 && right.eval(regs, bus) != 0) as u16,
+            Expr::Or(left, right) => (left.eval(regs, bus) != 0 || right.eval(regs, bus) != 0) as u16
+        }
+    }
+}
+
+// 記号はそのまま、数値とレジスタ名は英数字のまとまりで区切る
+fn tokenize(source: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = source.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        }
+        else if c.is_ascii_alphanumeric() || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        }
+        else {
+            let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            if ["==", "!=", "<=", ">=", "&&", "||"].contains(&pair.as_str()) {
+                tokens.push(pair);
+                i += 2;
+            }
+            else if "<>()[]".contains(c) {
+                tokens.push(c.to_string());
+                i += 1;
+            }
+            else {
+                bail!("unexpected \"{}\" in condition", c);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// or  := and ("||" and)*
+// and := cmp ("&&" cmp)*
+// cmp := term (op term)?
+// term := 数値 | レジスタ | "[" or "]" | "(" or ")"
+struct ExprParser<'a> {
+    tokens: &'a [String],
+    position: usize
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Result<&'a str> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token)
+            },
+            None => bail!("unexpected end of condition")
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            bail!("expected \"{}\" but found \"{}\" in condition", expected, token);
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut left = self.and()?;
+        while self.peek() == Some("||") {
+            self.position += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut left = self.compare()?;
+        while self.peek() == Some("&&") {
+            self.position += 1;
+            left = Expr::And(Box::new(left), Box::new(self.compare()?));
+        }
+        Ok(left)
+    }
+
+    fn compare(&mut self) -> Result<Expr> {
+        let left = self.term()?;
+        let op = match self.peek() {
+            Some("==") => CompareOp::Eq,
+            Some("!=") => CompareOp::Ne,
+            Some("<") => CompareOp::Lt,
+            Some("<=") => CompareOp::Le,
+            Some(">") => CompareOp::Gt,
+            Some(">=") => CompareOp::Ge,
+            _ => return Ok(left)
+        };
+        self.position += 1;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(self.term()?)))
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let token = self.next()?;
+        match token {
+            "[" => {
+                let address = self.or()?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(address)))
+            },
+            "(" => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            _ => match Register::parse(token) {
+                Some(reg) => Ok(Expr::Register(reg)),
+                None => Ok(Expr::Value(parse_hex(token)?))
+            }
+        }
+    }
+}
+
+// examineの表示形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Hex,
+    Decimal,
+    Instruction
+}
+
+// examineの単位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Byte,
+    HalfWord
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Next,
+    Finish,
+    Continue,
+    ReverseStep(usize),
+    ReverseContinue,
+    Registers,
+    Print(Register),
+    Set(Register, u16),
+    Examine { address: u16, count: usize, format: Format, unit: Unit },
+    Write(u16, Vec<u8>),
+    Disassemble(Option<u16>, usize),
+    Backtrace,
+    Break(BreakKind, u16, u16, Option<BreakCondition>),
+    Condition(usize, Option<BreakCondition>),
+    Ignore(usize, usize),
+    ListBreakpoints,
+    Enable(usize),
+    Disable(usize),
+    Delete(usize),
+    Trace,
+    Dump,
+    Help
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self> {
+        // break $0150 if A == 0 のように、ifから後ろは条件
+        let (line, condition) = match line.split_once(" if ") {
+            Some((line, condition)) => (line, Some(BreakCondition::parse(condition)?)),
+            None => (line, None)
+        };
+
+        let has_condition = condition.is_some();
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => bail!("empty command")
+        };
+        let args: Vec<&str> = words.collect();
+
+        // x/16xb のように、コマンド名に形式が続く
+        if let Some(spec) = name.strip_prefix("x") {
+            if spec.is_empty() || spec.starts_with('/') {
+                return parse_examine(spec.trim_start_matches('/'), &args);
+            }
+        }
+
+        let command = match (name, args.as_slice()) {
+            ("s" | "step" | "si" | "stepi", []) => Command::Step(1),
+            ("s" | "step" | "si" | "stepi", [count]) => Command::Step(parse_count(count)?),
+            ("n" | "next" | "ni" | "nexti", []) => Command::Next,
+            ("finish", []) => Command::Finish,
+            ("c" | "continue", []) => Command::Continue,
+            ("rs" | "reverse-step" | "rsi" | "reverse-stepi", []) => Command::ReverseStep(1),
+            ("rs" | "reverse-step" | "rsi" | "reverse-stepi", [count]) => Command::ReverseStep(parse_count(count)?),
+            ("rc" | "reverse-continue", []) => Command::ReverseContinue,
+            ("regs", []) => Command::Registers,
+            ("info", ["registers" | "reg" | "r"]) => Command::Registers,
+            ("p" | "print", [reg]) => Command::Print(parse_register(reg)?),
+            ("set", [reg, value]) => Command::Set(parse_register(reg)?, parse_hex(value)?),
+            ("w" | "write", [address, values @ ..]) if !values.is_empty() => {
+                let values = values.iter().map(|v| parse_byte(v)).collect::<Result<Vec<_>>>()?;
+                Command::Write(parse_hex(address)?, values)
+            },
+            ("disas" | "l", []) => Command::Disassemble(None, 10),
+            ("disas" | "l", [address]) => Command::Disassemble(Some(parse_hex(address)?), 10),
+            ("disas" | "l", [address, count]) => Command::Disassemble(Some(parse_hex(address)?), parse_count(count)?),
+            ("bt" | "backtrace", []) => Command::Backtrace,
+            ("b" | "break", [range]) => parse_break(BreakKind::Execute, range, condition)?,
+            ("watch", [range]) => parse_break(BreakKind::Write, range, condition)?,
+            ("rwatch", [range]) => parse_break(BreakKind::Read, range, condition)?,
+            ("awatch", [range]) => parse_break(BreakKind::Access, range, condition)?,
+            ("condition", [id, rest @ ..]) => {
+                // 条件はスペースを含むので、番号より後ろをまとめて読む
+                let source = rest.join(" ");
+                let condition = if source.is_empty() { None } else { Some(BreakCondition::parse(&source)?) };
+                Command::Condition(parse_count(id)?, condition)
+            },
+            ("ignore", [id, count]) => Command::Ignore(parse_count(id)?, parse_count(count)?),
+            ("info", ["break" | "breakpoints" | "b"]) => Command::ListBreakpoints,
+            ("enable", [id]) => Command::Enable(parse_count(id)?),
+            ("disable", [id]) => Command::Disable(parse_count(id)?),
+            ("d" | "delete", [id]) => Command::Delete(parse_count(id)?),
+            ("trace", []) => Command::Trace,
+            ("dump", []) => Command::Dump,
+            ("h" | "help", []) => Command::Help,
+            _ => bail!("unknown command: {} (type \"help\" for a list of commands)", line.trim())
+        };
+
+        if has_condition && !matches!(command, Command::Break(..)) {
+            bail!("only breakpoints and watchpoints can have a condition");
+        }
+        Ok(command)
+    }
+}
+
+// $C000 か $C000-$C0FF
+fn parse_break(kind: BreakKind, range: &str, condition: Option<BreakCondition>) -> Result<Command> {
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+        None => {
+            let address = parse_hex(range)?;
+            (address, address)
+        }
+    };
+    if start > end {
+        bail!("invalid range: {}", range);
+    }
+    Ok(Command::Break(kind, start, end, condition))
+}
+
+// x/<個数><形式><単位> <アドレス>
+fn parse_examine(spec: &str, args: &[&str]) -> Result<Command> {
+    let address = match args {
+        [address] => parse_hex(address)?,
+        _ => bail!("usage: x/<count><x|d|i><b|h> <address>")
+    };
+
+    let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+    let count = if digits == 0 { 1 } else { parse_count(&spec[..digits])? };
+
+    let mut format = Format::Hex;
+    let mut unit = Unit::Byte;
+    for c in spec[digits..].chars() {
+        match c {
+            'x' => format = Format::Hex,
+            'd' => format = Format::Decimal,
+            'i' => format = Format::Instruction,
+            'b' => unit = Unit::Byte,
+            'h' => unit = Unit::HalfWord,
+            _ => bail!("unknown format letter: {}", c)
+        }
+    }
+
+    Ok(Command::Examine { address, count, format, unit })
+}
+
+// $C000、0xC000、C000のどれでも16進数として読む
+pub(crate) fn parse_hex(text: &str) -> Result<u16> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).with_context(|| format!("invalid hex value: {}", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8> {
+    let value = parse_hex(text)?;
+    if value > 0xFF {
+        bail!("value does not fit in a byte: {}", text);
+    }
+    Ok(value as u8)
+}
+
+fn parse_count(text: &str) -> Result<usize> {
+    text.parse().with_context(|| format!("invalid number: {}", text))
+}
+
+fn parse_register(name: &str) -> Result<Register> {
+    Register::parse(name).with_context(|| format!("unknown register: {}", name))
+}
+
+// コマンドを実行して、表示する内容を返す
+// continueなどはデバッガの状態を変えるだけで、実際の実行はエミュレーションのスレッドが行う
+pub fn execute(cpu: &mut Cpu, command: &Command) -> Result<String> {
+    let mut out = String::new();
+
+    match command {
+        Command::Step(count) => {
+            for _ in 0..*count {
+                cpu.debug_step()?;
+            }
+            out.push_str(&location(cpu));
+        },
+        Command::Next => {
+            let pc = cpu.register(Register::PC);
+            let decoded = cpu.decode_at(pc);
+            match decoded.instruction {
+                Instruction::Call(..) | Instruction::Rst(_) => {
+                    let return_address = pc.wrapping_add(decoded.length as u16);
+                    let sp = cpu.register(Register::R16(Reg16::SP));
+                    cpu.debugger.resume_with(pc, RunMode::Over { return_address, sp });
+                    out.push_str("running");
+                },
+                _ => {
+                    cpu.debug_step()?;
+                    out.push_str(&location(cpu));
+                }
+            }
+        },
+        Command::Finish => {
+            let frame = match cpu.debugger.call_stack().last() {
+                Some(frame) => *frame,
+                None => bail!("\"finish\" not meaningful in the outermost frame")
+            };
+            let pc = cpu.register(Register::PC);
+            cpu.debugger.resume_with(pc, RunMode::Finish { sp: frame.sp });
+            write!(out, "running until return to ${:04X}", frame.return_address)?;
+        },
+        Command::Continue => {
+            let pc = cpu.register(Register::PC);
+            cpu.debugger.resume(pc);
+            out.push_str("running");
+        },
+        Command::ReverseStep(count) => {
+            if !cpu.rewind.is_enabled() {
+                bail!("rewind is not enabled");
+            }
+            for i in 0..*count {
+                if !cpu.reverse_step()? {
+                    if i == 0 {
+                        bail!("no earlier state in the rewind buffer");
+                    }
+                    break;
+                }
+            }
+            out.push_str(&location(cpu));
+        },
+        Command::ReverseContinue => {
+            if !cpu.rewind.is_enabled() {
+                bail!("rewind is not enabled");
+            }
+            if !cpu.reverse_continue()? {
+                bail!("no earlier state in the rewind buffer");
+            }
+            write!(out, "{} at {}", cpu.debugger.reason(), location(cpu))?;
+        },
+        Command::Registers => out.push_str(&registers(cpu)),
+        Command::Print(reg) => {
+            let value = cpu.register(*reg);
+            if reg.is_8bit() {
+                write!(out, "${:02X} ({})", value, value)?;
+            }
+            else {
+                write!(out, "${:04X} ({})", value, value)?;
+            }
+        },
+        Command::Set(reg, value) => {
+            if reg.is_8bit() && *value > 0xFF {
+                bail!("value does not fit in an 8-bit register: ${:X}", value);
+            }
+            cpu.set_register(*reg, *value);
+            out.push_str(&registers(cpu));
+        },
+        Command::Examine { address, count, format, unit } => out.push_str(&examine(cpu, *address, *count, *format, *unit)),
+        Command::Write(address, values) => {
+            for (i, value) in values.iter().enumerate() {
+                cpu.bus.poke(address.wrapping_add(i as u16), *value)?;
+            }
+            out.push_str(&examine(cpu, *address, values.len(), Format::Hex, Unit::Byte));
+        },
+        Command::Disassemble(address, count) => out.push_str(&disassemble(cpu, *address, *count)),
+        Command::Backtrace => out.push_str(&backtrace(cpu)),
+        Command::Break(kind, start, end, condition) => {
+            let id = cpu.debugger.add(*kind, *start, *end, condition.clone());
+            write!(out, "{}", cpu.debugger.breakpoint_mut(id)?)?;
+        },
+        Command::Condition(id, condition) => {
+            let breakpoint = cpu.debugger.breakpoint_mut(*id)?;
+            breakpoint.condition = condition.clone();
+            write!(out, "{}", breakpoint)?;
+        },
+        Command::Ignore(id, count) => {
+            cpu.debugger.breakpoint_mut(*id)?.ignore = *count;
+            write!(out, "Will ignore next {} crossings of breakpoint {}.", count, id)?;
+        },
+        Command::ListBreakpoints => {
+            if cpu.debugger.breakpoints().is_empty() {
+                out.push_str("No breakpoints.");
+            }
+            for breakpoint in cpu.debugger.breakpoints() {
+                let enabled = if breakpoint.enabled { "y" } else { "n" };
+                let range = if breakpoint.start == breakpoint.end {
+                    format!("${:04X}", breakpoint.start)
+                }
+                else {
+                    format!("${:04X}-${:04X}", breakpoint.start, breakpoint.end)
+                };
+                write!(out, "{:<4} {:<17} {} {:<11}", breakpoint.id, breakpoint.kind.name(), enabled, range)?;
+                if breakpoint.kind == BreakKind::Execute && breakpoint.start == breakpoint.end {
+                    write!(out, " {}", cpu.disassemble(breakpoint.start))?;
+                }
+                writeln!(out)?;
+                if let Some(condition) = &breakpoint.condition {
+                    writeln!(out, "        stop only if {}", condition.source)?;
+                }
+                if breakpoint.hits > 0 {
+                    writeln!(out, "        already hit {} time{}", breakpoint.hits, if breakpoint.hits == 1 { "" } else { "s" })?;
+                }
+                if breakpoint.ignore > 0 {
+                    writeln!(out, "        will ignore next {} hits", breakpoint.ignore)?;
+                }
+            }
+        },
+        Command::Enable(id) => cpu.debugger.set_breakpoint_enabled(*id, true)?,
+        Command::Disable(id) => cpu.debugger.set_breakpoint_enabled(*id, false)?,
+        Command::Delete(id) => cpu.debugger.remove_breakpoint(*id)?,
+        Command::Trace => {
+            cpu.debug_flag = !cpu.debug_flag;
+            write!(out, "trace {}", if cpu.debug_flag { "on" } else { "off" })?;
+        },
+        Command::Dump => cpu.bus.ppu.dump(),
+        Command::Help => out.push_str(HELP)
+    }
+
+    // ウォッチポイントが変わっていればバスに反映する
+    cpu.debugger.sync_watcher(&mut cpu.bus.watcher);
+    Ok(out.trim_end().to_string())
+}
+
+// 現在のPCと命令
+pub fn location(cpu: &Cpu) -> String {
+    let pc = cpu.register(Register::PC);
+    let halted = if cpu.is_halted() { " (halted)" } else { "" };
+    format!("${:04X}: {}{}", pc, cpu.disassemble(pc), halted)
+}
+
+fn registers(cpu: &Cpu) -> String {
+    let f = cpu.register(Register::F);
+    let flag = |bit: u16, name: char| if f & (1 << bit) != 0 { name } else { '-' };
+    format!(
+        "AF: ${:04X}  BC: ${:04X}  DE: ${:04X}  HL: ${:04X}\nSP: ${:04X}  PC: ${:04X}  flags: {}{}{}{}  IME: {}  {}",
+        cpu.register(Register::R16(Reg16::AF)),
+        cpu.register(Register::R16(Reg16::BC)),
+        cpu.register(Register::R16(Reg16::DE)),
+        cpu.register(Register::R16(Reg16::HL)),
+        cpu.register(Register::R16(Reg16::SP)),
+        cpu.register(Register::PC),
+        flag(7, 'Z'), flag(6, 'N'), flag(5, 'H'), flag(4, 'C'),
+        cpu.ime() as u8,
+        location(cpu)
+    )
+}
+
+// バスを読むだけで、サイクルは進めない
+fn examine(cpu: &Cpu, address: u16, count: usize, format: Format, unit: Unit) -> String {
+    if format == Format::Instruction {
+        return disassemble(cpu, Some(address), count);
+    }
+
+    let (size, per_line) = match unit {
+        Unit::Byte => (1, BYTES_PER_LINE),
+        Unit::HalfWord => (2, BYTES_PER_LINE / 2)
+    };
+
+    let mut out = String::new();
+    for i in 0..count {
+        let current = address.wrapping_add((i * size) as u16);
+        if i % per_line == 0 {
+            if i != 0 {
+                out.push('\n');
+            }
+            let _ = write!(out, "${:04X}:", current);
+        }
+
+        let low = cpu.bus.peek(current).unwrap_or(0xFF) as u16;
+        let value = match unit {
+            Unit::Byte => low,
+            Unit::HalfWord => low | (cpu.bus.peek(current.wrapping_add(1)).unwrap_or(0xFF) as u16) << 8
+        };
+
+        let _ = match (format, unit) {
+            (Format::Decimal, _) => write!(out, " {}", value),
+            (_, Unit::Byte) => write!(out, " {:02X}", value),
+            (_, Unit::HalfWord) => write!(out, " {:04X}", value)
+        };
+    }
+    out
+}
+
+// addressが指定されなければ、PCの少し前から表示する
+fn disassemble(cpu: &Cpu, address: Option<u16>, count: usize) -> String {
+    let pc = cpu.register(Register::PC);
+    let mut current = address.unwrap_or_else(|| start_before(cpu, pc, DISASSEMBLE_BEFORE));
+
+    let mut out = String::new();
+    for _ in 0..count {
+        let decoded = cpu.decode_at(current);
+        let marker = if current == pc { "=>" } else { "  " };
+        let bytes: Vec<String> = (0..decoded.length as u16)
+            .map(|i| format!("{:02X}", cpu.bus.peek(current.wrapping_add(i)).unwrap_or(0xFF)))
+            .collect();
+        let _ = writeln!(out, "{} ${:04X}: {:<9} {}", marker, current, bytes.join(" "), decoded.instruction);
+        current = current.wrapping_add(decoded.length as u16);
+    }
+    out
+}
+
+// 命令の長さがまちまちなので、少し前から順にデコードしてちょうどPCにたどり着く位置を探す
+fn start_before(cpu: &Cpu, pc: u16, count: usize) -> u16 {
+    for back in (1..=count as u16 * 3).rev() {
+        let Some(start) = pc.checked_sub(back) else { continue };
+
+        let mut addresses = Vec::new();
+        let mut current = start as u32;
+        while current < pc as u32 {
+            addresses.push(current as u16);
+            current += cpu.decode_at(current as u16).length as u32;
+        }
+
+        if current == pc as u32 {
+            return addresses[addresses.len().saturating_sub(count)];
+        }
+    }
+    pc
+}
+
+// 内側の呼び出しから順に表示する
+fn backtrace(cpu: &Cpu) -> String {
+    let stack = cpu.debugger.call_stack();
+    let function = |depth: usize| match stack.len().checked_sub(depth + 1) {
+        Some(idx) => format!(" in ${:04X}", stack[idx].target),
+        None => String::new()
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "#0  ${:04X}{}", cpu.register(Register::PC), function(0));
+    for (depth, frame) in stack.iter().rev().enumerate() {
+        let interrupt = if frame.interrupt { " <interrupt>" } else { "" };
+        let _ = writeln!(out, "#{:<2} ${:04X}{}{}", depth + 1, frame.return_address, function(depth + 1), interrupt);
+    }
+    out
+}
+
+// 標準入力からコマンドを読んで実行するスレッドを起動する
+// コマンドを実行する間だけロックを取るので、止まっている間もウィンドウは描画を続ける
+// 入力を待つ間も、実行中にブレークポイントなどで止まった場合はその場所を表示する
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn(game_boy: Arc<Mutex<GameBoy>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        // 標準入力の読み込みはブロックするので、別のスレッドで読んで受け渡す
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut last_command = None;
+        loop {
+            let line = match receiver.recv_timeout(REPORT_INTERVAL) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    let mut game_boy = game_boy.lock().unwrap();
+                    let cpu = &mut game_boy.cpu;
+                    if cpu.debugger.take_report() {
+                        println!("{} at {}", cpu.debugger.reason(), location(cpu));
+                        print!("{}", PROMPT);
+                        let _ = io::stdout().flush();
+                    }
+                    continue;
+                },
+                Err(RecvTimeoutError::Disconnected) => break
+            };
+
+            // 空行の場合は直前のコマンドを繰り返す
+            let command = if line.trim().is_empty() {
+                last_command.clone()
+            }
+            else {
+                match Command::parse(&line) {
+                    Ok(command) => Some(command),
+                    Err(e) => {
+                        println!("{}", e);
+                        None
+                    }
+                }
+            };
+
+            if let Some(command) = command {
+                let result = execute(&mut game_boy.lock().unwrap().cpu, &command);
+                match result {
+                    Ok(output) if output.is_empty() => {},
+                    Ok(output) => println!("{}", output),
+                    Err(e) => println!("{}", e)
+                }
+                last_command = Some(command);
+            }
+
+            print!("{}", PROMPT);
+            let _ = io::stdout().flush();
+        }
+    })
+}
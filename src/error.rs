@@ -0,0 +1,72 @@
+use std::fmt;
+
+use crate::cpu::CpuFault;
+use crate::rom::CartridgeType;
+
+// フロントエンドに返すエミュレータのエラー
+// 内部ではanyhowで伝播させ、GameBoyの境界でこの型にまとめる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    // ROMのヘッダが壊れている、またはヘッダとROMの内容が合わない
+    InvalidHeader(String),
+    // 対応していないカートリッジの種類
+    UnsupportedMapper(CartridgeType),
+    // MBCがバンクの範囲外のアドレスにアクセスした
+    BadBank(u16),
+    // 未定義の命令でCPUがロックした
+    CpuLocked(CpuFault),
+    // セーブデータの読み書きに失敗した
+    SaveIo(String),
+    // セーブステートを読み込めなかった
+    InvalidState(String),
+    // それ以外の内部エラー
+    Internal(String)
+}
+
+pub type EmuResult<T> = std::result::Result<T, EmuError>;
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::InvalidHeader(message) => write!(f, "invalid ROM header: {}", message),
+            EmuError::UnsupportedMapper(cartridge_type) => write!(f, "unsupported cartridge type: {}", cartridge_type),
+            EmuError::BadBank(address) => write!(f, "cartridge access out of bank range: {:#06X}", address),
+            EmuError::CpuLocked(fault) => write!(f, "{}", fault),
+            EmuError::SaveIo(message) => write!(f, "save data I/O failed: {}", message),
+            EmuError::InvalidState(message) => write!(f, "invalid save state: {}", message),
+            EmuError::Internal(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
+impl From<CpuFault> for EmuError {
+    fn from(fault: CpuFault) -> Self {
+        EmuError::CpuLocked(fault)
+    }
+}
+
+// 内部のエラーがEmuErrorやCpuFaultならそれを取り出し、そうでなければInternalにする
+impl From<anyhow::Error> for EmuError {
+    fn from(error: anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<EmuError>() {
+            return error.clone();
+        }
+        if let Some(fault) = error.downcast_ref::<CpuFault>() {
+            return EmuError::CpuLocked(*fault);
+        }
+        EmuError::Internal(format!("{:#}", error))
+    }
+}
+
+impl EmuError {
+    // 内部のエラーを、種類が決まっていなければ指定したものとして扱う
+    // セーブデータの読み書きのように、どこで失敗してもエラーの種類が同じ場合に使う
+    pub fn classify(error: anyhow::Error, kind: fn(String) -> EmuError) -> Self {
+        match EmuError::from(error) {
+            EmuError::Internal(message) => kind(message),
+            error => error
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::debugger::{BreakKind, Register};
+use crate::instruction::Reg16;
+use crate::GameBoy;
+
+// GDBのリモートシリアルプロトコル(RSP)でデバッガをつなぐ
+// パケットは $データ#チェックサム の形で、受け取った側は + か - で応答する
+
+// 止まった理由として返すシグナル
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// 実行中に中断(Ctrl-C)が来ていないか確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// g/Gパケットで送るレジスタの順番。どれも16bitのリトルエンディアン
+const REGISTERS: [Register; 6] = [
+    Register::R16(Reg16::AF),
+    Register::R16(Reg16::BC),
+    Register::R16(Reg16::DE),
+    Register::R16(Reg16::HL),
+    Register::R16(Reg16::SP),
+    Register::PC
+];
+
+// qXfer:features:readで返すレジスタの定義
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.sm83.core">
+    <reg name="af" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="bc" bitsize="16" type="uint16"/>
+    <reg name="de" bitsize="16" type="uint16"/>
+    <reg name="hl" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// 引数から --gdb <port> を取り除いて、そのポート番号を返す。指定がなければNone
+pub fn take_port_option(args: &mut Vec<String>) -> Result<Option<u16>> {
+    let idx = match args.iter().position(|arg| arg == "--gdb") {
+        Some(idx) => idx,
+        None => return Ok(None)
+    };
+    if idx + 1 >= args.len() {
+        bail!("--gdb requires a port");
+    }
+    let port = args[idx + 1].parse().with_context(|| format!("invalid port: {}", args[idx + 1]))?;
+    args.drain(idx..idx + 2);
+    Ok(Some(port))
+}
+
+// ローカルのTCPポートでGDBの接続を待つ
+pub struct GdbServer {
+    listener: TcpListener
+}
+
+impl GdbServer {
+    // 外部からは接続できないように127.0.0.1で待ち受ける。0を指定すると空いているポートを使う
+    pub fn bind(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).with_context(|| format!("failed to listen on port {}", port))?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    // 接続を1つずつ処理するスレッドを起動する
+    // 接続するとCPUを止め、切断すると実行を再開する
+    pub fn spawn(self, game_boy: Arc<Mutex<GameBoy>>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+
+                let mut session = GdbSession::new(stream, game_boy.clone());
+                if let Err(e) = session.run() {
+                    eprintln!("gdb connection closed: {}", e);
+                }
+
+                let mut game_boy = game_boy.lock().unwrap();
+                let pc = game_boy.cpu.register(Register::PC);
+                game_boy.cpu.debugger.resume(pc);
+            }
+        })
+    }
+}
+
+// 受け取ったパケット
+enum Packet {
+    Command(String),
+    // 実行中に送られてくる中断要求(0x03)
+    Interrupt
+}
+
+struct GdbSession {
+    stream: TcpStream,
+    game_boy: Arc<Mutex<GameBoy>>,
+    // QStartNoAckModeの後は+/-の応答をしない
+    no_ack: bool
+}
+
+impl GdbSession {
+    fn new(stream: TcpStream, game_boy: Arc<Mutex<GameBoy>>) -> Self {
+        Self {
+            stream,
+            game_boy,
+            no_ack: false
+        }
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.stream.set_nodelay(true)?;
+        self.game_boy.lock().unwrap().cpu.debugger.stop();
+
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => return Ok(())
+            };
+
+            let command = match packet {
+                Packet::Command(command) => command,
+                Packet::Interrupt => {
+                    self.game_boy.lock().unwrap().cpu.debugger.stop();
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+            };
+
+            match command.as_bytes().first() {
+                Some(b'c') => {
+                    let reply = self.resume(&command[1..])?;
+                    self.send(&reply)?;
+                },
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(());
+                },
+                Some(b'k') => return Ok(()),
+                _ => {
+                    // 解釈できないパケットにはエラーを返して接続は続ける
+                    let reply = self.handle(&command).unwrap_or_else(|_| "E01".to_string());
+                    self.send(&reply)?;
+                }
+            }
+        }
+    }
+
+    // 実行を伴わないパケットを処理して、その応答を返す
+    fn handle(&mut self, command: &str) -> Result<String> {
+        // 空のパケットには空で返す。先頭がASCIIでない場合も文字の境界で分ける
+        let kind_len = match command.chars().next() {
+            Some(c) => c.len_utf8(),
+            None => return Ok(String::new())
+        };
+        let (kind, args) = command.split_at(kind_len);
+        let mut game_boy = self.game_boy.lock().unwrap();
+        let cpu = &mut game_boy.cpu;
+
+        let reply = match kind {
+            "?" => stop_reply(&game_boy),
+            "g" => REGISTERS.iter().map(|reg| encode_u16(cpu.register(*reg))).collect(),
+            "G" => {
+                if args.len() != REGISTERS.len() * 4 {
+                    bail!("invalid register data: {}", args);
+                }
+                for (idx, reg) in REGISTERS.iter().enumerate() {
+                    cpu.set_register(*reg, decode_u16(&args[idx * 4..idx * 4 + 4])?);
+                }
+                "OK".to_string()
+            },
+            "p" => encode_u16(cpu.register(register_at(args)?)),
+            "P" => {
+                let (idx, value) = args.split_once('=').context("invalid P packet")?;
+                cpu.set_register(register_at(idx)?, decode_u16(value)?);
+                "OK".to_string()
+            },
+            "m" => {
+                let (address, length) = parse_range(args)?;
+                let mut data = String::new();
+                for i in 0..length {
+                    data.push_str(&format!("{:02x}", cpu.bus.peek(address.wrapping_add(i))?));
+                }
+                data
+            },
+            "M" => {
+                let (range, data) = args.split_once(':').context("invalid M packet")?;
+                let (address, length) = parse_range(range)?;
+                let data = decode_bytes(data)?;
+                if data.len() != length as usize {
+                    bail!("length mismatch: {}", args);
+                }
+                for (i, value) in data.iter().enumerate() {
+                    cpu.bus.poke(address.wrapping_add(i as u16), *value)?;
+                }
+                "OK".to_string()
+            },
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let (point, address, length) = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(point), Some(address), Some(length)) => (point, parse_hex(address)?, parse_hex(length)?),
+                    _ => bail!("invalid breakpoint packet: {}", args)
+                };
+                let break_kind = match point {
+                    // ソフトウェアとハードウェアのブレークポイントは区別しない
+                    "0" | "1" => BreakKind::Execute,
+                    "2" => BreakKind::Write,
+                    "3" => BreakKind::Read,
+                    "4" => BreakKind::Access,
+                    _ => return Ok(String::new())
+                };
+                let end = if break_kind == BreakKind::Execute { address } else { address.wrapping_add(length.max(1) - 1) };
+
+                if kind == "Z" {
+                    cpu.debugger.add(break_kind, address, end, None);
+                }
+                else {
+                    cpu.debugger.remove_matching(break_kind, address, end);
+                }
+                cpu.debugger.sync_watcher(&mut cpu.bus.watcher);
+                "OK".to_string()
+            },
+            "s" => {
+                if !args.is_empty() {
+                    cpu.set_register(Register::PC, parse_hex(args)?);
+                }
+                // 未定義の命令でロックした場合は、stop_replyがSIGILLを返す
+                if let Err(e) = cpu.debug_step() {
+                    if cpu.fault().is_none() {
+                        return Err(e);
+                    }
+                }
+                stop_reply(&game_boy)
+            },
+            // 巻き戻しのバッファを使って逆向きに実行する。バッファの先頭まで戻ったらreplaylog:begin
+            "b" => {
+                let moved = match args {
+                    "s" => cpu.reverse_step()?,
+                    "c" => cpu.reverse_continue()?,
+                    _ => return Ok(String::new())
+                };
+                if moved { stop_reply(&game_boy) } else { format!("T{:02x}replaylog:begin;", SIGTRAP) }
+            },
+            "H" => "OK".to_string(),
+            "q" => query(args),
+            "Q" if args == "StartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            },
+            _ => String::new()
+        };
+        Ok(reply)
+    }
+
+    // 止まるまで実行する。実行はフロントエンドのスレッドが進めるので、止まったかどうかだけを見る
+    fn resume(&mut self, args: &str) -> Result<String> {
+        {
+            let mut game_boy = self.game_boy.lock().unwrap();
+            let cpu = &mut game_boy.cpu;
+            if !args.is_empty() {
+                cpu.set_register(Register::PC, parse_hex(args)?);
+            }
+            let pc = cpu.register(Register::PC);
+            cpu.debugger.resume(pc);
+        }
+
+        self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let reply = loop {
+            {
+                let game_boy = self.game_boy.lock().unwrap();
+                // ロックしたCPUはそれ以上進まないので、デバッガが止まっていなくても止まったと返す
+                if game_boy.cpu.debugger.is_stopped() || game_boy.cpu.fault().is_some() {
+                    break stop_reply(&game_boy);
+                }
+            }
+
+            let mut byte = [0];
+            match self.stream.read(&mut byte) {
+                Ok(0) => bail!("connection closed while running"),
+                Ok(_) if byte[0] == 0x03 => {
+                    self.game_boy.lock().unwrap().cpu.debugger.stop();
+                    break format!("S{:02x}", SIGINT);
+                },
+                // 実行中に届いた+などは読み捨てる
+                Ok(_) => {},
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+                Err(e) => return Err(e.into())
+            }
+        };
+        self.stream.set_read_timeout(None)?;
+        Ok(reply)
+    }
+
+    // 次のパケットを読む。接続が閉じられたらNone
+    fn read_packet(&mut self) -> Result<Option<Packet>> {
+        loop {
+            let byte = match self.read_byte()? {
+                Some(byte) => byte,
+                None => return Ok(None)
+            };
+            match byte {
+                b'$' => {},
+                0x03 => return Ok(Some(Packet::Interrupt)),
+                // +/-やパケットの外の文字は無視する
+                _ => continue
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None)
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, v| sum.wrapping_add(*v));
+            if expected != Some(actual) {
+                if !self.no_ack {
+                    self.stream.write_all(b"-")?;
+                }
+                continue;
+            }
+            if !self.no_ack {
+                self.stream.write_all(b"+")?;
+            }
+            return Ok(Some(Packet::Command(String::from_utf8_lossy(&data).into_owned())));
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0]))
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, v| sum.wrapping_add(v));
+        write!(self.stream, "${}#{:02x}", data, checksum)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+// 止まった理由。ウォッチポイントの場合はアクセスしたアドレスも返す
+fn stop_reply(game_boy: &GameBoy) -> String {
+    if game_boy.cpu.fault().is_some() {
+        return format!("S{:02x}", SIGILL);
+    }
+    match game_boy.cpu.debugger.watch_hit() {
+        Some((BreakKind::Write, address)) => format!("T{:02x}watch:{:04x};", SIGTRAP, address),
+        Some((BreakKind::Read, address)) => format!("T{:02x}rwatch:{:04x};", SIGTRAP, address),
+        Some((BreakKind::Access, address)) => format!("T{:02x}awatch:{:04x};", SIGTRAP, address),
+        _ => format!("S{:02x}", SIGTRAP)
+    }
+}
+
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        return "PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string();
+    }
+    if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        return match parse_range(range) {
+            Ok((offset, length)) => {
+                let start = (offset as usize).min(TARGET_XML.len());
+                let end = (start + length as usize).min(TARGET_XML.len());
+                let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+                format!("{}{}", prefix, &TARGET_XML[start..end])
+            },
+            Err(_) => "E01".to_string()
+        };
+    }
+    match args {
+        "Attached" => "1".to_string(),
+        "C" => "QC1".to_string(),
+        "fThreadInfo" => "m1".to_string(),
+        "sThreadInfo" => "l".to_string(),
+        _ => String::new()
+    }
+}
+
+fn register_at(idx: &str) -> Result<Register> {
+    let idx = usize::from_str_radix(idx, 16).with_context(|| format!("invalid register: {}", idx))?;
+    REGISTERS.get(idx).copied().with_context(|| format!("invalid register: {}", idx))
+}
+
+fn parse_hex(value: &str) -> Result<u16> {
+    u16::from_str_radix(value, 16).with_context(|| format!("invalid number: {}", value))
+}
+
+// アドレス,長さ
+fn parse_range(args: &str) -> Result<(u16, u16)> {
+    let (address, length) = args.split_once(',').with_context(|| format!("invalid range: {}", args))?;
+    Ok((parse_hex(address)?, parse_hex(length)?))
+}
+
+fn encode_u16(value: u16) -> String {
+    format!("{:02x}{:02x}", value & 0xFF, value >> 8)
+}
+
+fn decode_u16(data: &str) -> Result<u16> {
+    match decode_bytes(data)?.as_slice() {
+        [low, high] => Ok(u16::from_le_bytes([*low, *high])),
+        _ => bail!("invalid register value: {}", data)
+    }
+}
+
+fn decode_bytes(data: &str) -> Result<Vec<u8>> {
+    if !data.len().is_multiple_of(2) || !data.is_ascii() {
+        bail!("invalid hex data: {}", data);
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&data[i..i + 2], 16).with_context(|| format!("invalid hex data: {}", data)))
+        .collect()
+}
@@ -0,0 +1,642 @@
+use std::fmt;
+
+use Instruction::*;
+use Operand as O;
+use Reg8 as R;
+use Reg16 as W;
+use Condition as Cond;
+
+// 8bitレジスタ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg8 {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L
+}
+
+// 16bitレジスタ(ペア)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg16 {
+    AF,
+    BC,
+    DE,
+    HL,
+    SP
+}
+
+// 条件付きジャンプなどの条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C
+}
+
+// 8bitの読み書きの対象
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg8),
+    // d8
+    Imm(u8),
+    // (BC), (DE), (HL)
+    Indirect(Reg16),
+    // (HL+)
+    HlInc,
+    // (HL-)
+    HlDec,
+    // (a16)
+    Absolute(u16),
+    // (0xFF00 + a8)
+    High(u8),
+    // (0xFF00 + C)
+    HighC
+}
+
+// デコードした命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Ld(Operand, Operand),
+    Ld16(Reg16, u16),
+    // LD (a16), SP
+    LdAbsoluteSp(u16),
+    // LD HL, SP+r8
+    LdHlSp(i8),
+    // LD SP, HL
+    LdSpHl,
+    Push(Reg16),
+    Pop(Reg16),
+    Add(Operand),
+    Adc(Operand),
+    Sub(Operand),
+    Sbc(Operand),
+    And(Operand),
+    Xor(Operand),
+    Or(Operand),
+    Cp(Operand),
+    Inc(Operand),
+    Dec(Operand),
+    Inc16(Reg16),
+    Dec16(Reg16),
+    // ADD HL, rr
+    AddHl(Reg16),
+    // ADD SP, r8
+    AddSp(i8),
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jp(Option<Condition>, u16),
+    JpHl,
+    Jr(Option<Condition>, i8),
+    Call(Option<Condition>, u16),
+    Ret(Option<Condition>),
+    Reti,
+    Rst(u8),
+    Rlc(Operand),
+    Rrc(Operand),
+    Rl(Operand),
+    Rr(Operand),
+    Sla(Operand),
+    Sra(Operand),
+    Swap(Operand),
+    Srl(Operand),
+    Bit(u8, Operand),
+    Res(u8, Operand),
+    Set(u8, Operand),
+    // 未定義の命令
+    Illegal(u8)
+}
+
+// 命令と、その長さ(byte)・サイクル数
+// branch_cyclesは条件付き命令で分岐したときのサイクル数。それ以外の命令ではcyclesと同じ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    pub instruction: Instruction,
+    pub length: u8,
+    pub cycles: u8,
+    pub branch_cycles: u8
+}
+
+const fn entry(instruction: Instruction, length: u8, cycles: u8, branch_cycles: u8) -> Decoded {
+    Decoded {
+        instruction,
+        length,
+        cycles,
+        branch_cycles
+    }
+}
+
+// 0xCBプレフィックスなしの命令表
+// 即値は0で置いてあり、デコード時に実際の値で埋める
+// 0xCBはプレフィックスなので、実際にはCB_OPERATIONSから作った命令表を使う
+const OPCODES: [Decoded; 256] = [
+    entry(Nop, 1, 4, 4), // 0x00
+    entry(Ld16(W::BC, 0), 3, 12, 12), // 0x01
+    entry(Ld(O::Indirect(W::BC), O::Reg(R::A)), 1, 8, 8), // 0x02
+    entry(Inc16(W::BC), 1, 8, 8), // 0x03
+    entry(Inc(O::Reg(R::B)), 1, 4, 4), // 0x04
+    entry(Dec(O::Reg(R::B)), 1, 4, 4), // 0x05
+    entry(Ld(O::Reg(R::B), O::Imm(0)), 2, 8, 8), // 0x06
+    entry(Rlca, 1, 4, 4), // 0x07
+    entry(LdAbsoluteSp(0), 3, 20, 20), // 0x08
+    entry(AddHl(W::BC), 1, 8, 8), // 0x09
+    entry(Ld(O::Reg(R::A), O::Indirect(W::BC)), 1, 8, 8), // 0x0A
+    entry(Dec16(W::BC), 1, 8, 8), // 0x0B
+    entry(Inc(O::Reg(R::C)), 1, 4, 4), // 0x0C
+    entry(Dec(O::Reg(R::C)), 1, 4, 4), // 0x0D
+    entry(Ld(O::Reg(R::C), O::Imm(0)), 2, 8, 8), // 0x0E
+    entry(Rrca, 1, 4, 4), // 0x0F
+    entry(Stop, 2, 4, 4), // 0x10
+    entry(Ld16(W::DE, 0), 3, 12, 12), // 0x11
+    entry(Ld(O::Indirect(W::DE), O::Reg(R::A)), 1, 8, 8), // 0x12
+    entry(Inc16(W::DE), 1, 8, 8), // 0x13
+    entry(Inc(O::Reg(R::D)), 1, 4, 4), // 0x14
+    entry(Dec(O::Reg(R::D)), 1, 4, 4), // 0x15
+    entry(Ld(O::Reg(R::D), O::Imm(0)), 2, 8, 8), // 0x16
+    entry(Rla, 1, 4, 4), // 0x17
+    entry(Jr(None, 0), 2, 12, 12), // 0x18
+    entry(AddHl(W::DE), 1, 8, 8), // 0x19
+    entry(Ld(O::Reg(R::A), O::Indirect(W::DE)), 1, 8, 8), // 0x1A
+    entry(Dec16(W::DE), 1, 8, 8), // 0x1B
+    entry(Inc(O::Reg(R::E)), 1, 4, 4), // 0x1C
+    entry(Dec(O::Reg(R::E)), 1, 4, 4), // 0x1D
+    entry(Ld(O::Reg(R::E), O::Imm(0)), 2, 8, 8), // 0x1E
+    entry(Rra, 1, 4, 4), // 0x1F
+    entry(Jr(Some(Cond::NZ), 0), 2, 8, 12), // 0x20
+    entry(Ld16(W::HL, 0), 3, 12, 12), // 0x21
+    entry(Ld(O::HlInc, O::Reg(R::A)), 1, 8, 8), // 0x22
+    entry(Inc16(W::HL), 1, 8, 8), // 0x23
+    entry(Inc(O::Reg(R::H)), 1, 4, 4), // 0x24
+    entry(Dec(O::Reg(R::H)), 1, 4, 4), // 0x25
+    entry(Ld(O::Reg(R::H), O::Imm(0)), 2, 8, 8), // 0x26
+    entry(Daa, 1, 4, 4), // 0x27
+    entry(Jr(Some(Cond::Z), 0), 2, 8, 12), // 0x28
+    entry(AddHl(W::HL), 1, 8, 8), // 0x29
+    entry(Ld(O::Reg(R::A), O::HlInc), 1, 8, 8), // 0x2A
+    entry(Dec16(W::HL), 1, 8, 8), // 0x2B
+    entry(Inc(O::Reg(R::L)), 1, 4, 4), // 0x2C
+    entry(Dec(O::Reg(R::L)), 1, 4, 4), // 0x2D
+    entry(Ld(O::Reg(R::L), O::Imm(0)), 2, 8, 8), // 0x2E
+    entry(Cpl, 1, 4, 4), // 0x2F
+    entry(Jr(Some(Cond::NC), 0), 2, 8, 12), // 0x30
+    entry(Ld16(W::SP, 0), 3, 12, 12), // 0x31
+    entry(Ld(O::HlDec, O::Reg(R::A)), 1, 8, 8), // 0x32
+    entry(Inc16(W::SP), 1, 8, 8), // 0x33
+    entry(Inc(O::Indirect(W::HL)), 1, 12, 12), // 0x34
+    entry(Dec(O::Indirect(W::HL)), 1, 12, 12), // 0x35
+    entry(Ld(O::Indirect(W::HL), O::Imm(0)), 2, 12, 12), // 0x36
+    entry(Scf, 1, 4, 4), // 0x37
+    entry(Jr(Some(Cond::C), 0), 2, 8, 12), // 0x38
+    entry(AddHl(W::SP), 1, 8, 8), // 0x39
+    entry(Ld(O::Reg(R::A), O::HlDec), 1, 8, 8), // 0x3A
+    entry(Dec16(W::SP), 1, 8, 8), // 0x3B
+    entry(Inc(O::Reg(R::A)), 1, 4, 4), // 0x3C
+    entry(Dec(O::Reg(R::A)), 1, 4, 4), // 0x3D
+    entry(Ld(O::Reg(R::A), O::Imm(0)), 2, 8, 8), // 0x3E
+    entry(Ccf, 1, 4, 4), // 0x3F
+    entry(Ld(O::Reg(R::B), O::Reg(R::B)), 1, 4, 4), // 0x40
+    entry(Ld(O::Reg(R::B), O::Reg(R::C)), 1, 4, 4), // 0x41
+    entry(Ld(O::Reg(R::B), O::Reg(R::D)), 1, 4, 4), // 0x42
+    entry(Ld(O::Reg(R::B), O::Reg(R::E)), 1, 4, 4), // 0x43
+    entry(Ld(O::Reg(R::B), O::Reg(R::H)), 1, 4, 4), // 0x44
+    entry(Ld(O::Reg(R::B), O::Reg(R::L)), 1, 4, 4), // 0x45
+    entry(Ld(O::Reg(R::B), O::Indirect(W::HL)), 1, 8, 8), // 0x46
+    entry(Ld(O::Reg(R::B), O::Reg(R::A)), 1, 4, 4), // 0x47
+    entry(Ld(O::Reg(R::C), O::Reg(R::B)), 1, 4, 4), // 0x48
+    entry(Ld(O::Reg(R::C), O::Reg(R::C)), 1, 4, 4), // 0x49
+    entry(Ld(O::Reg(R::C), O::Reg(R::D)), 1, 4, 4), // 0x4A
+    entry(Ld(O::Reg(R::C), O::Reg(R::E)), 1, 4, 4), // 0x4B
+    entry(Ld(O::Reg(R::C), O::Reg(R::H)), 1, 4, 4), // 0x4C
+    entry(Ld(O::Reg(R::C), O::Reg(R::L)), 1, 4, 4), // 0x4D
+    entry(Ld(O::Reg(R::C), O::Indirect(W::HL)), 1, 8, 8), // 0x4E
+    entry(Ld(O::Reg(R::C), O::Reg(R::A)), 1, 4, 4), // 0x4F
+    entry(Ld(O::Reg(R::D), O::Reg(R::B)), 1, 4, 4), // 0x50
+    entry(Ld(O::Reg(R::D), O::Reg(R::C)), 1, 4, 4), // 0x51
+    entry(Ld(O::Reg(R::D), O::Reg(R::D)), 1, 4, 4), // 0x52
+    entry(Ld(O::Reg(R::D), O::Reg(R::E)), 1, 4, 4), // 0x53
+    entry(Ld(O::Reg(R::D), O::Reg(R::H)), 1, 4, 4), // 0x54
+    entry(Ld(O::Reg(R::D), O::Reg(R::L)), 1, 4, 4), // 0x55
+    entry(Ld(O::Reg(R::D), O::Indirect(W::HL)), 1, 8, 8), // 0x56
+    entry(Ld(O::Reg(R::D), O::Reg(R::A)), 1, 4, 4), // 0x57
+    entry(Ld(O::Reg(R::E), O::Reg(R::B)), 1, 4, 4), // 0x58
+    entry(Ld(O::Reg(R::E), O::Reg(R::C)), 1, 4, 4), // 0x59
+    entry(Ld(O::Reg(R::E), O::Reg(R::D)), 1, 4, 4), // 0x5A
+    entry(Ld(O::Reg(R::E), O::Reg(R::E)), 1, 4, 4), // 0x5B
+    entry(Ld(O::Reg(R::E), O::Reg(R::H)), 1, 4, 4), // 0x5C
+    entry(Ld(O::Reg(R::E), O::Reg(R::L)), 1, 4, 4), // 0x5D
+    entry(Ld(O::Reg(R::E), O::Indirect(W::HL)), 1, 8, 8), // 0x5E
+    entry(Ld(O::Reg(R::E), O::Reg(R::A)), 1, 4, 4), // 0x5F
+    entry(Ld(O::Reg(R::H), O::Reg(R::B)), 1, 4, 4), // 0x60
+    entry(Ld(O::Reg(R::H), O::Reg(R::C)), 1, 4, 4), // 0x61
+    entry(Ld(O::Reg(R::H), O::Reg(R::D)), 1, 4, 4), // 0x62
+    entry(Ld(O::Reg(R::H), O::Reg(R::E)), 1, 4, 4), // 0x63
+    entry(Ld(O::Reg(R::H), O::Reg(R::H)), 1, 4, 4), // 0x64
+    entry(Ld(O::Reg(R::H), O::Reg(R::L)), 1, 4, 4), // 0x65
+    entry(Ld(O::Reg(R::H), O::Indirect(W::HL)), 1, 8, 8), // 0x66
+    entry(Ld(O::Reg(R::H), O::Reg(R::A)), 1, 4, 4), // 0x67
+    entry(Ld(O::Reg(R::L), O::Reg(R::B)), 1, 4, 4), // 0x68
+    entry(Ld(O::Reg(R::L), O::Reg(R::C)), 1, 4, 4), // 0x69
+    entry(Ld(O::Reg(R::L), O::Reg(R::D)), 1, 4, 4), // 0x6A
+    entry(Ld(O::Reg(R::L), O::Reg(R::E)), 1, 4, 4), // 0x6B
+    entry(Ld(O::Reg(R::L), O::Reg(R::H)), 1, 4, 4), // 0x6C
+    entry(Ld(O::Reg(R::L), O::Reg(R::L)), 1, 4, 4), // 0x6D
+    entry(Ld(O::Reg(R::L), O::Indirect(W::HL)), 1, 8, 8), // 0x6E
+    entry(Ld(O::Reg(R::L), O::Reg(R::A)), 1, 4, 4), // 0x6F
+    entry(Ld(O::Indirect(W::HL), O::Reg(R::B)), 1, 8, 8), // 0x70
+    entry(Ld(O::Indirect(W::HL), O::Reg(R::C)), 1, 8, 8), // 0x71
+    entry(Ld(O::Indirect(W::HL), O::Reg(R::D)), 1, 8, 8), // 0x72
+    entry(Ld(O::Indirect(W::HL), O::Reg(R::E)), 1, 8, 8), // 0x73
+    entry(Ld(O::Indirect(W::HL), O::Reg(R::H)), 1, 8, 8), // 0x74
+    entry(Ld(O::Indirect(W::HL), O::Reg(R::L)), 1, 8, 8), // 0x75
+    entry(Halt, 1, 4, 4), // 0x76
+    entry(Ld(O::Indirect(W::HL), O::Reg(R::A)), 1, 8, 8), // 0x77
+    entry(Ld(O::Reg(R::A), O::Reg(R::B)), 1, 4, 4), // 0x78
+    entry(Ld(O::Reg(R::A), O::Reg(R::C)), 1, 4, 4), // 0x79
+    entry(Ld(O::Reg(R::A), O::Reg(R::D)), 1, 4, 4), // 0x7A
+    entry(Ld(O::Reg(R::A), O::Reg(R::E)), 1, 4, 4), // 0x7B
+    entry(Ld(O::Reg(R::A), O::Reg(R::H)), 1, 4, 4), // 0x7C
+    entry(Ld(O::Reg(R::A), O::Reg(R::L)), 1, 4, 4), // 0x7D
+    entry(Ld(O::Reg(R::A), O::Indirect(W::HL)), 1, 8, 8), // 0x7E
+    entry(Ld(O::Reg(R::A), O::Reg(R::A)), 1, 4, 4), // 0x7F
+    entry(Add(O::Reg(R::B)), 1, 4, 4), // 0x80
+    entry(Add(O::Reg(R::C)), 1, 4, 4), // 0x81
+    entry(Add(O::Reg(R::D)), 1, 4, 4), // 0x82
+    entry(Add(O::Reg(R::E)), 1, 4, 4), // 0x83
+    entry(Add(O::Reg(R::H)), 1, 4, 4), // 0x84
+    entry(Add(O::Reg(R::L)), 1, 4, 4), // 0x85
+    entry(Add(O::Indirect(W::HL)), 1, 8, 8), // 0x86
+    entry(Add(O::Reg(R::A)), 1, 4, 4), // 0x87
+    entry(Adc(O::Reg(R::B)), 1, 4, 4), // 0x88
+    entry(Adc(O::Reg(R::C)), 1, 4, 4), // 0x89
+    entry(Adc(O::Reg(R::D)), 1, 4, 4), // 0x8A
+    entry(Adc(O::Reg(R::E)), 1, 4, 4), // 0x8B
+    entry(Adc(O::Reg(R::H)), 1, 4, 4), // 0x8C
+    entry(Adc(O::Reg(R::L)), 1, 4, 4), // 0x8D
+    entry(Adc(O::Indirect(W::HL)), 1, 8, 8), // 0x8E
+    entry(Adc(O::Reg(R::A)), 1, 4, 4), // 0x8F
+    entry(Sub(O::Reg(R::B)), 1, 4, 4), // 0x90
+    entry(Sub(O::Reg(R::C)), 1, 4, 4), // 0x91
+    entry(Sub(O::Reg(R::D)), 1, 4, 4), // 0x92
+    entry(Sub(O::Reg(R::E)), 1, 4, 4), // 0x93
+    entry(Sub(O::Reg(R::H)), 1, 4, 4), // 0x94
+    entry(Sub(O::Reg(R::L)), 1, 4, 4), // 0x95
+    entry(Sub(O::Indirect(W::HL)), 1, 8, 8), // 0x96
+    entry(Sub(O::Reg(R::A)), 1, 4, 4), // 0x97
+    entry(Sbc(O::Reg(R::B)), 1, 4, 4), // 0x98
+    entry(Sbc(O::Reg(R::C)), 1, 4, 4), // 0x99
+    entry(Sbc(O::Reg(R::D)), 1, 4, 4), // 0x9A
+    entry(Sbc(O::Reg(R::E)), 1, 4, 4), // 0x9B
+    entry(Sbc(O::Reg(R::H)), 1, 4, 4), // 0x9C
+    entry(Sbc(O::Reg(R::L)), 1, 4, 4), // 0x9D
+    entry(Sbc(O::Indirect(W::HL)), 1, 8, 8), // 0x9E
+    entry(Sbc(O::Reg(R::A)), 1, 4, 4), // 0x9F
+    entry(And(O::Reg(R::B)), 1, 4, 4), // 0xA0
+    entry(And(O::Reg(R::C)), 1, 4, 4), // 0xA1
+    entry(And(O::Reg(R::D)), 1, 4, 4), // 0xA2
+    entry(And(O::Reg(R::E)), 1, 4, 4), // 0xA3
+    entry(And(O::Reg(R::H)), 1, 4, 4), // 0xA4
+    entry(And(O::Reg(R::L)), 1, 4, 4), // 0xA5
+    entry(And(O::Indirect(W::HL)), 1, 8, 8), // 0xA6
+    entry(And(O::Reg(R::A)), 1, 4, 4), // 0xA7
+    entry(Xor(O::Reg(R::B)), 1, 4, 4), // 0xA8
+    entry(Xor(O::Reg(R::C)), 1, 4, 4), // 0xA9
+    entry(Xor(O::Reg(R::D)), 1, 4, 4), // 0xAA
+    entry(Xor(O::Reg(R::E)), 1, 4, 4), // 0xAB
+    entry(Xor(O::Reg(R::H)), 1, 4, 4), // 0xAC
+    entry(Xor(O::Reg(R::L)), 1, 4, 4), // 0xAD
+    entry(Xor(O::Indirect(W::HL)), 1, 8, 8), // 0xAE
+    entry(Xor(O::Reg(R::A)), 1, 4, 4), // 0xAF
+    entry(Or(O::Reg(R::B)), 1, 4, 4), // 0xB0
+    entry(Or(O::Reg(R::C)), 1, 4, 4), // 0xB1
+    entry(Or(O::Reg(R::D)), 1, 4, 4), // 0xB2
+    entry(Or(O::Reg(R::E)), 1, 4, 4), // 0xB3
+    entry(Or(O::Reg(R::H)), 1, 4, 4), // 0xB4
+    entry(Or(O::Reg(R::L)), 1, 4, 4), // 0xB5
+    entry(Or(O::Indirect(W::HL)), 1, 8, 8), // 0xB6
+    entry(Or(O::Reg(R::A)), 1, 4, 4), // 0xB7
+    entry(Cp(O::Reg(R::B)), 1, 4, 4), // 0xB8
+    entry(Cp(O::Reg(R::C)), 1, 4, 4), // 0xB9
+    entry(Cp(O::Reg(R::D)), 1, 4, 4), // 0xBA
+    entry(Cp(O::Reg(R::E)), 1, 4, 4), // 0xBB
+    entry(Cp(O::Reg(R::H)), 1, 4, 4), // 0xBC
+    entry(Cp(O::Reg(R::L)), 1, 4, 4), // 0xBD
+    entry(Cp(O::Indirect(W::HL)), 1, 8, 8), // 0xBE
+    entry(Cp(O::Reg(R::A)), 1, 4, 4), // 0xBF
+    entry(Ret(Some(Cond::NZ)), 1, 8, 20), // 0xC0
+    entry(Pop(W::BC), 1, 12, 12), // 0xC1
+    entry(Jp(Some(Cond::NZ), 0), 3, 12, 16), // 0xC2
+    entry(Jp(None, 0), 3, 16, 16), // 0xC3
+    entry(Call(Some(Cond::NZ), 0), 3, 12, 24), // 0xC4
+    entry(Push(W::BC), 1, 16, 16), // 0xC5
+    entry(Add(O::Imm(0)), 2, 8, 8), // 0xC6
+    entry(Rst(0x00), 1, 16, 16), // 0xC7
+    entry(Ret(Some(Cond::Z)), 1, 8, 20), // 0xC8
+    entry(Ret(None), 1, 16, 16), // 0xC9
+    entry(Jp(Some(Cond::Z), 0), 3, 12, 16), // 0xCA
+    entry(Illegal(0xCB), 1, 4, 4), // 0xCB
+    entry(Call(Some(Cond::Z), 0), 3, 12, 24), // 0xCC
+    entry(Call(None, 0), 3, 24, 24), // 0xCD
+    entry(Adc(O::Imm(0)), 2, 8, 8), // 0xCE
+    entry(Rst(0x08), 1, 16, 16), // 0xCF
+    entry(Ret(Some(Cond::NC)), 1, 8, 20), // 0xD0
+    entry(Pop(W::DE), 1, 12, 12), // 0xD1
+    entry(Jp(Some(Cond::NC), 0), 3, 12, 16), // 0xD2
+    entry(Illegal(0xD3), 1, 4, 4), // 0xD3
+    entry(Call(Some(Cond::NC), 0), 3, 12, 24), // 0xD4
+    entry(Push(W::DE), 1, 16, 16), // 0xD5
+    entry(Sub(O::Imm(0)), 2, 8, 8), // 0xD6
+    entry(Rst(0x10), 1, 16, 16), // 0xD7
+    entry(Ret(Some(Cond::C)), 1, 8, 20), // 0xD8
+    entry(Reti, 1, 16, 16), // 0xD9
+    entry(Jp(Some(Cond::C), 0), 3, 12, 16), // 0xDA
+    entry(Illegal(0xDB), 1, 4, 4), // 0xDB
+    entry(Call(Some(Cond::C), 0), 3, 12, 24), // 0xDC
+    entry(Illegal(0xDD), 1, 4, 4), // 0xDD
+    entry(Sbc(O::Imm(0)), 2, 8, 8), // 0xDE
+    entry(Rst(0x18), 1, 16, 16), // 0xDF
+    entry(Ld(O::High(0), O::Reg(R::A)), 2, 12, 12), // 0xE0
+    entry(Pop(W::HL), 1, 12, 12), // 0xE1
+    entry(Ld(O::HighC, O::Reg(R::A)), 1, 8, 8), // 0xE2
+    entry(Illegal(0xE3), 1, 4, 4), // 0xE3
+    entry(Illegal(0xE4), 1, 4, 4), // 0xE4
+    entry(Push(W::HL), 1, 16, 16), // 0xE5
+    entry(And(O::Imm(0)), 2, 8, 8), // 0xE6
+    entry(Rst(0x20), 1, 16, 16), // 0xE7
+    entry(AddSp(0), 2, 16, 16), // 0xE8
+    entry(JpHl, 1, 4, 4), // 0xE9
+    entry(Ld(O::Absolute(0), O::Reg(R::A)), 3, 16, 16), // 0xEA
+    entry(Illegal(0xEB), 1, 4, 4), // 0xEB
+    entry(Illegal(0xEC), 1, 4, 4), // 0xEC
+    entry(Illegal(0xED), 1, 4, 4), // 0xED
+    entry(Xor(O::Imm(0)), 2, 8, 8), // 0xEE
+    entry(Rst(0x28), 1, 16, 16), // 0xEF
+    entry(Ld(O::Reg(R::A), O::High(0)), 2, 12, 12), // 0xF0
+    entry(Pop(W::AF), 1, 12, 12), // 0xF1
+    entry(Ld(O::Reg(R::A), O::HighC), 1, 8, 8), // 0xF2
+    entry(Di, 1, 4, 4), // 0xF3
+    entry(Illegal(0xF4), 1, 4, 4), // 0xF4
+    entry(Push(W::AF), 1, 16, 16), // 0xF5
+    entry(Or(O::Imm(0)), 2, 8, 8), // 0xF6
+    entry(Rst(0x30), 1, 16, 16), // 0xF7
+    entry(LdHlSp(0), 2, 12, 12), // 0xF8
+    entry(LdSpHl, 1, 8, 8), // 0xF9
+    entry(Ld(O::Reg(R::A), O::Absolute(0)), 3, 16, 16), // 0xFA
+    entry(Ei, 1, 4, 4), // 0xFB
+    entry(Illegal(0xFC), 1, 4, 4), // 0xFC
+    entry(Illegal(0xFD), 1, 4, 4), // 0xFD
+    entry(Cp(O::Imm(0)), 2, 8, 8), // 0xFE
+    entry(Rst(0x38), 1, 16, 16), // 0xFF
+];
+
+// 0xCBプレフィックス付きの命令は、上位のbitで操作、下位3bitで対象のレジスタが決まる
+const CB_TARGETS: [Operand; 8] = [
+    O::Reg(R::B),
+    O::Reg(R::C),
+    O::Reg(R::D),
+    O::Reg(R::E),
+    O::Reg(R::H),
+    O::Reg(R::L),
+    O::Indirect(W::HL),
+    O::Reg(R::A)
+];
+
+impl Decoded {
+    // 即値を命令に埋め込む。8bitの即値はimmediateの下位8bitを使う
+    pub fn with_immediate(self, immediate: u16) -> Self {
+        let low = immediate as u8;
+        let instruction = match self.instruction {
+            Ld(dest, src) => Ld(dest.with_immediate(immediate), src.with_immediate(immediate)),
+            Ld16(reg, _) => Ld16(reg, immediate),
+            LdAbsoluteSp(_) => LdAbsoluteSp(immediate),
+            LdHlSp(_) => LdHlSp(low as i8),
+            AddSp(_) => AddSp(low as i8),
+            Add(operand) => Add(operand.with_immediate(immediate)),
+            Adc(operand) => Adc(operand.with_immediate(immediate)),
+            Sub(operand) => Sub(operand.with_immediate(immediate)),
+            Sbc(operand) => Sbc(operand.with_immediate(immediate)),
+            And(operand) => And(operand.with_immediate(immediate)),
+            Xor(operand) => Xor(operand.with_immediate(immediate)),
+            Or(operand) => Or(operand.with_immediate(immediate)),
+            Cp(operand) => Cp(operand.with_immediate(immediate)),
+            Jp(cond, _) => Jp(cond, immediate),
+            Jr(cond, _) => Jr(cond, low as i8),
+            Call(cond, _) => Call(cond, immediate),
+            other => other
+        };
+
+        Self {
+            instruction,
+            ..self
+        }
+    }
+
+    // 命令表から引いた、即値がまだ入っていない命令
+    pub fn lookup(opcode: u8) -> Self {
+        OPCODES[opcode as usize]
+    }
+
+    // 0xCBに続くバイトから命令を作る
+    pub fn lookup_cb(opcode: u8) -> Self {
+        let target = CB_TARGETS[(opcode & 0x07) as usize];
+        let bit = (opcode >> 3) & 0x07;
+        let is_hl = target == O::Indirect(W::HL);
+
+        let instruction = match opcode >> 6 {
+            0 => match bit {
+                0 => Rlc(target),
+                1 => Rrc(target),
+                2 => Rl(target),
+                3 => Rr(target),
+                4 => Sla(target),
+                5 => Sra(target),
+                6 => Swap(target),
+                _ => Srl(target)
+            },
+            1 => Bit(bit, target),
+            2 => Res(bit, target),
+            _ => Set(bit, target)
+        };
+
+        // (HL)を対象にする場合は読み書きの分だけ長くなる。BITは書き込まない
+        let cycles = match (instruction, is_hl) {
+            (_, false) => 8,
+            (Bit(..), true) => 12,
+            (_, true) => 16
+        };
+
+        entry(instruction, 2, cycles, cycles)
+    }
+}
+
+// 命令の先頭から最大3byteを受け取ってデコードする
+pub fn decode(bytes: [u8; 3]) -> Decoded {
+    if bytes[0] == 0xCB {
+        return Decoded::lookup_cb(bytes[1]);
+    }
+
+    Decoded::lookup(bytes[0]).with_immediate(u16::from_le_bytes([bytes[1], bytes[2]]))
+}
+
+impl Operand {
+    fn with_immediate(self, immediate: u16) -> Self {
+        match self {
+            O::Imm(_) => O::Imm(immediate as u8),
+            O::High(_) => O::High(immediate as u8),
+            O::Absolute(_) => O::Absolute(immediate),
+            other => other
+        }
+    }
+}
+
+impl Instruction {
+    // ニーモニック(LD, ADDなど)
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Nop => "NOP",
+            Stop => "STOP",
+            Halt => "HALT",
+            Di => "DI",
+            Ei => "EI",
+            Ld(O::High(_), _) | Ld(_, O::High(_)) => "LDH",
+            Ld(..) | Ld16(..) | LdAbsoluteSp(_) | LdHlSp(_) | LdSpHl => "LD",
+            Push(_) => "PUSH",
+            Pop(_) => "POP",
+            Add(_) | AddHl(_) | AddSp(_) => "ADD",
+            Adc(_) => "ADC",
+            Sub(_) => "SUB",
+            Sbc(_) => "SBC",
+            And(_) => "AND",
+            Xor(_) => "XOR",
+            Or(_) => "OR",
+            Cp(_) => "CP",
+            Inc(_) | Inc16(_) => "INC",
+            Dec(_) | Dec16(_) => "DEC",
+            Rlca => "RLCA",
+            Rrca => "RRCA",
+            Rla => "RLA",
+            Rra => "RRA",
+            Daa => "DAA",
+            Cpl => "CPL",
+            Scf => "SCF",
+            Ccf => "CCF",
+            Jp(..) | JpHl => "JP",
+            Jr(..) => "JR",
+            Call(..) => "CALL",
+            Ret(_) => "RET",
+            Reti => "RETI",
+            Rst(_) => "RST",
+            Rlc(_) => "RLC",
+            Rrc(_) => "RRC",
+            Rl(_) => "RL",
+            Rr(_) => "RR",
+            Sla(_) => "SLA",
+            Sra(_) => "SRA",
+            Swap(_) => "SWAP",
+            Srl(_) => "SRL",
+            Bit(..) => "BIT",
+            Res(..) => "RES",
+            Set(..) => "SET",
+            Illegal(_) => "ILLEGAL"
+        }
+    }
+}
+
+impl fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            R::A => "A",
+            R::B => "B",
+            R::C => "C",
+            R::D => "D",
+            R::E => "E",
+            R::H => "H",
+            R::L => "L"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            W::AF => "AF",
+            W::BC => "BC",
+            W::DE => "DE",
+            W::HL => "HL",
+            W::SP => "SP"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Cond::NZ => "NZ",
+            Cond::Z => "Z",
+            Cond::NC => "NC",
+            Cond::C => "C"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            O::Reg(reg) => write!(f, "{}", reg),
+            O::Imm(value) => write!(f, "${:02X}", value),
+            O::Indirect(reg) => write!(f, "({})", reg),
+            O::HlInc => write!(f, "(HL+)"),
+            O::HlDec => write!(f, "(HL-)"),
+            O::Absolute(address) => write!(f, "(${:04X})", address),
+            O::High(offset) => write!(f, "($FF{:02X})", offset),
+            O::HighC => write!(f, "($FF00+C)")
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+        match self {
+            Ld(dest, src) => write!(f, "{} {},{}", mnemonic, dest, src),
+            Ld16(reg, value) => write!(f, "{} {},${:04X}", mnemonic, reg, value),
+            LdAbsoluteSp(address) => write!(f, "{} (${:04X}),SP", mnemonic, address),
+            LdHlSp(offset) => write!(f, "{} HL,SP{:+}", mnemonic, offset),
+            LdSpHl => write!(f, "{} SP,HL", mnemonic),
+            Push(reg) | Pop(reg) | Inc16(reg) | Dec16(reg) => write!(f, "{} {}", mnemonic, reg),
+            Add(operand) | Adc(operand) | Sbc(operand) => write!(f, "{} A,{}", mnemonic, operand),
+            Sub(operand) | And(operand) | Xor(operand) | Or(operand) | Cp(operand) |
+            Inc(operand) | Dec(operand) |
+            Rlc(operand) | Rrc(operand) | Rl(operand) | Rr(operand) |
+            Sla(operand) | Sra(operand) | Swap(operand) | Srl(operand) => write!(f, "{} {}", mnemonic, operand),
+            AddHl(reg) => write!(f, "{} HL,{}", mnemonic, reg),
+            AddSp(offset) => write!(f, "{} SP,{:+}", mnemonic, offset),
+            Jp(Some(cond), address) | Call(Some(cond), address) => write!(f, "{} {},${:04X}", mnemonic, cond, address),
+            Jp(None, address) | Call(None, address) => write!(f, "{} ${:04X}", mnemonic, address),
+            JpHl => write!(f, "{} HL", mnemonic),
+            Jr(Some(cond), offset) => write!(f, "{} {},{:+}", mnemonic, cond, offset),
+            Jr(None, offset) => write!(f, "{} {:+}", mnemonic, offset),
+            Ret(Some(cond)) => write!(f, "{} {}", mnemonic, cond),
+            Rst(vector) => write!(f, "{} ${:02X}", mnemonic, vector),
+            Bit(bit, operand) | Res(bit, operand) | Set(bit, operand) => write!(f, "{} {},{}", mnemonic, bit, operand),
+            Illegal(opcode) => write!(f, "{} ${:02X}", mnemonic, opcode),
+            _ => write!(f, "{}", mnemonic)
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use anyhow::Result;
+
+use crate::state::{StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Button {
     Right,
     Left,
@@ -24,37 +29,6 @@ pub struct Joypad {
     pub int_flag: bool
 }
 
-#[cfg(target_arch = "wasm32")]
-use serde::{Serialize, Deserialize};
-#[cfg(target_arch = "wasm32")]
-#[derive(Serialize, Deserialize)]
-pub struct KeyConfig {
-    pub RIGHT: String,
-    pub LEFT: String,
-    pub UP: String,
-    pub DOWN: String,
-    pub A: String,
-    pub B: String,
-    pub SELECT: String,
-    pub START: String
-}
-
-#[cfg(target_arch = "wasm32")]
-impl KeyConfig {
-    pub fn find_key(&self, key: &str) -> Option<Button> {
-        if self.RIGHT.eq(key) { return Some(Button::Right); }
-        if self.LEFT.eq(key) { return Some(Button::Left); }
-        if self.UP.eq(key) { return Some(Button::Up); }
-        if self.DOWN.eq(key) { return Some(Button::Down); }
-        if self.A.eq(key) { return Some(Button::A); }
-        if self.B.eq(key) { return Some(Button::B); }
-        if self.SELECT.eq(key) { return Some(Button::Select); }
-        if self.START.eq(key) { return Some(Button::Start); }
-
-        None
-    }
-}
-
 impl Joypad {
     pub fn write(&mut self, data: u8) {
         self.p15 = data & (1 << 5) == (1 << 5);
@@ -93,6 +67,12 @@ impl Joypad {
         return 0xFF
     }
 
+    // 選択されている入力線のどれかがLowになっている(ボタンが押されている)
+    // STOPからの復帰に使う
+    pub fn is_line_low(&self) -> bool {
+        (self.read() & 0x0F) != 0x0F
+    }
+
     pub fn press(&mut self, button: Button) {
         self.int_flag = true;
         match button {
@@ -107,6 +87,26 @@ impl Joypad {
         }
     }
 
+    // 押されているボタンを1byteにまとめる。巻き戻しのときに使う
+    pub fn buttons(&self) -> u8 {
+        [self.right, self.left, self.up, self.down, self.a, self.b, self.select, self.start]
+            .iter()
+            .enumerate()
+            .fold(0, |buttons, (i, pressed)| buttons | ((*pressed as u8) << i))
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        let pressed = |i: u8| buttons & (1 << i) != 0;
+        self.right = pressed(0);
+        self.left = pressed(1);
+        self.up = pressed(2);
+        self.down = pressed(3);
+        self.a = pressed(4);
+        self.b = pressed(5);
+        self.select = pressed(6);
+        self.start = pressed(7);
+    }
+
     pub fn release(&mut self, button: Button) {
         match button {
             Button::Right => self.right = false,
@@ -119,4 +119,18 @@ impl Joypad {
             Button::Start => self.start = false
         }
     }
+
+    // ボタンの押下状態はホスト側の入力なので保存しない
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.p15);
+        writer.write_bool(self.p14);
+        writer.write_bool(self.int_flag);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.p15 = reader.read_bool()?;
+        self.p14 = reader.read_bool()?;
+        self.int_flag = reader.read_bool()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,361 @@
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use dasp::{frame::Stereo, ring_buffer};
+
+pub mod rom;
+pub mod mbc;
+pub mod rtc;
+pub mod bus;
+pub mod cpu;
+pub mod instruction;
+pub mod debugger;
+pub mod ppu;
+pub mod joypad;
+pub mod timer;
+pub mod sound;
+pub mod serial;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod link;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gdb;
+pub mod state;
+pub mod save;
+pub mod screenshot;
+pub mod printer;
+pub mod error;
+pub mod trace;
+pub mod rewind;
+
+use bus::Bus;
+use cpu::Cpu;
+use error::{EmuError, EmuResult};
+use joypad::Button;
+use save::{FileStorage, SaveStorage};
+use serial::SerialEndpoint;
+use state::{StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use trace::{TraceConfig, Tracer};
+
+// カートリッジのRAMが書き換えられてから、バッテリーバックアップを書き出すまでのフレーム数(約1秒)
+const SAVE_FLUSH_FRAMES: usize = 60;
+
+// ウィンドウや音声デバイスに依存しないゲームボーイ本体
+// フロントエンドやテスト用のハーネスはこれを通してエミュレータを動かす
+pub struct GameBoy {
+    pub cpu: Cpu,
+    sample_rate: usize,
+    buffer_size: usize,
+    // バッテリーバックアップの保存先。Noneの場合は保存しない
+    save_storage: Option<Box<dyn SaveStorage + Send>>,
+    // RAMが書き換えられてから経過したフレーム数
+    dirty_frames: usize,
+    paused: bool,
+    // 巻き戻しのキーが押されている間は、フレームを進める代わりに巻き戻す
+    rewinding: bool
+}
+
+impl GameBoy {
+    pub fn new<T>(reader: &mut T, sample_rate: usize, buffer_size: usize) -> EmuResult<Self>
+        where T: Read + Seek
+    {
+        let cpu = Self::power_on(reader, sample_rate, buffer_size)?;
+
+        Ok(Self {
+            cpu,
+            sample_rate,
+            buffer_size,
+            save_storage: None,
+            dirty_frames: 0,
+            paused: false,
+            rewinding: false
+        })
+    }
+
+    // カートリッジを差し替えて電源を入れ直す
+    pub fn load_rom<T>(&mut self, reader: &mut T) -> EmuResult<()>
+        where T: Read + Seek
+    {
+        // 差し替える前のカートリッジのセーブデータを書き出しておく
+        self.flush_save_file()?;
+        self.cpu = Self::power_on(reader, self.sample_rate, self.buffer_size)?;
+        self.dirty_frames = 0;
+        self.read_save_file()
+    }
+
+    fn power_on<T>(reader: &mut T, sample_rate: usize, buffer_size: usize) -> EmuResult<Cpu>
+        where T: Read + Seek
+    {
+        let bus = Bus::new(reader, sample_rate, buffer_size)?;
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        Ok(cpu)
+    }
+
+    // 同じカートリッジのまま電源を入れ直す。リンクケーブルの接続先はそのまま残す
+    // CPUがロックした後など、エラーで止まった状態から復帰するのに使う
+    pub fn reset(&mut self) -> EmuResult<()> {
+        self.flush_save_file()?;
+        let endpoint = self.cpu.bus.serial.take_endpoint();
+        let mut reader = Cursor::new(self.cpu.bus.mbc.rom().data.clone());
+        let mut cpu = Self::power_on(&mut reader, self.sample_rate, self.buffer_size)?;
+        cpu.bus.serial.set_endpoint(endpoint);
+        if self.cpu.bus.serial.is_capturing_output() {
+            cpu.bus.serial.capture_output();
+        }
+        // ブレークポイントは残し、コールスタックだけ捨てる
+        cpu.debugger = std::mem::take(&mut self.cpu.debugger);
+        cpu.debugger.clear_call_stack();
+        // CPUがロックして止まっていた場合は、リセットしたら実行を再開する
+        if self.cpu.fault().is_some() {
+            let pc = cpu.registers().pc;
+            cpu.debugger.resume(pc);
+        }
+        if let Some(tracer) = self.cpu.take_tracer() {
+            cpu.start_trace(tracer);
+        }
+        // 巻き戻しの設定は残すが、リセットより前には戻れない
+        cpu.rewind = std::mem::take(&mut self.cpu.rewind);
+        cpu.rewind.clear();
+        cpu.debugger.sync_watcher(&mut cpu.bus.watcher);
+        self.cpu = cpu;
+        self.dirty_frames = 0;
+        self.read_save_file()
+    }
+
+    // 1フレーム(70224サイクル)分だけ実行する。一時停止中とデバッガで止まっている間は何もしない
+    // カートリッジのRAMが書き換えられていれば、一定フレーム後にまとめて書き出す
+    pub fn run_frame(&mut self) -> EmuResult<()> {
+        if self.paused || self.cpu.debugger.is_stopped() {
+            return Ok(());
+        }
+        if self.rewinding {
+            self.cpu.rewind_snapshot()?;
+            return Ok(());
+        }
+
+        self.cpu.run()?;
+
+        if self.cpu.bus.mbc.is_dirty() {
+            self.dirty_frames += 1;
+            if self.dirty_frames >= SAVE_FLUSH_FRAMES {
+                self.flush_save_file()?;
+            }
+        }
+        Ok(())
+    }
+
+    // 一時停止する。止めている間にゲームを終了されてもいいように、ここでも書き出す
+    pub fn pause(&mut self) -> EmuResult<()> {
+        self.paused = true;
+        self.flush_save_file()
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // interval フレームごとに状態を記録し、capacity 個まで巻き戻せるようにする
+    pub fn enable_rewind(&mut self, interval: usize, capacity: usize) {
+        self.cpu.rewind.enable(interval, capacity);
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.cpu.rewind.disable();
+    }
+
+    // trueの間はrun_frameのたびに1つ前の記録まで巻き戻す
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+    }
+
+    // 1つ前の記録まで巻き戻す。それより前がなければfalseを返す
+    pub fn rewind(&mut self) -> EmuResult<bool> {
+        Ok(self.cpu.rewind_snapshot()?)
+    }
+
+    // 命令ごとのCPUの状態をファイルに書き出す。すでに書き出している場合はそちらを閉じる
+    pub fn start_trace<P: AsRef<Path>>(&mut self, path: P, config: TraceConfig) -> EmuResult<()> {
+        self.stop_trace()?;
+        self.cpu.start_trace(Tracer::create(path, config)?);
+        Ok(())
+    }
+
+    // トレースの残りを書き出して閉じる
+    pub fn stop_trace(&mut self) -> EmuResult<()> {
+        Ok(self.cpu.stop_trace()?)
+    }
+
+    // 160x144ピクセルのRGBAデータ
+    pub fn frame_buffer(&self) -> &[[u8; 4]] {
+        self.cpu.bus.ppu.frame_buffer()
+    }
+
+    // 出力待ちの音声サンプル
+    pub fn audio_samples(&mut self) -> &mut ring_buffer::Bounded<Vec<Stereo<f32>>> {
+        self.cpu.bus.sound.get_sound_buffer()
+    }
+
+    // シリアルポートから送信されたバイトを記録し始める
+    pub fn capture_serial_output(&mut self) {
+        self.cpu.bus.serial.capture_output();
+    }
+
+    // capture_serial_outputを呼んでからシリアルポートから送信されたバイト列
+    pub fn serial_output(&self) -> &[u8] {
+        self.cpu.bus.serial.output()
+    }
+
+    // プリンタが保存した印刷結果のパスを取り出す。保存できなかったものはエラー
+    pub fn take_printouts(&mut self) -> Vec<EmuResult<PathBuf>> {
+        self.cpu.bus.serial.take_printouts().into_iter().map(|printout| Ok(printout?)).collect()
+    }
+
+    // リンクケーブルの接続先を設定する
+    pub fn set_serial_endpoint(&mut self, endpoint: Box<dyn SerialEndpoint + Send>) {
+        self.cpu.bus.serial.set_endpoint(endpoint);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        // 巻き戻した後に実行し直すときのために、入力を記録しておく
+        let steps = self.cpu.steps();
+        self.cpu.rewind.record_input(steps, button, pressed);
+        if pressed {
+            self.cpu.bus.joypad.press(button);
+        }
+        else {
+            self.cpu.bus.joypad.release(button);
+        }
+    }
+
+    // バッテリーバックアップの保存先を設定し、既存のセーブデータがあれば読み込む
+    pub fn set_save_storage(&mut self, storage: Box<dyn SaveStorage + Send>) -> EmuResult<()> {
+        self.save_storage = Some(storage);
+        self.read_save_file()
+    }
+
+    // 指定したディレクトリにセーブファイルとして保存する
+    pub fn set_save_dir<P: AsRef<Path>>(&mut self, dir: P) -> EmuResult<()> {
+        self.set_save_storage(Box::new(FileStorage::new(dir)))
+    }
+
+    // 現在のROMのセーブデータの名前
+    pub fn save_file_name(&self) -> String {
+        save::save_file_name(self.cpu.bus.mbc.rom())
+    }
+
+    fn read_save_file(&mut self) -> EmuResult<()> {
+        if !self.cpu.bus.mbc.has_battery() {
+            return Ok(());
+        }
+
+        let name = self.save_file_name();
+        if let Some(storage) = &self.save_storage {
+            let data = storage.read(&name).map_err(|e| EmuError::classify(e, EmuError::SaveIo))?;
+            if let Some(data) = data {
+                self.cpu.bus.mbc.load_save_data(&data).map_err(|e| EmuError::classify(e, EmuError::SaveIo))?;
+            }
+        }
+        self.cpu.bus.mbc.clear_dirty();
+        Ok(())
+    }
+
+    // RAMの内容に関わらずセーブデータを書き出す
+    pub fn write_save_file(&mut self) -> EmuResult<()> {
+        if !self.cpu.bus.mbc.has_battery() {
+            return Ok(());
+        }
+
+        let name = self.save_file_name();
+        if let Some(storage) = &mut self.save_storage {
+            storage.write(&name, &self.cpu.bus.mbc.save_data()).map_err(|e| EmuError::classify(e, EmuError::SaveIo))?;
+        }
+        self.cpu.bus.mbc.clear_dirty();
+        self.dirty_frames = 0;
+        Ok(())
+    }
+
+    // 前回書き出してからRAMが書き換えられている場合だけセーブデータを書き出す
+    pub fn flush_save_file(&mut self) -> EmuResult<()> {
+        if self.cpu.bus.mbc.is_dirty() {
+            self.write_save_file()?;
+        }
+        Ok(())
+    }
+
+    // 現在の状態をセーブステートとして書き出す
+    // 先頭にマジックナンバー、形式のバージョン、ROMのタイトルとチェックサムを付けておく
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        let rom = self.cpu.bus.mbc.rom();
+
+        writer.write_bytes(&STATE_MAGIC);
+        writer.write_u32(STATE_VERSION);
+        writer.write_bytes(&rom.title);
+        writer.write_bytes(&rom.global_check_sum);
+        self.cpu.save_state(&mut writer);
+
+        writer.into_inner()
+    }
+
+    // セーブステートを読み込む
+    // 形式が合わない場合はエラーを返し、エミュレータの状態は読み込み前のまま残す
+    pub fn load_state(&mut self, data: &[u8]) -> EmuResult<()> {
+        self.read_state(data).map_err(|e| EmuError::classify(e, EmuError::InvalidState))
+    }
+
+    fn read_state(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let mut reader = StateReader::new(data);
+
+        let mut magic = [0; 4];
+        reader.read_bytes(&mut magic)?;
+        if magic != STATE_MAGIC {
+            bail!("not a save state file");
+        }
+
+        let version = reader.read_u32()?;
+        if version != STATE_VERSION {
+            bail!("save state version {} is not supported (expected version {})", version, STATE_VERSION);
+        }
+
+        let rom = self.cpu.bus.mbc.rom();
+        let mut title = [0; 0x10];
+        let mut global_check_sum = [0; 2];
+        reader.read_bytes(&mut title)?;
+        reader.read_bytes(&mut global_check_sum)?;
+        if title != rom.title || global_check_sum != rom.global_check_sum {
+            bail!("save state was made with a different ROM");
+        }
+
+        // 途中で失敗した場合に備えて、現在の状態を取っておく
+        let backup = self.save_state();
+        let result = self.cpu.load_state(&mut reader).and_then(|_| {
+            if !reader.is_end() {
+                bail!("save state has trailing data");
+            }
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            self.restore_state(&backup);
+            return Err(e);
+        }
+
+        // 読み込む前の履歴には戻れない
+        self.cpu.rewind.clear();
+        Ok(())
+    }
+
+    fn restore_state(&mut self, backup: &[u8]) {
+        // 直前に自分で書き出したものなので、ヘッダは読み飛ばすだけで失敗しない
+        let mut reader = StateReader::new(backup);
+        let mut header = [0; 4 + 4 + 0x10 + 2];
+        reader.read_bytes(&mut header).unwrap();
+        self.cpu.load_state(&mut reader).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::printer::Printer;
+use crate::serial::SerialEndpoint;
+
+// 相手の応答を待つ時間。これを過ぎたら相手がいないものとして0xFFを受信する
+pub const DEFAULT_LINK_TIMEOUT: Duration = Duration::from_secs(1);
+
+// メッセージは種類とデータの2バイト
+// 内部クロック側が転送を要求し、外部クロック側が自分のSBの内容で応答する
+const MSG_TRANSFER: u8 = 0x01;
+const MSG_REPLY: u8 = 0x02;
+
+// コマンドラインで指定するシリアルポートの接続先
+pub enum LinkConfig {
+    // --link-listen <port>
+    Listen(u16),
+    // --link-connect <host:port>
+    Connect(String),
+    // --printer
+    Printer
+}
+
+impl LinkConfig {
+    // 残りの引数からリンクケーブルの設定を読み取る。指定がなければNone
+    pub fn from_args(args: &[String]) -> Result<Option<Self>> {
+        match args {
+            [] => Ok(None),
+            [option, port] if option == "--link-listen" => {
+                let port = port.parse().with_context(|| format!("invalid port: {}", port))?;
+                Ok(Some(LinkConfig::Listen(port)))
+            },
+            [option, address] if option == "--link-connect" => Ok(Some(LinkConfig::Connect(address.clone()))),
+            [option] if option == "--printer" => Ok(Some(LinkConfig::Printer)),
+            _ => bail!("invalid link options: expected --link-listen <port>, --link-connect <host:port> or --printer")
+        }
+    }
+
+    // 接続先を作る。プリンタの場合はprinter_dirに印刷結果を保存する
+    pub fn connect(&self, printer_dir: &Path) -> Result<Box<dyn SerialEndpoint + Send>> {
+        match self {
+            LinkConfig::Listen(port) => {
+                println!("waiting for link connection on port {}", port);
+                Ok(Box::new(TcpLinkEndpoint::listen(*port, DEFAULT_LINK_TIMEOUT)?))
+            },
+            LinkConfig::Connect(address) => Ok(Box::new(TcpLinkEndpoint::connect(address.as_str(), DEFAULT_LINK_TIMEOUT)?)),
+            LinkConfig::Printer => Ok(Box::new(Printer::new(printer_dir)))
+        }
+    }
+}
+
+// TCPでつながったもう一台のエミュレータとの間のリンクケーブル
+// 内部クロック側は相手の応答が来るまでCPUを止めるので、転送の単位で二台の実行が揃う
+pub struct TcpLinkEndpoint {
+    stream: TcpStream,
+    // 受信スレッドが読み込んだメッセージ
+    messages: Receiver<[u8; 2]>,
+    timeout: Duration
+}
+
+impl TcpLinkEndpoint {
+    // 指定したポートで相手からの接続を待つ
+    pub fn listen(port: u16, timeout: Duration) -> Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).with_context(|| format!("failed to listen on port {}", port))?;
+        let (stream, _) = listener.accept().context("failed to accept link connection")?;
+        Self::from_stream(stream, timeout)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A, timeout: Duration) -> Result<Self> {
+        let stream = TcpStream::connect(address).context("failed to connect to link peer")?;
+        Self::from_stream(stream, timeout)
+    }
+
+    pub fn from_stream(stream: TcpStream, timeout: Duration) -> Result<Self> {
+        stream.set_nodelay(true)?;
+
+        let (sender, messages) = mpsc::channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            let mut message = [0; 2];
+            // 切断されたら受信スレッドを終了する
+            while reader.read_exact(&mut message).is_ok() {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            stream,
+            messages,
+            timeout
+        })
+    }
+
+    fn send(&mut self, kind: u8, data: u8) {
+        // 切断されている場合は相手がいないのと同じ扱いにする
+        let _ = self.stream.write_all(&[kind, data]);
+    }
+}
+
+impl SerialEndpoint for TcpLinkEndpoint {
+    fn exchange(&mut self, data: u8) -> u8 {
+        // タイムアウトした後に届いた古い応答は捨てる
+        self.poll_external(None);
+        self.send(MSG_TRANSFER, data);
+
+        loop {
+            match self.messages.recv_timeout(self.timeout) {
+                Ok([MSG_REPLY, reply]) => return reply,
+                // 相手も内部クロックで転送しようとしている場合は、どちらもクロックを受け取れない
+                Ok([MSG_TRANSFER, _]) => self.send(MSG_REPLY, 0xFF),
+                Ok(_) => {},
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => return 0xFF
+            }
+        }
+    }
+
+    fn poll_external(&mut self, data: Option<u8>) -> Option<u8> {
+        while let Ok(message) = self.messages.try_recv() {
+            if let [MSG_TRANSFER, received] = message {
+                match data {
+                    // 外部クロックで待っている場合は転送を完了させる
+                    Some(data) => {
+                        self.send(MSG_REPLY, data);
+                        return Some(received);
+                    },
+                    // 転送の準備ができていない場合は何もつながっていないのと同じ応答を返す
+                    None => self.send(MSG_REPLY, 0xFF)
+                }
+            }
+        }
+        None
+    }
+}
@@ -1,35 +1,36 @@
-use std::fs::File;
-use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use std::{env, thread};
-use std::io::BufReader;
-use std::time::{Duration, Instant};
-use std::future::Future;
 
 use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
-use cpal::{self, StreamError, SampleFormat, OutputCallbackInfo, Stream};
+use cpal::{self, StreamError, SampleFormat};
 use dasp::frame::Stereo;
-use dasp::ring_buffer::Bounded;
-use dasp::{Frame, Sample, Signal};
+use dasp::Frame;
 
-use dotenvy::dotenv;
-
-use winit::event::{Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode};
+use winit::event::{Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode, ModifiersState};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit::dpi::LogicalSize;
 use pixels::{Pixels, SurfaceTexture};
 
-mod rom;
-mod mbc;
-mod bus;
-mod cpu;
-mod ppu;
-mod joypad;
-mod timer;
-mod sound;
+use game_boy_rust::GameBoy;
+use game_boy_rust::error::EmuResult;
+#[cfg(not(target_arch = "wasm32"))]
+use game_boy_rust::error::EmuError;
+#[cfg(not(target_arch = "wasm32"))]
+use game_boy_rust::save;
+#[cfg(not(target_arch = "wasm32"))]
+use game_boy_rust::link::LinkConfig;
+#[cfg(not(target_arch = "wasm32"))]
+use game_boy_rust::debugger;
+#[cfg(not(target_arch = "wasm32"))]
+use game_boy_rust::gdb::{self, GdbServer};
+#[cfg(not(target_arch = "wasm32"))]
+use game_boy_rust::trace::TraceConfig;
+use game_boy_rust::rewind;
+#[cfg(target_arch = "wasm32")]
+use game_boy_rust::save::SaveStorage;
+use game_boy_rust::joypad::Button;
 
-use joypad::{Button};
+const WINDOW_TITLE: &str = "My Game Boy";
 
 fn main() {
     #[cfg(target_arch = "wasm32")]
@@ -46,17 +47,74 @@ fn main() {
 }
 
 #[cfg(target_arch = "wasm32")]
-async fn web_run() {
-    use log::{Level, info};
-    use wasm_bindgen::prelude::*;
+use serde::{Serialize, Deserialize};
+#[cfg(target_arch = "wasm32")]
+#[derive(Serialize, Deserialize)]
+pub struct KeyConfig {
+    pub RIGHT: String,
+    pub LEFT: String,
+    pub UP: String,
+    pub DOWN: String,
+    pub A: String,
+    pub B: String,
+    pub SELECT: String,
+    pub START: String
+}
+
+#[cfg(target_arch = "wasm32")]
+impl KeyConfig {
+    pub fn find_key(&self, key: &str) -> Option<Button> {
+        if self.RIGHT.eq(key) { return Some(Button::Right); }
+        if self.LEFT.eq(key) { return Some(Button::Left); }
+        if self.UP.eq(key) { return Some(Button::Up); }
+        if self.DOWN.eq(key) { return Some(Button::Down); }
+        if self.A.eq(key) { return Some(Button::A); }
+        if self.B.eq(key) { return Some(Button::B); }
+        if self.SELECT.eq(key) { return Some(Button::Select); }
+        if self.START.eq(key) { return Some(Button::Start); }
+
+        None
+    }
+}
 
+// バッテリーバックアップをLocalStorageにbase64で保存する
+// web_sys::StorageはSendではないので、使うたびに取得する
+#[cfg(target_arch = "wasm32")]
+struct LocalStorageSave;
+
+#[cfg(target_arch = "wasm32")]
+impl LocalStorageSave {
+    fn storage() -> anyhow::Result<web_sys::Storage> {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .ok_or_else(|| anyhow::anyhow!("LocalStorage is not available"))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl SaveStorage for LocalStorageSave {
+    fn read(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let key = format!("save:{}", name);
+        match Self::storage()?.get_item(&key).map_err(|e| anyhow::anyhow!("failed to read {}: {:?}", key, e))? {
+            Some(res) => Ok(Some(base64::decode(res)?)),
+            None => Ok(None)
+        }
+    }
+
+    fn write(&mut self, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let key = format!("save:{}", name);
+        Self::storage()?
+            .set_item(&key, &base64::encode(data))
+            .map_err(|e| anyhow::anyhow!("failed to write {}: {:?}", key, e))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn web_run() {
+    use std::rc::Rc;
     use std::io::Cursor;
-    use gloo::storage::LocalStorage;
-    use gloo_storage::Storage;
-    use gloo_storage::errors::StorageError;
-    use base64::decode;
-    use serde::{Serialize,Serializer};
-    use joypad::KeyConfig;
+    use wasm_bindgen::prelude::*;
+    use base64::{decode, encode};
     use web_sys::console;
 
     // LocalStorageからROMデータを読み出す
@@ -99,19 +157,30 @@ async fn web_run() {
     let config = device.default_output_config().unwrap();
     let sample_rate = config.sample_rate().0 as usize;
 
-    // cpu作成
-    let bus = bus::Bus::new(&mut reader, sample_rate, 4000);
-    let cpu = Arc::new(Mutex::new(cpu::Cpu::new(bus)));
+    // ゲームボーイ本体を作成
+    let mut game_boy = match GameBoy::new(&mut reader, sample_rate, 4000) {
+        Ok(game_boy) => game_boy,
+        Err(e) => {
+            log::error!("failed to load ROM: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = game_boy.set_save_storage(Box::new(LocalStorageSave)) {
+        log::error!("failed to read save data: {}", e);
+    }
+    // Backspaceを押している間は巻き戻す
+    game_boy.enable_rewind(rewind::DEFAULT_INTERVAL, rewind::DEFAULT_CAPACITY);
+    let game_boy = Arc::new(Mutex::new(game_boy));
 
     // GUI生成
     let event_loop = EventLoop::new();
     let window_ = WindowBuilder::new()
-        .with_title("My Game Boy")
+        .with_title(WINDOW_TITLE)
         .with_inner_size(LogicalSize::new(160, 144))
         .with_min_inner_size(LogicalSize::new(160, 144))
         .build(&event_loop)
         .unwrap();
-    
+
     let window = Rc::new(window_);
 
     // Canvasをhtmlのbodyにappendする
@@ -167,33 +236,36 @@ async fn web_run() {
     };
 
     // 音声設定
-    let cpu_sound = cpu.clone();
+    let game_boy_sound = game_boy.clone();
     let channels = config.channels() as usize;
     let err_fn = |err: StreamError| eprintln!("an error occured in sound stream: {}", err);
     let stream = match config.sample_format() {
-        SampleFormat::F32 => device.build_output_stream(&config.into(), move |data: &mut [f32], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &cpu_sound) }, err_fn),
-        SampleFormat::I16 => device.build_output_stream(&config.into(), move |data: &mut [i16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &cpu_sound) }, err_fn),
-        SampleFormat::U16 => device.build_output_stream(&config.into(), move |data: &mut [u16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &cpu_sound) }, err_fn)
+        SampleFormat::F32 => device.build_output_stream(&config.into(), move |data: &mut [f32], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &game_boy_sound) }, err_fn),
+        SampleFormat::I16 => device.build_output_stream(&config.into(), move |data: &mut [i16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &game_boy_sound) }, err_fn),
+        SampleFormat::U16 => device.build_output_stream(&config.into(), move |data: &mut [u16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &game_boy_sound) }, err_fn)
     }.unwrap();
     console::log_1(&JsValue::from_f64(channels as f64));
 
     // cpuの実行
-    let cpu_a = cpu.clone();
-    wasm_bindgen_futures::spawn_local(run_cpu(cpu_a));
+    let game_boy_cpu = game_boy.clone();
+    wasm_bindgen_futures::spawn_local(run_cpu(game_boy_cpu));
 
     // 音声再生の開始
     stream.play().unwrap();
 
+    let mut modifiers = ModifiersState::empty();
     let mut current_time = instant::Instant::now();
     // 画面描画ループ
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => {
-                    // cpu.lock().unwrap().bus.mbc.write_save_file().unwrap();
+                    if let Err(e) = game_boy.lock().unwrap().flush_save_file() {
+                        eprintln!("failed to write save data: {}", e);
+                    }
                     *control_flow = ControlFlow::Exit
                 },
-                WindowEvent::KeyboardInput { 
+                WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
                             virtual_keycode: Some(virtual_code),
@@ -202,62 +274,65 @@ async fn web_run() {
                         },
                     ..
                 } => {
-                    let key_code_input: String = virtual_code.serialize(serde_json::value::Serializer).unwrap().to_string();
-                    let input_len = key_code_input.len();
+                    let pressed = button_state == ElementState::Pressed;
 
-                    if let Some(button) = key_config.find_key(&key_code_input[1..input_len-1]) {
-                        match button {
-                            Button::A => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::A),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::A)
-                                }
-                            },
-                            Button::B => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::B),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::B)
-                                }
-                            },
-                            Button::Down => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Down),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Down)
-                                }
-                            },
-                            Button::Left => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Left),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Left)
-                                }
-                            },
-                            Button::Right => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Right),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Right)
-                                }
-                            },
-                            Button::Select => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Select),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Select)
-                                }
-                            },
-                            Button::Start => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Start),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Start)
-                                }
-                            },
-                            Button::Up => {
-                                match button_state {
-                                    ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Up),
-                                    ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Up)
+                    if virtual_code == VirtualKeyCode::P {
+                        if pressed {
+                            toggle_pause(&mut game_boy.lock().unwrap());
+                        }
+                        return;
+                    }
+
+                    if virtual_code == VirtualKeyCode::Back {
+                        game_boy.lock().unwrap().set_rewinding(pressed);
+                        return;
+                    }
+
+                    if virtual_code == VirtualKeyCode::R {
+                        if pressed {
+                            match reset(&mut game_boy.lock().unwrap()) {
+                                Ok(_) => log::info!("reset"),
+                                Err(e) => log::error!("failed to reset: {}", e)
+                            }
+                        }
+                        return;
+                    }
+
+                    // 数字キーでLocalStorageのスロットからロード、Shift+数字キーでセーブ
+                    if let Some(slot) = key_to_slot(virtual_code) {
+                        if pressed {
+                            let key = format!("state{}", slot);
+                            if modifiers.shift() {
+                                let data = game_boy.lock().unwrap().save_state();
+                                local_storage.set_item(&key, &encode(data)).unwrap();
+                                log::info!("saved state to slot {}", slot);
+                            }
+                            else {
+                                let result = match local_storage.get_item(&key).unwrap() {
+                                    Some(res) => decode(res)
+                                        .map_err(anyhow::Error::from)
+                                        .and_then(|data| game_boy.lock().unwrap().load_state(&data).map_err(anyhow::Error::from)),
+                                    None => Err(anyhow::anyhow!("slot {} is empty", slot))
+                                };
+                                match result {
+                                    Ok(_) => log::info!("loaded state from slot {}", slot),
+                                    Err(e) => log::error!("failed to load state: {}", e)
                                 }
                             }
                         }
+                        return;
+                    }
+
+                    let key_code_input: String = virtual_code.serialize(serde_json::value::Serializer).unwrap().to_string();
+                    let input_len = key_code_input.len();
+
+                    if let Some(button) = key_config.find_key(&key_code_input[1..input_len-1]) {
+                        game_boy.lock().unwrap().set_button(button, pressed);
                     }
                 },
+                WindowEvent::ModifiersChanged(state) => {
+                    modifiers = state;
+                },
                 WindowEvent::Resized(size) => {
                     pixels.resize_surface(size.width, size.height);
                 },
@@ -266,13 +341,13 @@ async fn web_run() {
             Event::MainEventsCleared => {
                 let duration = current_time.elapsed().as_micros();
                 let frame_microsec: u128 = 1_000_000 / 60;
-                if duration >= frame_microsec && cpu.lock().unwrap().sleep {
+                if duration >= frame_microsec && game_boy.lock().unwrap().cpu.sleep {
                     current_time = instant::Instant::now();
-                    window.request_redraw();    
+                    window.request_redraw();
                 }
             }
             Event::RedrawRequested(_) => {
-                cpu.lock().unwrap().render(pixels.get_frame());
+                draw(&game_boy.lock().unwrap(), pixels.get_frame());
                 if pixels.render().is_err() {
                     *control_flow = ControlFlow::Exit;
                     return;
@@ -285,15 +360,17 @@ async fn web_run() {
 }
 
 #[cfg(target_arch = "wasm32")]
-async fn run_cpu(cpu: Arc<Mutex<cpu::Cpu>>) {
-    cpu.lock().unwrap().reset();
-    cpu.lock().unwrap().bus.mbc.read_save_file().unwrap();
+async fn run_cpu(game_boy: Arc<Mutex<GameBoy>>) {
     loop {
         let start = instant::Instant::now();
-        cpu.lock().unwrap().run().unwrap();
+        let result = game_boy.lock().unwrap().run_frame();
+        if let Err(e) = result {
+            log::error!("emulation stopped: {} (press R to reset)", e);
+            stop_on_error(&mut game_boy.lock().unwrap());
+        }
         let duration = start.elapsed().as_micros();
         let frame_microsec: u128 = 1_000_000 / 60;
-        
+
         if duration < frame_microsec {
             let wait_time: u128 = frame_microsec - duration;
             sleep((wait_time / 1000) as i32).await;
@@ -302,7 +379,7 @@ async fn run_cpu(cpu: Arc<Mutex<cpu::Cpu>>) {
 }
 
 #[cfg(target_arch = "wasm32")]
-fn sleep(ms: i32) -> impl Future {
+fn sleep(ms: i32) -> impl std::future::Future {
     let p = js_sys::Promise::new(&mut |resolve, _| {
         web_sys::window()
             .unwrap()
@@ -312,63 +389,146 @@ fn sleep(ms: i32) -> impl Future {
     wasm_bindgen_futures::JsFuture::from(p)
 }
 
-#[cfg(target_arch = "wasm32")]
-fn write_data<T>(output: &mut [T], channels: usize, cpu_sound: &Arc<Mutex<cpu::Cpu>>) 
-where T: cpal::Sample
-{
-    for frame in output.chunks_mut(channels) {
-        let value: [T; 2] = match cpu_sound.lock().unwrap().bus.sound.get_sound_buffer().pop() {
-            Some(res) => res.map(|e| cpal::Sample::from::<f32>(&e)),
-            None => Stereo::EQUILIBRIUM.map(|e| cpal::Sample::from::<f32>(&e)),
-        };
-
-        frame.copy_from_slice(&value);
-    }
-}
-
 #[cfg(not(target_arch = "wasm32"))]
 fn run() {
+    use std::fs::File;
+    use std::{env, thread};
+    use std::io::BufReader;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+    use dotenvy::dotenv;
+
     dotenv().ok();
     let args: Vec<String> = env::args().collect();
     let rom_name = &args[1];
     let base_path = env::var("BASE_PATH").unwrap_or("".to_string());
 
-    let event_loop = EventLoop::new();
+    let event_loop = EventLoop::<EmuError>::with_user_event();
     let window = WindowBuilder::new()
-        .with_title("My Game Boy")
+        .with_title(WINDOW_TITLE)
         .with_inner_size(LogicalSize::new(160, 144))
         .with_min_inner_size(LogicalSize::new(160, 144))
         .build(&event_loop)
         .unwrap();
-    
+
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
         Pixels::new(160, 144, surface_texture).expect("Pixels error")
     };
 
-    let file_path = base_path + rom_name;
-    let mut reader = BufReader::new(File::open(file_path).unwrap());
+    let file_path = PathBuf::from(base_path + rom_name);
+    let mut reader = match File::open(&file_path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("failed to open {}: {}", file_path.display(), e);
+            std::process::exit(1);
+        }
+    };
 
     let host = cpal::default_host();
     let device = host.default_output_device().expect("failed to find a default output device");
     let config = device.default_output_config().unwrap();
     let sample_rate = config.sample_rate().0 as usize;
 
-    let bus = bus::Bus::new(&mut reader, sample_rate, 2000);
-    let cpu = Arc::new(Mutex::new(cpu::Cpu::new(bus)));
-    
+    // バッテリーバックアップはROMごとにSAVE_DIR(デフォルトはsaves)以下に保存する
+    let save_dir = env::var("SAVE_DIR").unwrap_or(save::DEFAULT_SAVE_DIR.to_string());
+    let mut game_boy = match GameBoy::new(&mut reader, sample_rate, 2000) {
+        Ok(game_boy) => game_boy,
+        Err(e) => {
+            eprintln!("failed to load {}: {}", file_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = game_boy.set_save_dir(&save_dir) {
+        eprintln!("failed to read save data: {}", e);
+    }
+    // Backspaceを押している間は巻き戻す
+    game_boy.enable_rewind(rewind::DEFAULT_INTERVAL, rewind::DEFAULT_CAPACITY);
+
+    // GDBの接続を待つポート(--gdb <port>)。指定した場合はGDBから再開するまで止めておく
+    let mut options = args[2..].to_vec();
+    let gdb_port = match gdb::take_port_option(&mut options) {
+        Ok(port) => port,
+        Err(e) => {
+            eprintln!("invalid gdb option: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if gdb_port.is_some() {
+        game_boy.cpu.debugger.stop();
+    }
+
+    // 実行した命令のトレース(--trace <file> [--trace-start <addr>] [--trace-stop <addr>] [--trace-frames <first>-<last>])
+    let trace = match TraceConfig::take_options(&mut options) {
+        Ok(trace) => trace,
+        Err(e) => {
+            eprintln!("invalid trace option: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some((path, config)) = trace {
+        if let Err(e) = game_boy.start_trace(&path, config) {
+            eprintln!("failed to start trace: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // リンクケーブル(--link-listen <port> / --link-connect <host:port>)かプリンタ(--printer)
+    // プリンタの印刷結果はセーブデータと同じ場所に保存する
+    let link = match LinkConfig::from_args(&options) {
+        Ok(link) => link,
+        Err(e) => {
+            eprintln!("invalid link option: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(link) = link {
+        match link.connect(Path::new(&save_dir)) {
+            Ok(endpoint) => game_boy.set_serial_endpoint(endpoint),
+            Err(e) => {
+                eprintln!("failed to connect the link cable: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let game_boy = Arc::new(Mutex::new(game_boy));
+
+    // 標準入力から操作するデバッガ。Mキーで止めてからコマンドを入力する
+    debugger::spawn(game_boy.clone());
+    if let Some(port) = gdb_port {
+        match GdbServer::bind(port) {
+            Ok(server) => {
+                println!("waiting for gdb connection on port {}", port);
+                server.spawn(game_boy.clone());
+            },
+            Err(e) => {
+                eprintln!("failed to listen for gdb on port {}: {}", port, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     {
-        let cpu = cpu.clone();
-        cpu.lock().unwrap().reset();
-        cpu.lock().unwrap().bus.mbc.read_save_file().unwrap();
+        let game_boy = game_boy.clone();
+        let proxy = event_loop.create_proxy();
 
         thread::spawn(move || loop {
             let start = Instant::now();
-            cpu.lock().unwrap().run().unwrap();
+            let (result, printouts) = {
+                let mut game_boy = game_boy.lock().unwrap();
+                (game_boy.run_frame(), game_boy.take_printouts())
+            };
+            report_printouts(printouts);
+            // エラーで止まっても画面の更新と入力は続け、ウィンドウにエラーを表示する
+            if let Err(e) = result {
+                eprintln!("emulation stopped: {}", e);
+                stop_on_error(&mut game_boy.lock().unwrap());
+                let _ = proxy.send_event(e);
+            }
             let duration = start.elapsed().as_micros();
             let frame_microsec: u128 = 1_000_000 / 60;
-            
+
             if duration < frame_microsec {
                 let wait_time: u128 = frame_microsec - duration;
                 thread::sleep(Duration::from_micros(wait_time as u64));
@@ -377,27 +537,34 @@ fn run() {
     }
 
     // 音声
-    let cpu_sound = cpu.clone();
+    let game_boy_sound = game_boy.clone();
     let channels = config.channels() as usize;
     let err_fn = |err: StreamError| eprintln!("an error occured in sound stream: {}", err);
     let stream = match config.sample_format() {
-        SampleFormat::F32 => device.build_output_stream(&config.into(), move |data: &mut [f32], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &cpu_sound) }, err_fn),
-        SampleFormat::I16 => device.build_output_stream(&config.into(), move |data: &mut [i16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &cpu_sound) }, err_fn),
-        SampleFormat::U16 => device.build_output_stream(&config.into(), move |data: &mut [u16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &cpu_sound) }, err_fn)
+        SampleFormat::F32 => device.build_output_stream(&config.into(), move |data: &mut [f32], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &game_boy_sound) }, err_fn),
+        SampleFormat::I16 => device.build_output_stream(&config.into(), move |data: &mut [i16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &game_boy_sound) }, err_fn),
+        SampleFormat::U16 => device.build_output_stream(&config.into(), move |data: &mut [u16], _: &cpal::OutputCallbackInfo| { write_data(data, channels, &game_boy_sound) }, err_fn)
     }.unwrap();
 
     stream.play().unwrap();
 
+    let mut modifiers = ModifiersState::empty();
     let mut current_time = Instant::now();
     // 画面描画
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => {
-                    cpu.lock().unwrap().bus.mbc.write_save_file().unwrap();
+                    let mut game_boy = game_boy.lock().unwrap();
+                    if let Err(e) = game_boy.flush_save_file() {
+                        eprintln!("failed to write save data: {}", e);
+                    }
+                    if let Err(e) = game_boy.stop_trace() {
+                        eprintln!("failed to write trace: {}", e);
+                    }
                     *control_flow = ControlFlow::Exit
                 },
-                WindowEvent::KeyboardInput { 
+                WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
                             virtual_keycode: Some(virtual_code),
@@ -405,72 +572,69 @@ fn run() {
                             ..
                         },
                     ..
-                } => match virtual_code {
-                    VirtualKeyCode::E => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Up),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Up)
-                        }
-                    },
-                    VirtualKeyCode::D => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Down),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Down)
-                        }
-                    }
-                    VirtualKeyCode::S => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Left),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Left)
-                        }
-                    }
-                    VirtualKeyCode::F => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Right),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Right)
-                        }
-                    },
-                    VirtualKeyCode::J => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::B),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::B)
-                        }
-                    },
-                    VirtualKeyCode::K => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::A),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::A)
-                        }
-                    },
-                    VirtualKeyCode::Space => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Select),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Select)
-                        }
-                    },
-                    VirtualKeyCode::Return => {
-                        match button_state {
-                            ElementState::Pressed => cpu.lock().unwrap().bus.joypad.press(Button::Start),
-                            ElementState::Released => cpu.lock().unwrap().bus.joypad.release(Button::Start)
-                        }
-                    },
-                    VirtualKeyCode::N => {
-                        match button_state {
-                            ElementState::Pressed => {
-                                cpu.lock().unwrap().debug_flag ^= true;
-                            },
-                            ElementState::Released => {}
-                        }
-                    },
-                    VirtualKeyCode::M => {
-                        match button_state {
-                            ElementState::Pressed => {
-                                cpu.lock().unwrap().step_flag ^= true;
-                            },
-                            ElementState::Released => {}
+                } => {
+                    let pressed = button_state == ElementState::Pressed;
+                    match virtual_code {
+                        VirtualKeyCode::N => {
+                            if pressed {
+                                game_boy.lock().unwrap().cpu.debug_flag ^= true;
+                            }
+                        },
+                        VirtualKeyCode::M => {
+                            if pressed {
+                                game_boy.lock().unwrap().cpu.debugger.request_break();
+                            }
+                        },
+                        VirtualKeyCode::P => {
+                            if pressed {
+                                toggle_pause(&mut game_boy.lock().unwrap());
+                            }
+                        },
+                        // 押している間は巻き戻す
+                        VirtualKeyCode::Back => {
+                            game_boy.lock().unwrap().set_rewinding(pressed);
+                        },
+                        VirtualKeyCode::R => {
+                            if pressed {
+                                match reset(&mut game_boy.lock().unwrap()) {
+                                    Ok(_) => window.set_title(WINDOW_TITLE),
+                                    Err(e) => {
+                                        eprintln!("failed to reset: {}", e);
+                                        window.set_title(&error_title(&e));
+                                    }
+                                }
+                            }
+                        },
+                        _ => {
+                            // 数字キーでスロットからロード、Shift+数字キーでセーブ
+                            if let Some(slot) = key_to_slot(virtual_code) {
+                                if pressed {
+                                    let path = file_path.with_extension(format!("ss{}", slot));
+                                    if modifiers.shift() {
+                                        match std::fs::write(&path, game_boy.lock().unwrap().save_state()) {
+                                            Ok(_) => println!("saved state to {}", path.display()),
+                                            Err(e) => eprintln!("failed to save state to {}: {}", path.display(), e)
+                                        }
+                                    }
+                                    else {
+                                        let result = std::fs::read(&path)
+                                            .map_err(|e| EmuError::SaveIo(e.to_string()))
+                                            .and_then(|data| game_boy.lock().unwrap().load_state(&data));
+                                        match result {
+                                            Ok(_) => println!("loaded state from {}", path.display()),
+                                            Err(e) => eprintln!("failed to load state from {}: {}", path.display(), e)
+                                        }
+                                    }
+                                }
+                            }
+                            else if let Some(button) = key_to_button(virtual_code) {
+                                game_boy.lock().unwrap().set_button(button, pressed);
+                            }
                         }
                     }
-                    _ => {}
+                },
+                WindowEvent::ModifiersChanged(state) => {
+                    modifiers = state;
                 },
                 WindowEvent::Resized(size) => {
                     pixels.resize_surface(size.width, size.height);
@@ -480,13 +644,16 @@ fn run() {
             Event::MainEventsCleared => {
                 let duration = current_time.elapsed().as_micros();
                 let frame_microsec: u128 = 1_000_000 / 60;
-                if duration >= frame_microsec && cpu.lock().unwrap().sleep {
+                if duration >= frame_microsec && game_boy.lock().unwrap().cpu.sleep {
                     current_time = Instant::now();
-                    window.request_redraw();    
+                    window.request_redraw();
                 }
+            },
+            Event::UserEvent(error) => {
+                window.set_title(&error_title(&error));
             }
             Event::RedrawRequested(_) => {
-                cpu.lock().unwrap().render(pixels.get_frame());
+                draw(&game_boy.lock().unwrap(), pixels.get_frame());
                 if pixels.render().is_err() {
                     *control_flow = ControlFlow::Exit;
                     return;
@@ -498,12 +665,91 @@ fn run() {
     })
 }
 
+// キーボードとゲームボーイのボタンの対応
+#[cfg(not(target_arch = "wasm32"))]
+fn key_to_button(key: VirtualKeyCode) -> Option<Button> {
+    match key {
+        VirtualKeyCode::E => Some(Button::Up),
+        VirtualKeyCode::D => Some(Button::Down),
+        VirtualKeyCode::S => Some(Button::Left),
+        VirtualKeyCode::F => Some(Button::Right),
+        VirtualKeyCode::J => Some(Button::B),
+        VirtualKeyCode::K => Some(Button::A),
+        VirtualKeyCode::Space => Some(Button::Select),
+        VirtualKeyCode::Return => Some(Button::Start),
+        _ => None
+    }
+}
+
+// エラーで止まったときのウィンドウのタイトル
 #[cfg(not(target_arch = "wasm32"))]
-fn write_data<T>(output: &mut [T], channels: usize, cpu_sound: &Arc<Mutex<cpu::Cpu>>) 
+fn error_title(error: &EmuError) -> String {
+    format!("{} - {} (R: reset)", WINDOW_TITLE, error)
+}
+
+// プリンタの印刷結果を知らせる
+#[cfg(not(target_arch = "wasm32"))]
+fn report_printouts(printouts: Vec<EmuResult<std::path::PathBuf>>) {
+    for printout in printouts {
+        match printout {
+            Ok(path) => println!("printed to {}", path.display()),
+            Err(e) => eprintln!("failed to save printout: {}", e)
+        }
+    }
+}
+
+// エラーで止まったら一時停止する。Rキーでリセットされるまでそのまま待つ
+fn stop_on_error(game_boy: &mut GameBoy) {
+    if let Err(e) = game_boy.pause() {
+        eprintln!("failed to write save data: {}", e);
+    }
+}
+
+// 電源を入れ直して実行を再開する
+fn reset(game_boy: &mut GameBoy) -> EmuResult<()> {
+    game_boy.reset()?;
+    game_boy.resume();
+    Ok(())
+}
+
+// 一時停止を切り替える。一時停止するときにバッテリーバックアップを書き出す
+fn toggle_pause(game_boy: &mut GameBoy) {
+    if game_boy.is_paused() {
+        game_boy.resume();
+    }
+    else if let Err(e) = game_boy.pause() {
+        eprintln!("failed to write save data: {}", e);
+    }
+}
+
+// セーブステートのスロット番号(1-9)
+fn key_to_slot(key: VirtualKeyCode) -> Option<u8> {
+    match key {
+        VirtualKeyCode::Key1 => Some(1),
+        VirtualKeyCode::Key2 => Some(2),
+        VirtualKeyCode::Key3 => Some(3),
+        VirtualKeyCode::Key4 => Some(4),
+        VirtualKeyCode::Key5 => Some(5),
+        VirtualKeyCode::Key6 => Some(6),
+        VirtualKeyCode::Key7 => Some(7),
+        VirtualKeyCode::Key8 => Some(8),
+        VirtualKeyCode::Key9 => Some(9),
+        _ => None
+    }
+}
+
+// フレームバッファの内容をウィンドウに書き込む
+fn draw(game_boy: &GameBoy, frame: &mut [u8]) {
+    for (pixel, pixel_data) in frame.chunks_exact_mut(4).zip(game_boy.frame_buffer()) {
+        pixel.copy_from_slice(pixel_data);
+    }
+}
+
+fn write_data<T>(output: &mut [T], channels: usize, game_boy: &Arc<Mutex<GameBoy>>)
 where T: cpal::Sample
 {
     for frame in output.chunks_mut(channels) {
-        let value: [T; 2] = match cpu_sound.lock().unwrap().bus.sound.get_sound_buffer().pop() {
+        let value: [T; 2] = match game_boy.lock().unwrap().audio_samples().pop() {
             Some(res) => res.map(|e| cpal::Sample::from::<f32>(&e)),
             None => Stereo::EQUILIBRIUM.map(|e| cpal::Sample::from::<f32>(&e)),
         };
@@ -1,10 +1,12 @@
-use std::{fs::File, io::{BufReader, Read, self, Write}};
-
 use anyhow::{Result, bail};
 
-use crate::rom::Rom;
+use crate::rom::{CartridgeType, Rom};
+use crate::error::EmuError;
+use crate::rtc::{self, Rtc};
+use crate::state::{StateReader, StateWriter};
 
 pub trait Mbc {
+    fn rom(&self) -> &Rom;
     fn read_rom(&self, address: u16) -> Result<u8>;
     fn read_ram(&self, address: u16) -> Result<u8> {
         Ok(0)
@@ -15,115 +17,237 @@ pub trait Mbc {
     fn write_registers(&mut self, address: u16, data: u8) -> Result<()> {
         Ok(())
     }
-    fn read_save_file(&mut self) -> Result<()> {
+    // バッテリーバックアップされたRAMを持つかどうか
+    fn has_battery(&self) -> bool {
+        false
+    }
+    // セーブファイルとして書き出すRAMの内容
+    fn save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    // セーブファイルの内容をRAMに読み込む
+    fn load_save_data(&mut self, _data: &[u8]) -> Result<()> {
         Ok(())
     }
-    fn write_save_file(&mut self) -> Result<()> {
+    // 前回セーブファイルに書き出してからRAMが書き換えられたかどうか
+    fn is_dirty(&self) -> bool {
+        false
+    }
+    fn clear_dirty(&mut self) {
+    }
+    // カートリッジ側をサイクル分動かす(MBC3のRTCなど)
+    fn tick(&mut self, _cycles: u8) {
+    }
+    // バンクレジスタや外部RAMなど、カートリッジ側の状態をセーブステートに書き出す
+    fn save_state(&self, _writer: &mut StateWriter) {
+    }
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<()> {
         Ok(())
     }
 }
 
 pub struct NoMbc {
-    pub mbc_type: u8,
+    pub mbc_type: CartridgeType,
     pub rom: Rom
 }
 
 pub struct Mbc1 {
-    pub mbc_type: u8,
+    pub mbc_type: CartridgeType,
     pub rom: Rom,
-    pub rom_size: u8,
-    pub ram_size: u8,
     pub is_external_ram_enable: bool,
     pub rom_bank_number: u8,
     pub ram_bank_number: u8,
     pub mode_flag: bool,
-    pub ram: [u8; 0x8000]
+    pub ram: Vec<u8>,
+    // バッテリーバックアップの書き出しが必要かどうか
+    pub dirty: bool
 }
 
 pub struct Mbc5 {
-    pub mbc_type: u8,
+    pub mbc_type: CartridgeType,
     pub rom: Rom,
     pub is_external_ram_enable: bool,
     pub rom_bank_number_low: u8,
     pub rom_bank_number_high: bool,
     pub ram_bank_number: u8,
-    pub ram: [u8; 0x20000]
+    pub ram: Vec<u8>,
+    // バッテリーバックアップの書き出しが必要かどうか
+    pub dirty: bool
+}
+
+pub struct Mbc2 {
+    pub mbc_type: CartridgeType,
+    pub rom: Rom,
+    pub is_external_ram_enable: bool,
+    pub rom_bank_number: u8,
+    // 512x4bitのRAMを内蔵している。下位4bitだけを使う
+    pub ram: Vec<u8>,
+    pub dirty: bool
+}
+
+pub struct Mbc3 {
+    pub mbc_type: CartridgeType,
+    pub rom: Rom,
+    // RAMとRTCの両方の有効化を兼ねる
+    pub is_external_ram_enable: bool,
+    pub rom_bank_number: u8,
+    // 0x00-0x03はRAMバンク、0x08-0x0CはRTCのレジスタを選択する
+    pub ram_bank_number: u8,
+    pub ram: Vec<u8>,
+    pub rtc: Rtc,
+    pub dirty: bool
 }
 
 impl Mbc for NoMbc {
+    fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
     fn read_rom(&self, address: u16) -> Result<u8> {
-        let ret = self.rom.data[address as usize];
-        Ok(ret)
+        read_rom_data(&self.rom, address as usize, address)
+    }
+}
+
+impl Mbc1 {
+    // モード1の場合だけRAMバンクを切り替える
+    fn ram_bank(&self) -> usize {
+        if self.mode_flag { self.ram_bank_number as usize } else { 0 }
     }
 }
 
 impl Mbc for Mbc1 {
+    fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
     fn read_rom(&self, raw_address: u16) -> Result<u8> {
+        // 0x4000-0x5FFFに書き込まれた2bitは、ROMバンク番号の上位bit(bit 5-6)として使われる
+        let upper_bank_number = (self.ram_bank_number as usize) << 5;
+
         match raw_address {
             0x0000..=0x3FFF => {
-                if !self.mode_flag {
-                    Ok(self.rom.data[raw_address as usize])
+                // モード1の場合は0x0000-0x3FFFも上位bitによって切り替わる
+                let bank_number = if self.mode_flag { upper_bank_number } else { 0 };
+                read_rom_bank(&self.rom, bank_number, raw_address)
+            },
+            0x4000..=0x7FFF => {
+                // 下位5bitが0の場合は1として扱う
+                let lower_bank_number = match self.rom_bank_number {
+                    0 => 1,
+                    n => n as usize
+                };
+                let bank_number = upper_bank_number | lower_bank_number;
+                read_rom_bank(&self.rom, bank_number, raw_address)
+            },
+            _ => bail!(EmuError::BadBank(raw_address))
+        }
+    }
+
+    fn read_ram(&self, raw_address: u16) -> Result<u8> {
+        if !self.is_external_ram_enable {
+            return Ok(0xFF);
+        }
+
+        match ram_address(&self.ram, self.ram_bank(), raw_address) {
+            Some(address) => Ok(self.ram[address]),
+            None => Ok(0xFF)
+        }
+    }
+
+    fn write_ram(&mut self, raw_address: u16, data: u8) -> Result<()> {
+        if !self.is_external_ram_enable {
+            return Ok(());
+        }
+
+        if let Some(address) = ram_address(&self.ram, self.ram_bank(), raw_address) {
+            self.ram[address] = data;
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn write_registers(&mut self, address: u16, data: u8) -> Result<()> {
+        match address {
+            0x0000..=0x1FFF => {
+                if (data & 0x0F) == 0x0A {
+                    self.is_external_ram_enable = true;
                 }
                 else {
-                    let zero_bank_number = match self.rom_size {
-                        0x00..=0x04 => 0,
-                        0x05 => (self.ram_bank_number & 0x01) << 5,
-                        0x06 | _ => (self.ram_bank_number & 0x11) << 6
-                    };
-
-                    let address = 0x4000 * zero_bank_number as usize + raw_address as usize;
-                    Ok(self.rom.data[address])
+                    self.is_external_ram_enable = false;
                 }
             },
-            0x4000..=0x7FFF => {
-                let rom_size_mask_bit = match self.rom_size {
-                    0..=6 => (1 << (self.rom_size + 1)) - 1,
-                    _ => bail!("MBC1 does not support this rom size")
-                };
+            0x2000..=0x3FFF => self.rom_bank_number = data & 0x1F,
+            0x4000..=0x5FFF => self.ram_bank_number = data & 0x03,
+            0x6000..=0x7FFF => self.mode_flag = (data & 0x01) == 0x01,
+            _ => bail!(EmuError::BadBank(address))
+        }
+        Ok(())
+    }
+
+    fn has_battery(&self) -> bool {
+        self.mbc_type.has_battery()
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> Result<()> {
+        load_ram(&mut self.ram, data);
+        Ok(())
+    }
 
-                let high_bank_number = match self.rom_bank_number {
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.is_external_ram_enable);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_bool(self.mode_flag);
+        writer.write_vec(&self.ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.is_external_ram_enable = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        self.ram_bank_number = reader.read_u8()?;
+        self.mode_flag = reader.read_bool()?;
+        // RAMの内容が変わるのでセーブデータも書き出し直す
+        self.dirty = true;
+        reader.read_vec_into(&mut self.ram)
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    fn read_rom(&self, raw_address: u16) -> Result<u8> {
+        match raw_address {
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, 0, raw_address),
+            0x4000..=0x7FFF => {
+                let rom_bank_number = match self.rom_bank_number {
                     0 => 1,
-                    _ => {
-                        match self.rom_size {
-                            // 512KBまでは書き込まれたデータをマスクするだけ
-                            0..=4 => self.rom_bank_number & rom_size_mask_bit,
-                            // 1MB
-                            5 => (self.rom_bank_number & rom_size_mask_bit) + ((self.ram_bank_number & 0x01) << 5),
-                            // 2MB
-                            6 | _ => (self.rom_bank_number & rom_size_mask_bit) + ((self.ram_bank_number & 0x11) << 6)
-                        }
-                    }
+                    n => n as usize
                 };
-
-                let address = 0x4000 * high_bank_number as usize + (raw_address as usize - 0x4000);
-                let ret = self.rom.data[address];
-                Ok(ret)
+                read_rom_bank(&self.rom, rom_bank_number, raw_address)
             },
-            _ => bail!("Error in mbc1: invalid address")
+            _ => bail!(EmuError::BadBank(raw_address))
         }
     }
 
     fn read_ram(&self, raw_address: u16) -> Result<u8> {
         if self.is_external_ram_enable {
-            let data = match self.ram_size {
-                0x02 => {
-                    let address = raw_address as usize - 0xA000_usize;
-                    self.ram[address]
-                },
-                0x03 => {
-                    if self.mode_flag {
-                        let address = (0x2000 * self.ram_bank_number as usize) + (raw_address as usize - 0xA000);
-                        self.ram[address]
-                    }
-                    else {
-                        let address = raw_address as usize - 0xA000;
-                        self.ram[address]
-                    }
-                },
-                _ => bail!("Error in mbc1: invalid ram size")
-            };
-
-            Ok(data)
+            // 0xA200以降は0xA000-0xA1FFのミラー。上位4bitは常に1が読める
+            let address = (raw_address as usize - 0xA000) & 0x1FF;
+            Ok(0xF0 | self.ram[address])
         }
         else {
             Ok(0xFF)
@@ -132,122 +256,239 @@ impl Mbc for Mbc1 {
 
     fn write_ram(&mut self, raw_address: u16, data: u8) -> Result<()> {
         if self.is_external_ram_enable {
-            match self.ram_size {
-                0x02 => {
-                    let address = raw_address as usize - 0xA000_usize;
-                    self.ram[address] = data;
-                },
-                0x03 => {
-                    if self.mode_flag {
-                        let address = (0x2000 * self.ram_bank_number as usize) + (raw_address as usize - 0xA000);
-                        self.ram[address] = data;
-                    }
-                    else {
-                        let address = raw_address as usize - 0xA000;
-                        self.ram[address] = data;
-                    }
-                },
-                _ => bail!("Error in mbc1: invalid ram size")
-            };
-
-            Ok(())
-        }
-        else {
-
-            Ok(())
+            let address = (raw_address as usize - 0xA000) & 0x1FF;
+            self.ram[address] = data & 0x0F;
+            self.dirty = true;
         }
+        Ok(())
     }
 
     fn write_registers(&mut self, address: u16, data: u8) -> Result<()> {
         match address {
-            0x0000..=0x1FFF => {
-                if data == 0x0A {
-                    self.is_external_ram_enable = true;
+            // アドレスのbit 8でRAMの有効化とROMバンクの切り替えを選択する
+            0x0000..=0x3FFF => {
+                if (address & 0x0100) == 0 {
+                    self.is_external_ram_enable = (data & 0x0F) == 0x0A;
                 }
                 else {
-                    self.is_external_ram_enable = false;
+                    self.rom_bank_number = data & 0x0F;
                 }
             },
-            0x2000..=0x3FFF => self.rom_bank_number = data,
-            0x4000..=0x5FFF => self.ram_bank_number = data & 0x11,
-            0x6000..=0x7FFF => self.mode_flag = (data & 0x01) == 0x01,
-            _ => bail!("Error in mbc1: Invalid address")
+            0x4000..=0x7FFF => {},
+            _ => bail!(EmuError::BadBank(address))
         }
         Ok(())
     }
 
-    fn read_save_file(&mut self) -> Result<()> {
-        // save機能対応の場合はRAM内容を読み込む
-        if self.mbc_type == 0x03 {
-            let file = File::open("save_file");
-            if let Ok(f) = file {
-                let mut reader = BufReader::new(f);
-                reader.read_exact(&mut self.ram).unwrap();
-            }
+    fn has_battery(&self) -> bool {
+        self.mbc_type.has_battery()
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> Result<()> {
+        load_ram(&mut self.ram, data);
+        for v in self.ram.iter_mut() {
+            *v &= 0x0F;
         }
         Ok(())
     }
-    
-    fn write_save_file(&mut self) -> Result<()> {
-        if self.mbc_type == 0x03 {
-            // save機能対応の場合はRAM内容をファイルとして書き出す
-            let mut save_file = File::create("save_file")?;
-            let buf = self.ram.bytes().collect::<io::Result<Vec<u8>>>()?;
-            save_file.write_all(&buf)?;
-            save_file.flush()?;
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.is_external_ram_enable);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_vec(&self.ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.is_external_ram_enable = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        // RAMの内容が変わるのでセーブデータも書き出し直す
+        self.dirty = true;
+        reader.read_vec_into(&mut self.ram)
+    }
+}
+
+impl Mbc3 {
+    fn has_rtc(&self) -> bool {
+        self.mbc_type.has_timer()
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    fn read_rom(&self, raw_address: u16) -> Result<u8> {
+        match raw_address {
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, 0, raw_address),
+            0x4000..=0x7FFF => {
+                let rom_bank_number = match self.rom_bank_number {
+                    0 => 1,
+                    n => n as usize
+                };
+                read_rom_bank(&self.rom, rom_bank_number, raw_address)
+            },
+            _ => bail!(EmuError::BadBank(raw_address))
+        }
+    }
+
+    fn read_ram(&self, raw_address: u16) -> Result<u8> {
+        if !self.is_external_ram_enable {
+            return Ok(0xFF);
+        }
+
+        match self.ram_bank_number {
+            0x00..=0x03 => match ram_address(&self.ram, self.ram_bank_number as usize, raw_address) {
+                Some(address) => Ok(self.ram[address]),
+                None => Ok(0xFF)
+            },
+            0x08..=0x0C if self.has_rtc() => Ok(self.rtc.read(self.ram_bank_number)),
+            _ => Ok(0xFF)
+        }
+    }
+
+    fn write_ram(&mut self, raw_address: u16, data: u8) -> Result<()> {
+        if !self.is_external_ram_enable {
+            return Ok(());
+        }
+
+        match self.ram_bank_number {
+            0x00..=0x03 => {
+                if let Some(address) = ram_address(&self.ram, self.ram_bank_number as usize, raw_address) {
+                    self.ram[address] = data;
+                    self.dirty = true;
+                }
+            },
+            0x08..=0x0C if self.has_rtc() => {
+                self.rtc.write(self.ram_bank_number, data);
+                self.dirty = true;
+            },
+            _ => {}
         }
         Ok(())
     }
+
+    fn write_registers(&mut self, address: u16, data: u8) -> Result<()> {
+        match address {
+            0x0000..=0x1FFF => self.is_external_ram_enable = (data & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank_number = data & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank_number = data,
+            0x6000..=0x7FFF => self.rtc.write_latch(data),
+            _ => bail!(EmuError::BadBank(address))
+        }
+        Ok(())
+    }
+
+    fn has_battery(&self) -> bool {
+        self.mbc_type.has_battery()
+    }
+
+    // RTC付きの場合はRAMの後ろにRTCの状態を付ける
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if self.has_rtc() {
+            data.extend_from_slice(&self.rtc.footer(rtc::unix_time()));
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> Result<()> {
+        // RAMのサイズは2KBの倍数なので、余りがあればRTCの状態とみなす
+        let footer_size = data.len() % 0x800;
+        let (ram, footer) = data.split_at(data.len() - footer_size);
+
+        load_ram(&mut self.ram, ram);
+        if self.has_rtc() && footer_size != 0 {
+            self.rtc.load_footer(footer, rtc::unix_time())?;
+        }
+        Ok(())
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        if self.has_rtc() {
+            self.rtc.tick(cycles);
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.is_external_ram_enable);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_vec(&self.ram);
+        self.rtc.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.is_external_ram_enable = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        self.ram_bank_number = reader.read_u8()?;
+        // RAMの内容が変わるのでセーブデータも書き出し直す
+        self.dirty = true;
+        reader.read_vec_into(&mut self.ram)?;
+        self.rtc.load_state(reader)
+    }
 }
 
 impl Mbc for Mbc5 {
+    fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
     fn read_rom(&self, raw_address: u16) -> Result<u8> {
         match raw_address {
-            0x0000..=0x3FFF => Ok(self.rom.data[raw_address as usize]),
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, 0, raw_address),
             0x4000..=0x7FFF => {
                 let mut rom_bank_number = self.rom_bank_number_low as usize;
                 if self.rom_bank_number_high {
                     rom_bank_number += 1 << 8;
                 }
-                let address = 0x4000 * rom_bank_number + (raw_address as usize - 0x4000);
-                Ok(self.rom.data[address])
+                read_rom_bank(&self.rom, rom_bank_number, raw_address)
             },
-            _ => bail!("Error in mbc5: invalid address")
+            _ => bail!(EmuError::BadBank(raw_address))
         }
     }
 
     fn read_ram(&self, raw_address: u16) -> Result<u8> {
-        if self.is_external_ram_enable {
-            let address = 0x2000 * self.ram_bank_number as usize + (raw_address as usize - 0xA000);
-            let data = self.ram[address];
-            Ok(data)
+        if !self.is_external_ram_enable {
+            return Ok(0xFF);
         }
-        else {
-            Ok(0xFF)
+
+        match ram_address(&self.ram, self.ram_bank_number as usize, raw_address) {
+            Some(address) => Ok(self.ram[address]),
+            None => Ok(0xFF)
         }
     }
 
     fn write_ram(&mut self, raw_address: u16, data: u8) -> Result<()> {
-        if self.is_external_ram_enable {
-            let address = 0x2000 * self.ram_bank_number as usize + (raw_address as usize - 0xA000);
-            self.ram[address] = data;
-
-            match self.mbc_type {
-                0x1B | 0x1E => {
-                    // save機能対応の場合はRAM内容をファイルとして書き出す
-                    let mut save_file = File::create("save_file")?;
-                    let buf = self.ram.bytes().collect::<io::Result<Vec<u8>>>()?;
-                    save_file.write_all(&buf)?;
-                    save_file.flush()?;
-                },
-                _ => {}
-            }
-
-            Ok(())
+        if !self.is_external_ram_enable {
+            return Ok(());
         }
-        else {
-            Ok(())
+
+        if let Some(address) = ram_address(&self.ram, self.ram_bank_number as usize, raw_address) {
+            self.ram[address] = data;
+            self.dirty = true;
         }
+        Ok(())
     }
 
     fn write_registers(&mut self, address: u16, data: u8) -> Result<()> {
@@ -272,34 +513,332 @@ impl Mbc for Mbc5 {
         Ok(())
     }
 
-    fn read_save_file(&mut self) -> Result<()> {
-        match self.mbc_type {
-            0x1B | 0x1E => {
-                // save機能対応の場合はRAM内容をファイルとして書き出す
-                let mut save_file = File::create("save_file")?;
-                let buf = self.ram.bytes().collect::<io::Result<Vec<u8>>>()?;
-                save_file.write_all(&buf)?;
-                save_file.flush()?;
-            },
-            _ => {}
-        }
+    fn has_battery(&self) -> bool {
+        self.mbc_type.has_battery()
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
 
+    fn load_save_data(&mut self, data: &[u8]) -> Result<()> {
+        load_ram(&mut self.ram, data);
         Ok(())
     }
 
-    fn write_save_file(&mut self) -> Result<()> {
-        match self.mbc_type {
-            0x1B | 0x1E => {
-                // save機能対応の場合はRAM内容をファイルとして書き出す
-                let mut save_file = File::create("save_file")?;
-                let buf = self.ram.bytes().collect::<io::Result<Vec<u8>>>()?;
-                save_file.write_all(&buf)?;
-                save_file.flush()?;
-            },
-            _ => {}
-        }
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
 
-        Ok(())
+    fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.is_external_ram_enable);
+        writer.write_u8(self.rom_bank_number_low);
+        writer.write_bool(self.rom_bank_number_high);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_vec(&self.ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.is_external_ram_enable = reader.read_bool()?;
+        self.rom_bank_number_low = reader.read_u8()?;
+        self.rom_bank_number_high = reader.read_bool()?;
+        self.ram_bank_number = reader.read_u8()?;
+        // RAMの内容が変わるのでセーブデータも書き出し直す
+        self.dirty = true;
+        reader.read_vec_into(&mut self.ram)
+    }
+}
+
+// セーブファイルの内容をRAMにコピーする
+// サイズが合わない場合は重なる部分だけをコピーし、残りはそのままにする
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+// ROMバンク番号をヘッダのROMサイズのバンク数で折り返して読む
+// raw_addressの下位14bitをバンク内のオフセットとして使う
+fn read_rom_bank(rom: &Rom, bank_number: usize, raw_address: u16) -> Result<u8> {
+    let address = 0x4000 * (bank_number % rom.rom_banks()) + (raw_address as usize & 0x3FFF);
+    read_rom_data(rom, address, raw_address)
+}
+
+// ヘッダのROMサイズより短いデータでも落ちないようにする
+fn read_rom_data(rom: &Rom, address: usize, raw_address: u16) -> Result<u8> {
+    match rom.data.get(address) {
+        Some(&ret) => Ok(ret),
+        None => bail!(EmuError::BadBank(raw_address))
     }
 }
 
+// RAMバンク番号とアドレス(0xA000-0xBFFF)から、実際のRAMのサイズで折り返したアドレスにする
+// RAMを持たないカートリッジの場合はNone
+fn ram_address(ram: &[u8], bank_number: usize, raw_address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+
+    let address = 0x2000 * bank_number + (raw_address as usize - 0xA000);
+    Some(address % ram.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 各ROMバンクの先頭にバンク番号を書いたROM。rom_sizeはヘッダのROMサイズ
+    fn build_rom(rom_size: u8, banks: usize) -> Rom {
+        let mut data = vec![0; 0x4000 * banks];
+        for bank in 0..banks {
+            data[0x4000 * bank] = bank as u8;
+        }
+        Rom { rom_size, data, ..Default::default() }
+    }
+
+    fn build_mbc3(mbc_type: CartridgeType) -> Mbc3 {
+        Mbc3 {
+            mbc_type,
+            rom: build_rom(0x00, 2),
+            is_external_ram_enable: false,
+            rom_bank_number: 0,
+            ram_bank_number: 0,
+            ram: vec![0; 0x2000],
+            rtc: Rtc::default(),
+            dirty: false
+        }
+    }
+
+    fn build_mbc2() -> Mbc2 {
+        Mbc2 {
+            mbc_type: CartridgeType::Mbc2Battery,
+            rom: build_rom(0x02, 8),
+            is_external_ram_enable: false,
+            rom_bank_number: 0,
+            ram: vec![0; 0x200],
+            dirty: false
+        }
+    }
+
+    fn latch(mbc: &mut dyn Mbc) {
+        mbc.write_registers(0x6000, 0x00).unwrap();
+        mbc.write_registers(0x6000, 0x01).unwrap();
+    }
+
+    #[test]
+    fn mbc3_rtc_registers() {
+        let mut mbc = build_mbc3(CartridgeType::Mbc3TimerRamBattery);
+        mbc.write_registers(0x0000, 0x0A).unwrap();
+        mbc.write_registers(0x4000, 0x08).unwrap();
+        mbc.write_ram(0xA000, 30).unwrap();
+        assert!(mbc.is_dirty());
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 30);
+
+        // 1秒進めても、ラッチするまでは前の値が読める
+        for _ in 0..0x8000 {
+            mbc.tick(128);
+        }
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 30);
+        latch(&mut mbc);
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 31);
+
+        // RAMバンクに戻すとRAMが読める
+        mbc.write_registers(0x4000, 0x00).unwrap();
+        mbc.write_ram(0xA000, 0x42).unwrap();
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 0x42);
+
+        // 無効にするとどちらも読めない
+        mbc.write_registers(0x0000, 0x00).unwrap();
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn mbc3_without_timer() {
+        let mut mbc = build_mbc3(CartridgeType::Mbc3RamBattery);
+        mbc.write_registers(0x0000, 0x0A).unwrap();
+        mbc.write_registers(0x4000, 0x08).unwrap();
+        mbc.write_ram(0xA000, 30).unwrap();
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 0xFF);
+        assert!(!mbc.is_dirty());
+        assert_eq!(mbc.save_data().len(), 0x2000);
+    }
+
+    #[test]
+    fn mbc3_save_data_with_footer() {
+        let mut mbc = build_mbc3(CartridgeType::Mbc3TimerRamBattery);
+        mbc.write_registers(0x0000, 0x0A).unwrap();
+        mbc.write_ram(0xA123, 0x55).unwrap();
+        // 止めておけば、読み込むまでの時間で時計が進まない
+        for (register, data) in [(0x0C, 0x40), (0x08, 12), (0x09, 34), (0x0A, 5)] {
+            mbc.write_registers(0x4000, register).unwrap();
+            mbc.write_ram(0xA000, data).unwrap();
+        }
+
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x2000 + rtc::FOOTER_SIZE);
+
+        for len in [rtc::FOOTER_SIZE, rtc::FOOTER_SIZE_32] {
+            let mut loaded = build_mbc3(CartridgeType::Mbc3TimerRamBattery);
+            loaded.load_save_data(&data[..0x2000 + len]).unwrap();
+            loaded.write_registers(0x0000, 0x0A).unwrap();
+            assert_eq!(loaded.read_ram(0xA123).unwrap(), 0x55);
+
+            latch(&mut loaded);
+            for (register, data) in [(0x08, 12), (0x09, 34), (0x0A, 5), (0x0C, 0x40)] {
+                loaded.write_registers(0x4000, register).unwrap();
+                assert_eq!(loaded.read_ram(0xA000).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn mbc2_register_select_by_address_bit_8() {
+        let mut mbc = build_mbc2();
+
+        // bit 8が1ならROMバンク番号
+        mbc.write_registers(0x2100, 0x03).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 3);
+        assert!(!mbc.is_external_ram_enable);
+        // 0を書き込むとバンク1になる
+        mbc.write_registers(0x0100, 0x00).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 1);
+
+        // bit 8が0ならRAMの有効化。ROMバンクは変わらない
+        mbc.write_registers(0x2000, 0x0A).unwrap();
+        assert!(mbc.is_external_ram_enable);
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 1);
+        mbc.write_registers(0x00FF, 0x00).unwrap();
+        assert!(!mbc.is_external_ram_enable);
+    }
+
+    #[test]
+    fn mbc2_ram_nibbles() {
+        let mut mbc = build_mbc2();
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 0xFF);
+        mbc.write_ram(0xA000, 0x12).unwrap();
+        assert!(!mbc.is_dirty());
+
+        mbc.write_registers(0x0000, 0x0A).unwrap();
+        mbc.write_ram(0xA000, 0xAB).unwrap();
+        mbc.write_ram(0xA1FF, 0x07).unwrap();
+        assert!(mbc.is_dirty());
+
+        // 下位4bitだけが残り、上位4bitは1が読める
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 0xFB);
+        assert_eq!(mbc.read_ram(0xA1FF).unwrap(), 0xF7);
+        assert_eq!(mbc.save_data()[0], 0x0B);
+
+        // セーブファイルの上位4bitも捨てる
+        mbc.load_save_data(&[0xFF; 0x200]).unwrap();
+        assert_eq!(mbc.save_data(), vec![0x0F; 0x200]);
+    }
+
+    #[test]
+    fn mbc2_ram_echo() {
+        let mut mbc = build_mbc2();
+        mbc.write_registers(0x0000, 0x0A).unwrap();
+        mbc.write_ram(0xA005, 0x05).unwrap();
+
+        // 512byteごとにA000-BFFF全体で同じRAMが見える
+        for base in (0xA000..0xC000).step_by(0x200) {
+            assert_eq!(mbc.read_ram(base + 0x005).unwrap(), 0xF5);
+        }
+        mbc.write_ram(0xBE05, 0x09).unwrap();
+        assert_eq!(mbc.read_ram(0xA005).unwrap(), 0xF9);
+        assert_eq!(mbc.ram.len(), 0x200);
+    }
+
+    fn bank_error(result: Result<u8>) -> Option<EmuError> {
+        result.unwrap_err().downcast_ref::<EmuError>().cloned()
+    }
+
+    #[test]
+    fn mbc1_wraps_rom_bank() {
+        let mut mbc = Mbc1 {
+            mbc_type: CartridgeType::Mbc1,
+            rom: build_rom(0x01, 4),
+            is_external_ram_enable: false,
+            rom_bank_number: 0,
+            ram_bank_number: 0,
+            mode_flag: false,
+            ram: Vec::new(),
+            dirty: false
+        };
+
+        mbc.write_registers(0x2000, 0x05).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 1);
+        mbc.write_registers(0x2000, 0x1F).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 3);
+
+        // 上位bitを足したバンク0x22も4バンクで折り返す
+        mbc.write_registers(0x2000, 0x02).unwrap();
+        mbc.write_registers(0x4000, 0x01).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 2);
+        mbc.write_registers(0x6000, 0x01).unwrap();
+        assert_eq!(mbc.read_rom(0x0000).unwrap(), 0);
+    }
+
+    #[test]
+    fn mbc5_wraps_rom_bank() {
+        let mut mbc = Mbc5 {
+            mbc_type: CartridgeType::Mbc5,
+            rom: build_rom(0x02, 8),
+            is_external_ram_enable: false,
+            rom_bank_number_low: 1,
+            rom_bank_number_high: false,
+            ram_bank_number: 0,
+            ram: Vec::new(),
+            dirty: false
+        };
+
+        mbc.write_registers(0x2000, 0x0B).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 3);
+        mbc.write_registers(0x2000, 0x01).unwrap();
+        mbc.write_registers(0x3000, 0x01).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 1);
+        // バンク0も選べる
+        mbc.write_registers(0x2000, 0x00).unwrap();
+        mbc.write_registers(0x3000, 0x00).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 0);
+    }
+
+    #[test]
+    fn wraps_by_header_rom_size() {
+        // ヘッダは4バンクだが、データは8バンクある
+        let mut mbc = build_mbc3(CartridgeType::Mbc3);
+        mbc.rom = build_rom(0x01, 8);
+        mbc.write_registers(0x2000, 0x05).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 1);
+
+        // ヘッダは8バンクだが、データは4バンクしかない
+        mbc.rom = build_rom(0x02, 4);
+        mbc.write_registers(0x2000, 0x06).unwrap();
+        assert_eq!(bank_error(mbc.read_rom(0x4000)), Some(EmuError::BadBank(0x4000)));
+        mbc.write_registers(0x2000, 0x0B).unwrap();
+        assert_eq!(mbc.read_rom(0x4000).unwrap(), 3);
+
+        let short = NoMbc { mbc_type: CartridgeType::RomOnly, rom: build_rom(0x00, 1) };
+        assert_eq!(short.read_rom(0x3FFF).unwrap(), 0);
+        assert_eq!(bank_error(short.read_rom(0x4000)), Some(EmuError::BadBank(0x4000)));
+    }
+
+    #[test]
+    fn wraps_ram_bank() {
+        // 8KBのRAMでは、どのRAMバンクを選んでも同じRAMが見える
+        let mut mbc = build_mbc3(CartridgeType::Mbc3RamBattery);
+        mbc.write_registers(0x0000, 0x0A).unwrap();
+        mbc.write_registers(0x4000, 0x03).unwrap();
+        mbc.write_ram(0xA010, 0x33).unwrap();
+        mbc.write_registers(0x4000, 0x00).unwrap();
+        assert_eq!(mbc.read_ram(0xA010).unwrap(), 0x33);
+
+        // 2KBのRAMは0xA000-0xBFFFの中で繰り返す
+        mbc.ram = vec![0; 0x800];
+        mbc.write_ram(0xA801, 0x44).unwrap();
+        assert_eq!(mbc.read_ram(0xA001).unwrap(), 0x44);
+        assert_eq!(mbc.read_ram(0xB801).unwrap(), 0x44);
+    }
+}
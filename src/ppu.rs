@@ -1,6 +1,8 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::{collections::VecDeque, cmp::Ordering};
 
+use crate::state::{StateReader, StateWriter};
+
 pub enum Mode {
     OamScan,
     Drawing,
@@ -36,6 +38,19 @@ impl OAM {
             _ => self.sprite_flags = data
         }
     }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        for i in 0..4 {
+            writer.write_u8(self.get(i));
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        for i in 0..4 {
+            self.set(i, reader.read_u8()?);
+        }
+        Ok(())
+    }
 }
 
 pub struct PixelData {
@@ -45,12 +60,51 @@ pub struct PixelData {
     background_priority: u8
 }
 
+impl PixelData {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.color);
+        writer.write_u8(self.palette);
+        writer.write_u8(self.sprite_priority);
+        writer.write_u8(self.background_priority);
+    }
+
+    fn load_state(reader: &mut StateReader) -> Result<Self> {
+        Ok(Self {
+            color: reader.read_u8()?,
+            palette: reader.read_u8()?,
+            sprite_priority: reader.read_u8()?,
+            background_priority: reader.read_u8()?
+        })
+    }
+}
+
 impl Default for Mode {
     fn default() -> Self {
         Self::OamScan
     }
 }
 
+impl Mode {
+    fn to_u8(&self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3
+        }
+    }
+
+    fn from_u8(data: u8) -> Result<Self> {
+        match data {
+            0 => Ok(Mode::HBlank),
+            1 => Ok(Mode::VBlank),
+            2 => Ok(Mode::OamScan),
+            3 => Ok(Mode::Drawing),
+            _ => bail!("invalid PPU mode in save state: {}", data)
+        }
+    }
+}
+
 pub enum Color {
     White,
     LightGray,
@@ -58,14 +112,55 @@ pub enum Color {
     Black
 }
 
+impl Color {
+    fn to_u8(&self) -> u8 {
+        match self {
+            Color::White => 0,
+            Color::LightGray => 1,
+            Color::DarkGray => 2,
+            Color::Black => 3
+        }
+    }
+
+    fn from_u8(data: u8) -> Color {
+        match data & 0x03 {
+            0 => Color::White,
+            1 => Color::LightGray,
+            2 => Color::DarkGray,
+            _ => Color::Black
+        }
+    }
+}
+
 pub struct Palette([Color; 4]);
 
+impl Palette {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for color in self.0.iter() {
+            writer.write_u8(color.to_u8());
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        for color in self.0.iter_mut() {
+            *color = Color::from_u8(reader.read_u8()?);
+        }
+        Ok(())
+    }
+}
+
 impl Default for Palette {
     fn default() -> Self {
         Self([Color::White, Color::LightGray, Color::DarkGray, Color::Black])
     }
 }
 
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Ppu {
     vram: [u8; 0x8192],
     oam: [OAM; 40],
@@ -87,13 +182,25 @@ pub struct Ppu {
     sprite_buffer: Vec<(OAM, usize)>,
     bg_color_palette: Palette,
     obp_color_palette: [Palette; 2],
-    frame_buffer: [[u8; 4]; 160 * 144],
+    frame_buffer: Vec<[u8; 4]>,
     current_cycle: usize,
     pub mode: Mode,
     pub int_vblank: bool,
     pub int_lcd_stat: bool
 }
 
+// 2bppのタイルデータ1行分(下位バイト、上位バイト)を左から順にカラー番号(0-3)にする
+pub fn decode_tile_row(lower: u8, higher: u8) -> [u8; 8] {
+    let mut colors = [0; 8];
+    for (i, color) in colors.iter_mut().enumerate() {
+        let bit = 7 - i;
+        let top = (higher >> bit) & 0x01;
+        let bottom = (lower >> bit) & 0x01;
+        *color = top * 2 + bottom;
+    }
+    colors
+}
+
 impl Ppu {
     pub fn new() -> Self {
         Self {
@@ -117,7 +224,7 @@ impl Ppu {
             sprite_buffer: Vec::new(),
             bg_color_palette: Default::default(),
             obp_color_palette: Default::default(),
-            frame_buffer: [[0; 4]; 160 * 144],
+            frame_buffer: vec![[0; 4]; 160 * 144],
             current_cycle: Default::default(),
             mode: Default::default(),
             int_vblank: Default::default(),
@@ -389,6 +496,10 @@ impl Ppu {
         Ok(())
     }
 
+    pub fn frame_buffer(&self) -> &[[u8; 4]] {
+        &self.frame_buffer
+    }
+
     pub fn dump(&self) {
         let start = 0x1000;
         for i in start..=0x1500 {
@@ -551,11 +662,7 @@ impl Ppu {
         // println!("tile_data = 0x{:02X} 0x{:02X}", lower_tile_data, higher_tile_data);
 
         // push fifo
-        for bit in (0_u8..8).rev() {
-            let top = if higher_tile_data & (1 << bit) == (1 << bit) {1_u8} else {0_u8};
-            let bottom = if lower_tile_data & (1 << bit) == (1 << bit) {1_u8} else {0_u8};
-
-            let pixel_color = top*2 + bottom;
+        for pixel_color in decode_tile_row(lower_tile_data, higher_tile_data) {
             let pixel_data = PixelData {
                 color: pixel_color,
                 background_priority: 0,
@@ -607,11 +714,7 @@ impl Ppu {
         // println!("tile_data = 0x{:02X} 0x{:02X}", lower_tile_data, higher_tile_data);
 
         // push fifo
-        for bit in (0_u8..8).rev() {
-            let top = if higher_tile_data & (1 << bit) == (1 << bit) {1_u8} else {0_u8};
-            let bottom = if lower_tile_data & (1 << bit) == (1 << bit) {1_u8} else {0_u8};
-
-            let pixel_color = top*2 + bottom;
+        for pixel_color in decode_tile_row(lower_tile_data, higher_tile_data) {
             let pixel_data = PixelData {
                 color: pixel_color,
                 background_priority: 0,
@@ -758,6 +861,109 @@ impl Ppu {
         Ok(())
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+        for sprite in self.oam.iter() {
+            sprite.save_state(writer);
+        }
+        writer.write_u8(self.lcd_control);
+        writer.write_u8(self.lcd_stat);
+        writer.write_u8(self.scy);
+        writer.write_u8(self.scx);
+        writer.write_u8(self.ly);
+        writer.write_u8(self.lyc);
+        writer.write_u8(self.wy);
+        writer.write_u8(self.wx);
+        writer.write_bool(self.render_window_flag);
+        writer.write_bool(self.window_line_flag);
+        writer.write_u8(self.bgp);
+        writer.write_bytes(&self.obp);
+        writer.write_u8(self.window_line_counter);
+
+        writer.write_u32(self.bg_fifo.len() as u32);
+        for pixel in self.bg_fifo.iter() {
+            pixel.save_state(writer);
+        }
+        writer.write_u32(self.sprite_fifo.len() as u32);
+        for pixel in self.sprite_fifo.iter() {
+            pixel.save_state(writer);
+        }
+        writer.write_u32(self.sprite_buffer.len() as u32);
+        for (sprite, idx) in self.sprite_buffer.iter() {
+            sprite.save_state(writer);
+            writer.write_u8(*idx as u8);
+        }
+
+        self.bg_color_palette.save_state(writer);
+        for palette in self.obp_color_palette.iter() {
+            palette.save_state(writer);
+        }
+
+        for pixel in self.frame_buffer.iter() {
+            writer.write_bytes(pixel);
+        }
+        writer.write_u32(self.current_cycle as u32);
+        writer.write_u8(self.mode.to_u8());
+        writer.write_bool(self.int_vblank);
+        writer.write_bool(self.int_lcd_stat);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.read_bytes(&mut self.vram)?;
+        for sprite in self.oam.iter_mut() {
+            sprite.load_state(reader)?;
+        }
+        self.lcd_control = reader.read_u8()?;
+        self.lcd_stat = reader.read_u8()?;
+        self.scy = reader.read_u8()?;
+        self.scx = reader.read_u8()?;
+        self.ly = reader.read_u8()?;
+        self.lyc = reader.read_u8()?;
+        self.wy = reader.read_u8()?;
+        self.wx = reader.read_u8()?;
+        self.render_window_flag = reader.read_bool()?;
+        self.window_line_flag = reader.read_bool()?;
+        self.bgp = reader.read_u8()?;
+        reader.read_bytes(&mut self.obp)?;
+        self.window_line_counter = reader.read_u8()?;
+
+        self.bg_fifo.clear();
+        for _ in 0..reader.read_u32()? {
+            self.bg_fifo.push_back(PixelData::load_state(reader)?);
+        }
+        self.sprite_fifo.clear();
+        for _ in 0..reader.read_u32()? {
+            self.sprite_fifo.push_back(PixelData::load_state(reader)?);
+        }
+        self.sprite_buffer.clear();
+        for _ in 0..reader.read_u32()? {
+            let mut sprite = OAM::default();
+            sprite.load_state(reader)?;
+            let idx = reader.read_u8()? as usize;
+            self.sprite_buffer.push((sprite, idx));
+        }
+
+        self.bg_color_palette.load_state(reader)?;
+        for palette in self.obp_color_palette.iter_mut() {
+            palette.load_state(reader)?;
+        }
+
+        for pixel in self.frame_buffer.iter_mut() {
+            reader.read_bytes(pixel)?;
+        }
+        self.current_cycle = reader.read_u32()? as usize;
+        self.mode = Mode::from_u8(reader.read_u8()?)?;
+        self.int_vblank = reader.read_bool()?;
+        self.int_lcd_stat = reader.read_bool()?;
+        Ok(())
+    }
+
+    // STOP中などLCDが止まっているときは画面が白くなる
+    pub fn clear_screen(&mut self) {
+        let white: [u8; 4] = [0xe1, 0xef, 0xdc, 0xff];
+        self.frame_buffer.fill(white);
+    }
+
     fn read_lcd_bit(&self, bit: u8) -> bool {
         return &self.lcd_control & (1 << bit) == (1 << bit);
     }
@@ -0,0 +1,317 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::ppu::decode_tile_row;
+use crate::screenshot;
+use crate::serial::SerialEndpoint;
+
+// 印刷の幅は20タイル(160ピクセル)
+const WIDTH: usize = 160;
+// タイル1行(20タイル x 16byte)分のデータ量
+const TILE_ROW_BYTES: usize = 20 * 16;
+// プリンタのバッファに溜められるデータ量(9タイル行分)
+const BUFFER_SIZE: usize = TILE_ROW_BYTES * 2 * 9;
+// 余白の1単位を何ピクセル分の紙送りとして扱うか
+const MARGIN_LINES: usize = 8;
+// 印刷命令の後、何回のステータス確認の間「印刷中」を返すか
+const PRINTING_POLLS: u8 = 4;
+// 後ろの余白なしで印刷した後、この回数(約1秒分)ポーリングされる間データが来なければ紙を切る
+const IDLE_POLLS: usize = 8192;
+
+// コマンド
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+// ステータスのbit
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_PRINTING: u8 = 0x02;
+const STATUS_IMAGE_DATA_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED_DATA: u8 = 0x08;
+
+// 印刷した紙の色(白、薄い灰色、濃い灰色、黒)
+const COLORS: [[u8; 4]; 4] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF]
+];
+
+// パケットのどこを受信しているか
+// 0x88 0x33 コマンド 圧縮フラグ データ長(2byte) データ チェックサム(2byte) 0x00 0x00
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    // プリンタは0x81を返して接続されていることを知らせる
+    Alive,
+    // プリンタはステータスを返す
+    Status
+}
+
+// シリアルポートにつなぐポケットプリンタ
+// 印刷した画像はoutput_dirにPNGとして保存する
+pub struct Printer {
+    output_dir: PathBuf,
+    state: PacketState,
+    command: u8,
+    compression: bool,
+    length: u16,
+    data: Vec<u8>,
+    // 計算したチェックサムと受信したチェックサム
+    checksum: u16,
+    received_checksum: u16,
+    // 印刷待ちの画像データ(タイル形式)
+    image: Vec<u8>,
+    // 印刷中の紙。余白なしで続けて印刷された分は1枚につなげる
+    paper: Vec<[u8; 4]>,
+    // 最後にデータを受信してからポーリングされた回数
+    idle_polls: usize,
+    // 保存した印刷結果。フロントエンドが取り出して知らせる
+    printouts: Vec<Result<PathBuf>>,
+    status: u8,
+    printing_polls: u8
+}
+
+impl Printer {
+    pub fn new<P: AsRef<Path>>(output_dir: P) -> Self {
+        Self {
+            output_dir: output_dir.as_ref().to_path_buf(),
+            state: PacketState::Magic1,
+            command: 0,
+            compression: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            image: Vec::new(),
+            paper: Vec::new(),
+            idle_polls: 0,
+            printouts: Vec::new(),
+            status: 0,
+            printing_polls: 0
+        }
+    }
+
+    // 受信したバイトを処理して、プリンタ側から送り返すバイトを返す
+    fn receive(&mut self, data: u8) -> u8 {
+        self.idle_polls = 0;
+
+        match self.state {
+            PacketState::Magic1 => {
+                if data == 0x88 {
+                    self.state = PacketState::Magic2;
+                }
+            },
+            PacketState::Magic2 => {
+                self.state = if data == 0x33 { PacketState::Command } else { PacketState::Magic1 };
+            },
+            PacketState::Command => {
+                self.command = data;
+                self.checksum = data as u16;
+                self.state = PacketState::Compression;
+            },
+            PacketState::Compression => {
+                self.compression = (data & 0x01) == 0x01;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                self.state = PacketState::LengthLow;
+            },
+            PacketState::LengthLow => {
+                self.length = data as u16;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                self.state = PacketState::LengthHigh;
+            },
+            PacketState::LengthHigh => {
+                self.length |= (data as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                self.data.clear();
+                self.state = if self.length == 0 { PacketState::ChecksumLow } else { PacketState::Data };
+            },
+            PacketState::Data => {
+                self.data.push(data);
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                if self.data.len() == self.length as usize {
+                    self.state = PacketState::ChecksumLow;
+                }
+            },
+            PacketState::ChecksumLow => {
+                self.received_checksum = data as u16;
+                self.state = PacketState::ChecksumHigh;
+            },
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (data as u16) << 8;
+                self.state = PacketState::Alive;
+            },
+            PacketState::Alive => {
+                self.execute();
+                self.state = PacketState::Status;
+                return 0x81;
+            },
+            PacketState::Status => {
+                self.state = PacketState::Magic1;
+                return self.read_status();
+            }
+        }
+
+        0x00
+    }
+
+    fn execute(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.printing_polls = 0;
+            },
+            COMMAND_DATA => {
+                let data = if self.compression { decompress(&self.data) } else { self.data.clone() };
+                let space = BUFFER_SIZE - self.image.len();
+                self.image.extend_from_slice(&data[..data.len().min(space)]);
+
+                if !self.image.is_empty() {
+                    self.status |= STATUS_UNPROCESSED_DATA;
+                }
+                if self.image.len() >= BUFFER_SIZE {
+                    self.status |= STATUS_IMAGE_DATA_FULL;
+                }
+            },
+            COMMAND_PRINT => {
+                // データは枚数、余白(上位4bitが前、下位4bitが後)、パレット、濃度
+                let margins = self.data.get(1).copied().unwrap_or(0);
+                let palette = self.data.get(2).copied().unwrap_or(0);
+
+                self.print(margins, palette);
+
+                self.image.clear();
+                self.status &= !(STATUS_UNPROCESSED_DATA | STATUS_IMAGE_DATA_FULL);
+                self.printing_polls = PRINTING_POLLS;
+            },
+            COMMAND_STATUS => {},
+            _ => {}
+        }
+    }
+
+    fn read_status(&mut self) -> u8 {
+        if self.printing_polls > 0 {
+            self.printing_polls -= 1;
+            self.status | STATUS_PRINTING
+        }
+        else {
+            self.status
+        }
+    }
+
+    fn print(&mut self, margins: u8, palette: u8) {
+        let margin_before = (margins >> 4) as usize * MARGIN_LINES;
+        let margin_after = (margins & 0x0F) as usize * MARGIN_LINES;
+
+        // パレットが0の場合は標準のパレットとして扱うソフトが多い
+        let palette = if palette == 0 { 0xE4 } else { palette };
+
+        self.paper.resize(self.paper.len() + margin_before * WIDTH, COLORS[0]);
+
+        for tile_row in self.image.chunks_exact(TILE_ROW_BYTES) {
+            for y in 0..8 {
+                for tile in tile_row.chunks_exact(16) {
+                    let colors = decode_tile_row(tile[y * 2], tile[y * 2 + 1]);
+                    for color in colors {
+                        let shade = (palette >> (color * 2)) & 0x03;
+                        self.paper.push(COLORS[shade as usize]);
+                    }
+                }
+            }
+        }
+
+        // 後ろに余白がある場合はそこで紙を切る
+        if margin_after > 0 {
+            self.paper.resize(self.paper.len() + margin_after * WIDTH, COLORS[0]);
+            self.cut_paper();
+        }
+    }
+
+    // 印刷中の紙を1枚の画像として保存する
+    fn cut_paper(&mut self) {
+        let paper = std::mem::take(&mut self.paper);
+        if !paper.is_empty() {
+            let result = self.save(&paper);
+            self.printouts.push(result);
+        }
+    }
+
+    fn save(&self, paper: &[[u8; 4]]) -> Result<PathBuf> {
+        fs::create_dir_all(&self.output_dir).with_context(|| format!("failed to create {}", self.output_dir.display()))?;
+
+        // 既存のファイルを上書きしないように、空いている番号を使う
+        let path = (1..)
+            .map(|n| self.output_dir.join(format!("print_{:04}.png", n)))
+            .find(|path| !path.exists())
+            .unwrap();
+
+        screenshot::save_png(&path, paper, WIDTH as u32, (paper.len() / WIDTH) as u32)?;
+        Ok(path)
+    }
+}
+
+impl SerialEndpoint for Printer {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.receive(data)
+    }
+
+    // 後ろの余白なしで印刷したまま次のデータが来なければ、そこまでを1枚として保存する
+    fn poll_external(&mut self, _data: Option<u8>) -> Option<u8> {
+        if !self.paper.is_empty() {
+            self.idle_polls += 1;
+            if self.idle_polls >= IDLE_POLLS {
+                self.cut_paper();
+            }
+        }
+        None
+    }
+
+    fn take_printouts(&mut self) -> Vec<Result<PathBuf>> {
+        std::mem::take(&mut self.printouts)
+    }
+}
+
+// プリンタのRLE圧縮を展開する
+// 制御バイトのbit 7が立っていれば次の1byteを(下位7bit + 2)回、そうでなければ続く(下位7bit + 1)byteをそのまま使う
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+
+        if (control & 0x80) == 0x80 {
+            let len = (control & 0x7F) as usize + 2;
+            if let Some(&value) = data.get(i) {
+                ret.resize(ret.len() + len, value);
+            }
+            i += 1;
+        }
+        else {
+            let len = control as usize + 1;
+            let end = (i + len).min(data.len());
+            ret.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    ret
+}
@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Context, Result};
+
+use crate::joypad::Button;
+
+// スナップショットを取る間隔(フレーム数)と、残しておく数。合わせて約60秒分
+pub const DEFAULT_INTERVAL: usize = 5;
+pub const DEFAULT_CAPACITY: usize = 720;
+
+// 巻き戻しの起点になるCPUとバスの状態
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    // 電源を入れてから実行したステップ数
+    pub steps: u64,
+    // 押されていたボタン。ボタンの状態はセーブステートに含まれないので別に持つ
+    pub buttons: u8,
+    pub state: Vec<u8>
+}
+
+// 古いスナップショットは、1つ新しいスナップショットとの差分を圧縮して持つ
+struct Delta {
+    steps: u64,
+    buttons: u8,
+    data: Vec<u8>
+}
+
+// スナップショットを新しい方からたどる位置
+pub struct Cursor {
+    // まだ当てていない差分の数
+    remaining: usize,
+    // 直前に復元した状態
+    state: Option<Vec<u8>>
+}
+
+// 一定フレームごとのスナップショットのリングバッファ
+// 最新のものだけをそのまま持ち、それより古いものは新しい方から順に差分を当てて復元する
+// スナップショットの間の命令へは、記録したボタン入力を再現しながら実行し直して戻る
+pub struct Rewind {
+    enabled: bool,
+    interval: usize,
+    capacity: usize,
+    // 前回スナップショットを取ってからのフレーム数
+    frames: usize,
+    deltas: VecDeque<Delta>,
+    latest: Option<Snapshot>,
+    // ボタン入力とそのときのステップ数。古い順に並ぶ
+    inputs: VecDeque<(u64, Button, bool)>,
+    // シリアル通信で受信したバイトとそのステップ数。古い順に並ぶ
+    received: VecDeque<(u64, u8)>
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self {
+            enabled: Default::default(),
+            interval: DEFAULT_INTERVAL,
+            capacity: DEFAULT_CAPACITY,
+            frames: Default::default(),
+            deltas: Default::default(),
+            latest: Default::default(),
+            inputs: Default::default(),
+            received: Default::default()
+        }
+    }
+}
+
+impl Rewind {
+    // interval フレームごとにスナップショットを取り、capacity 個まで残す
+    pub fn enable(&mut self, interval: usize, capacity: usize) {
+        self.clear();
+        self.enabled = true;
+        self.interval = interval.max(1);
+        self.capacity = capacity.max(1);
+    }
+
+    pub fn disable(&mut self) {
+        self.clear();
+        self.enabled = false;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // セーブステートの読み込みやリセットで、それまでの履歴がつながらなくなったときに捨てる
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.deltas.clear();
+        self.latest = None;
+        self.inputs.clear();
+        self.received.clear();
+    }
+
+    // 残っているスナップショットの数
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    // 1フレーム分の実行を終えたときに呼ぶ。スナップショットを取る場合はtrueを返す
+    pub fn end_frame(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.frames += 1;
+        if self.frames < self.interval {
+            return false;
+        }
+        self.frames = 0;
+        true
+    }
+
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(Delta {
+                steps: latest.steps,
+                buttons: latest.buttons,
+                data: diff(&snapshot.state, &latest.state)
+            });
+        }
+        self.latest = Some(snapshot);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+        if let Some(oldest) = self.oldest_steps() {
+            while matches!(self.inputs.front(), Some((steps, ..)) if *steps < oldest) {
+                self.inputs.pop_front();
+            }
+            while matches!(self.received.front(), Some((steps, _)) if *steps < oldest) {
+                self.received.pop_front();
+            }
+        }
+    }
+
+    pub fn record_input(&mut self, steps: u64, button: Button, pressed: bool) {
+        if self.enabled && self.latest.is_some() {
+            self.inputs.push_back((steps, button, pressed));
+        }
+    }
+
+    // 指定したステップ数の命令を実行する前に押された、または離されたボタン
+    pub fn inputs_at(&self, steps: u64) -> impl Iterator<Item = (Button, bool)> + '_ {
+        let start = self.inputs.partition_point(|(s, ..)| *s < steps);
+        self.inputs.range(start..).take_while(move |(s, ..)| *s == steps).map(|(_, button, pressed)| (*button, *pressed))
+    }
+
+    pub fn record_received(&mut self, steps: u64, data: u8) {
+        if self.enabled && self.latest.is_some() {
+            self.received.push_back((steps, data));
+        }
+    }
+
+    // 指定したステップ数の命令でシリアル通信の相手から受信したバイト
+    pub fn received_at(&self, steps: u64) -> Option<u8> {
+        let idx = self.received.partition_point(|(s, _)| *s < steps);
+        self.received.get(idx).filter(|(s, _)| *s == steps).map(|(_, data)| *data)
+    }
+
+    pub fn oldest_steps(&self) -> Option<u64> {
+        self.deltas.front().map(|delta| delta.steps).or(self.latest.as_ref().map(|latest| latest.steps))
+    }
+
+    // target以前で最も新しいスナップショットを復元する。なければNone
+    pub fn find(&self, target: u64) -> Result<Option<Snapshot>> {
+        let mut cursor = self.cursor();
+        while let Some(snapshot) = self.next_back(&mut cursor)? {
+            if snapshot.steps <= target {
+                return Ok(Some(snapshot));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            remaining: self.deltas.len(),
+            state: None
+        }
+    }
+
+    // 新しい方から順にスナップショットを復元する。全て返したらNone
+    // 差分は1つ前に復元した状態に当てるので、バッファ全体をたどっても差分を当てるのは1回ずつで済む
+    pub fn next_back(&self, cursor: &mut Cursor) -> Result<Option<Snapshot>> {
+        let snapshot = match cursor.state.take() {
+            None => match &self.latest {
+                Some(latest) if cursor.remaining == self.deltas.len() => latest.clone(),
+                _ => return Ok(None)
+            },
+            Some(state) => {
+                if cursor.remaining == 0 {
+                    cursor.state = Some(state);
+                    return Ok(None);
+                }
+                cursor.remaining -= 1;
+                let delta = &self.deltas[cursor.remaining];
+                Snapshot { steps: delta.steps, buttons: delta.buttons, state: apply(&state, &delta.data)? }
+            }
+        };
+        cursor.state = Some(snapshot.state.clone());
+        Ok(Some(snapshot))
+    }
+
+    // currentより前で最も新しいスナップショットのステップ数
+    pub fn previous(&self, current: u64) -> Option<u64> {
+        match &self.latest {
+            Some(latest) if latest.steps < current => Some(latest.steps),
+            Some(_) => self.deltas.iter().rev().find(|delta| delta.steps < current).map(|delta| delta.steps),
+            None => None
+        }
+    }
+
+    // targetまで戻ったら、それより後のスナップショットと入力は別の歴史になるので捨てる
+    pub fn truncate(&mut self, target: u64) -> Result<()> {
+        while let Some(latest) = self.latest.take() {
+            if latest.steps <= target {
+                self.latest = Some(latest);
+                break;
+            }
+            if let Some(delta) = self.deltas.pop_back() {
+                let state = apply(&latest.state, &delta.data)?;
+                self.latest = Some(Snapshot { steps: delta.steps, buttons: delta.buttons, state });
+            }
+        }
+        while matches!(self.inputs.back(), Some((steps, ..)) if *steps > target) {
+            self.inputs.pop_back();
+        }
+        // targetの命令はこれから実行し直すので、そのときに受信したものも捨てる
+        while matches!(self.received.back(), Some((steps, _)) if *steps >= target) {
+            self.received.pop_back();
+        }
+        self.frames = 0;
+        Ok(())
+    }
+}
+
+// baseとの排他的論理和を取り、0が続く部分を詰める
+// targetの長さ(u32)に続けて「0の数、そのまま置く数、その数だけのバイト列」を繰り返す。数はLEB128
+pub fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| target[i] ^ base.get(i).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    out.extend_from_slice(&(target.len() as u32).to_le_bytes());
+
+    let mut i = 0;
+    while i < target.len() {
+        let zero_start = i;
+        while i < target.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < target.len() && xor(i) != 0 {
+            i += 1;
+        }
+
+        write_varint(&mut out, literal_start - zero_start);
+        write_varint(&mut out, i - literal_start);
+        out.extend((literal_start..i).map(xor));
+    }
+    out
+}
+
+// diffで作った差分をbaseに当てて、元のtargetを復元する
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let header = delta.get(..4).context("rewind delta is too short")?;
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let base_at = |i: usize| base.get(i).copied().unwrap_or(0);
+
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 4;
+    while out.len() < len {
+        let zeros = read_varint(delta, &mut pos)?;
+        let literals = read_varint(delta, &mut pos)?;
+        if out.len() + zeros + literals > len {
+            bail!("rewind delta is longer than its state");
+        }
+
+        for _ in 0..zeros {
+            out.push(base_at(out.len()));
+        }
+        let data = delta.get(pos..pos + literals).context("rewind delta is too short")?;
+        for v in data {
+            out.push(v ^ base_at(out.len()));
+        }
+        pos += literals;
+    }
+    Ok(out)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).context("rewind delta is too short")?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            bail!("rewind delta has an invalid length");
+        }
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
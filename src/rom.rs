@@ -1,4 +1,5 @@
 use anyhow::{bail, Result};
+use std::fmt;
 use std::io::{BufReader, Seek, SeekFrom, Read};
 use std::fs::File;
 
@@ -24,6 +25,142 @@ impl Default for DestinationCode {
     }
 }
 
+// ヘッダの0x147に書かれているカートリッジの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery
+}
+
+impl Default for CartridgeType {
+    fn default() -> Self {
+        CartridgeType::RomOnly
+    }
+}
+
+impl CartridgeType {
+    pub fn from_u8(data: u8) -> Option<Self> {
+        let ret = match data {
+            0x00 => CartridgeType::RomOnly,
+            0x01 => CartridgeType::Mbc1,
+            0x02 => CartridgeType::Mbc1Ram,
+            0x03 => CartridgeType::Mbc1RamBattery,
+            0x05 => CartridgeType::Mbc2,
+            0x06 => CartridgeType::Mbc2Battery,
+            0x08 => CartridgeType::RomRam,
+            0x09 => CartridgeType::RomRamBattery,
+            0x0B => CartridgeType::Mmm01,
+            0x0C => CartridgeType::Mmm01Ram,
+            0x0D => CartridgeType::Mmm01RamBattery,
+            0x0F => CartridgeType::Mbc3TimerBattery,
+            0x10 => CartridgeType::Mbc3TimerRamBattery,
+            0x11 => CartridgeType::Mbc3,
+            0x12 => CartridgeType::Mbc3Ram,
+            0x13 => CartridgeType::Mbc3RamBattery,
+            0x19 => CartridgeType::Mbc5,
+            0x1A => CartridgeType::Mbc5Ram,
+            0x1B => CartridgeType::Mbc5RamBattery,
+            0x1C => CartridgeType::Mbc5Rumble,
+            0x1D => CartridgeType::Mbc5RumbleRam,
+            0x1E => CartridgeType::Mbc5RumbleRamBattery,
+            0x20 => CartridgeType::Mbc6,
+            0x22 => CartridgeType::Mbc7SensorRumbleRamBattery,
+            0xFC => CartridgeType::PocketCamera,
+            0xFD => CartridgeType::BandaiTama5,
+            0xFE => CartridgeType::HuC3,
+            0xFF => CartridgeType::HuC1RamBattery,
+            _ => return None
+        };
+        Some(ret)
+    }
+
+    // バッテリーバックアップを持つかどうか
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::Mbc1RamBattery
+                | CartridgeType::Mbc2Battery
+                | CartridgeType::RomRamBattery
+                | CartridgeType::Mmm01RamBattery
+                | CartridgeType::Mbc3TimerBattery
+                | CartridgeType::Mbc3TimerRamBattery
+                | CartridgeType::Mbc3RamBattery
+                | CartridgeType::Mbc5RamBattery
+                | CartridgeType::Mbc5RumbleRamBattery
+                | CartridgeType::Mbc7SensorRumbleRamBattery
+                | CartridgeType::HuC1RamBattery
+        )
+    }
+
+    // リアルタイムクロックを持つかどうか
+    pub fn has_timer(&self) -> bool {
+        matches!(self, CartridgeType::Mbc3TimerBattery | CartridgeType::Mbc3TimerRamBattery)
+    }
+}
+
+// Pan Docsの表記に合わせる
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CartridgeType::RomOnly => "ROM ONLY",
+            CartridgeType::Mbc1 => "MBC1",
+            CartridgeType::Mbc1Ram => "MBC1+RAM",
+            CartridgeType::Mbc1RamBattery => "MBC1+RAM+BATTERY",
+            CartridgeType::Mbc2 => "MBC2",
+            CartridgeType::Mbc2Battery => "MBC2+BATTERY",
+            CartridgeType::RomRam => "ROM+RAM",
+            CartridgeType::RomRamBattery => "ROM+RAM+BATTERY",
+            CartridgeType::Mmm01 => "MMM01",
+            CartridgeType::Mmm01Ram => "MMM01+RAM",
+            CartridgeType::Mmm01RamBattery => "MMM01+RAM+BATTERY",
+            CartridgeType::Mbc3TimerBattery => "MBC3+TIMER+BATTERY",
+            CartridgeType::Mbc3TimerRamBattery => "MBC3+TIMER+RAM+BATTERY",
+            CartridgeType::Mbc3 => "MBC3",
+            CartridgeType::Mbc3Ram => "MBC3+RAM",
+            CartridgeType::Mbc3RamBattery => "MBC3+RAM+BATTERY",
+            CartridgeType::Mbc5 => "MBC5",
+            CartridgeType::Mbc5Ram => "MBC5+RAM",
+            CartridgeType::Mbc5RamBattery => "MBC5+RAM+BATTERY",
+            CartridgeType::Mbc5Rumble => "MBC5+RUMBLE",
+            CartridgeType::Mbc5RumbleRam => "MBC5+RUMBLE+RAM",
+            CartridgeType::Mbc5RumbleRamBattery => "MBC5+RUMBLE+RAM+BATTERY",
+            CartridgeType::Mbc6 => "MBC6",
+            CartridgeType::Mbc7SensorRumbleRamBattery => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            CartridgeType::PocketCamera => "POCKET CAMERA",
+            CartridgeType::BandaiTama5 => "BANDAI TAMA5",
+            CartridgeType::HuC3 => "HuC3",
+            CartridgeType::HuC1RamBattery => "HuC1+RAM+BATTERY"
+        };
+        write!(f, "{}", name)
+    }
+}
+
 pub struct Rom {
     pub entry_point: [u8; 4],
     pub logo: [u8; 0x0030],
@@ -32,7 +169,7 @@ pub struct Rom {
     pub cgb_flag: CGBMode,
     pub licensee_code: [u8; 2],
     pub sgb_flag: bool,
-    pub cartridge_type: u8,
+    pub cartridge_type: CartridgeType,
     pub rom_size: u8,
     pub ram_size: u8,
     pub destination_code: DestinationCode,
@@ -67,6 +204,30 @@ impl Default for Rom {
 }
 
 impl Rom {
+    // ヘッダに書かれているRAMサイズをバイト数にする
+    pub fn ram_bytes(&self) -> usize {
+        match self.ram_size {
+            // 0x01は公式には未使用だが、2KBとして扱われることがある
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0
+        }
+    }
+
+    // ヘッダに書かれているROMサイズを16KBのバンク数にする
+    pub fn rom_banks(&self) -> usize {
+        match self.rom_size {
+            0x00..=0x08 => 2 << self.rom_size,
+            0x52 => 72,
+            0x53 => 80,
+            0x54 => 96,
+            _ => 2
+        }
+    }
+
     pub fn new<T>(reader: &mut T) -> Result<Rom> 
         where T: Read + Seek,
     {
@@ -103,9 +264,12 @@ impl Rom {
             _ => bail!("fail! SGBFlag is broken or there is unexpected EOF in SGB flag")
         };
 
-        // MBCのタイプについての読み込み。TODO: そのうちここもEnumにする？
+        // MBCのタイプについての読み込み
         rom.cartridge_type = match reader.take(1).bytes().next() {
-            Some(Ok(res)) => res,
+            Some(Ok(res)) => match CartridgeType::from_u8(res) {
+                Some(cartridge_type) => cartridge_type,
+                None => bail!("fail! Unknown cartridge type: {:#04X}", res)
+            },
             Some(Err(_err)) => bail!("fail! a byte data of cartridge type is broken"),
             None => bail!("fail! There is unexpected EOF in cartridge type")
         };
@@ -160,19 +324,13 @@ impl Rom {
         reader.read_exact(&mut rom.global_check_sum[..])?;
 
         // headerのチェックサムを計算する
-        let mut header_checksum: u8 = 0;
+        let mut header = [0; 0x14D - 0x134];
         reader.seek(SeekFrom::Start(0x134))?;
-
-        for _ in 0x134..=0x14C {
-            let v = match reader.take(1).bytes().next() {
-                Some(Ok(res)) => res,
-                _ => bail!("fail! Some error occured in calculating checksum")
-            };
-
-            header_checksum = header_checksum.wrapping_sub(v).wrapping_sub(1);
+        if reader.read_exact(&mut header).is_err() {
+            bail!("fail! Some error occured in calculating checksum");
         }
 
-        if header_checksum != rom.header_check_sum {
+        if header_checksum(&header) != rom.header_check_sum {
             bail!("Actual checksum is different from header checksum which is in ROM");
         }
 
@@ -196,3 +354,26 @@ impl Rom {
         Ok(rom)
     }
 }
+
+// 0x0134-0x014Cのヘッダから計算したチェックサム
+pub fn header_checksum(header: &[u8]) -> u8 {
+    header.iter().fold(0u8, |checksum, v| checksum.wrapping_sub(*v).wrapping_sub(1))
+}
+
+// テスト用に、プログラムを指定したアドレスに置いた32KBのROMを作る。0x0150から実行する
+// 結合テストからも使うので、cfg(test)にはしない
+#[doc(hidden)]
+pub fn build_test_rom(title: &[u8], cartridge_type: u8, parts: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // NOP; JP 0x0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x134..0x134 + title.len()].copy_from_slice(title);
+    rom[0x147] = cartridge_type;
+
+    for (address, program) in parts {
+        rom[*address..*address + program.len()].copy_from_slice(program);
+    }
+
+    rom[0x14D] = header_checksum(&rom[0x134..0x14D]);
+    rom
+}
@@ -0,0 +1,336 @@
+use anyhow::{bail, Result};
+
+use crate::state::{StateReader, StateWriter};
+
+// CPUのクロック周波数。このサイクル数で1秒進む
+const CYCLES_PER_SECOND: usize = 4194304;
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+// 日数カウンタの上位bit、停止フラグ、繰り上がりフラグ
+const DAY_HIGH_BIT: u8 = 0x01;
+const HALT_BIT: u8 = 0x40;
+const CARRY_BIT: u8 = 0x80;
+
+// セーブファイルの末尾に付けるRTCの状態のサイズ
+// 現在のレジスタ5つ、ラッチされたレジスタ5つ(各4byte)とUNIX時間(8byte)
+pub const FOOTER_SIZE: usize = 48;
+// UNIX時間が4byteの古い形式
+pub const FOOTER_SIZE_32: usize = 44;
+
+// MBC3のリアルタイムクロック
+#[derive(Debug, Default, Clone)]
+pub struct Rtc {
+    // 0: 秒, 1: 分, 2: 時, 3: 日数の下位8bit, 4: 日数の上位bitと停止・繰り上がりフラグ
+    registers: [u8; 5],
+    // ラッチした時点のレジスタ。ゲームから読めるのはこちら
+    latched: [u8; 5],
+    // ラッチは0を書き込んだ後に1を書き込むと行われる
+    latch_ready: bool,
+    // 1秒に満たないサイクル数
+    cycles: usize
+}
+
+impl Rtc {
+    // エミュレータが動いている間はCPUのサイクル数で時計を進める
+    pub fn tick(&mut self, cycles: u8) {
+        if self.is_halted() {
+            return;
+        }
+
+        self.cycles += cycles as usize;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.advance_second();
+        }
+    }
+
+    pub fn write_latch(&mut self, data: u8) {
+        if self.latch_ready && data == 0x01 {
+            self.latched = self.registers;
+        }
+        self.latch_ready = data == 0x00;
+    }
+
+    // registerは0x08から0x0C
+    pub fn read(&self, register: u8) -> u8 {
+        let idx = (register - 0x08) as usize;
+        self.latched[idx] & Self::mask(idx)
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        let idx = (register - 0x08) as usize;
+        let data = data & Self::mask(idx);
+
+        // 秒を書き込むと1秒未満のカウンタもリセットされる
+        if idx == 0 {
+            self.cycles = 0;
+        }
+
+        self.registers[idx] = data;
+        self.latched[idx] = data;
+    }
+
+    fn mask(idx: usize) -> u8 {
+        match idx {
+            0 | 1 => 0x3F,
+            2 => 0x1F,
+            3 => 0xFF,
+            _ => DAY_HIGH_BIT | HALT_BIT | CARRY_BIT
+        }
+    }
+
+    fn is_halted(&self) -> bool {
+        (self.registers[4] & HALT_BIT) == HALT_BIT
+    }
+
+    fn day(&self) -> u16 {
+        self.registers[3] as u16 | (((self.registers[4] & DAY_HIGH_BIT) as u16) << 8)
+    }
+
+    fn set_day(&mut self, day: u16) {
+        self.registers[3] = day as u8;
+        self.registers[4] = (self.registers[4] & !DAY_HIGH_BIT) | ((day >> 8) as u8 & DAY_HIGH_BIT);
+    }
+
+    // 日数カウンタを進める。511日を超えたら0に戻して繰り上がりフラグを立てる
+    fn advance_days(&mut self, days: u64) {
+        let day = self.day() as u64 + days;
+        if day > 0x1FF {
+            self.registers[4] |= CARRY_BIT;
+        }
+        self.set_day((day & 0x1FF) as u16);
+    }
+
+    fn advance_second(&mut self) {
+        // 範囲外の値が書き込まれている場合は、各レジスタのbit幅で一周するまで繰り上がらない
+        self.registers[0] = (self.registers[0] + 1) & 0x3F;
+        if self.registers[0] != 60 {
+            return;
+        }
+        self.registers[0] = 0;
+
+        self.registers[1] = (self.registers[1] + 1) & 0x3F;
+        if self.registers[1] != 60 {
+            return;
+        }
+        self.registers[1] = 0;
+
+        self.registers[2] = (self.registers[2] + 1) & 0x1F;
+        if self.registers[2] != 24 {
+            return;
+        }
+        self.registers[2] = 0;
+
+        self.advance_days(1);
+    }
+
+    // エミュレータを終了していた間の時間をまとめて進める
+    pub fn advance_seconds(&mut self, seconds: u64) {
+        if self.is_halted() {
+            return;
+        }
+
+        self.advance_days(seconds / SECONDS_PER_DAY);
+        for _ in 0..(seconds % SECONDS_PER_DAY) {
+            self.advance_second();
+        }
+    }
+
+    // セーブファイルの末尾に付けるRTCの状態(リトルエンディアン)
+    pub fn footer(&self, timestamp: u64) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+        for register in self.registers.iter().chain(self.latched.iter()) {
+            footer.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        footer.extend_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+
+    // セーブファイル末尾のRTCの状態を読み込み、保存されてからnowまでの時間だけ時計を進める
+    pub fn load_footer(&mut self, footer: &[u8], now: u64) -> Result<()> {
+        let timestamp = match footer.len() {
+            FOOTER_SIZE => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&footer[40..48]);
+                u64::from_le_bytes(bytes)
+            },
+            FOOTER_SIZE_32 => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(&footer[40..44]);
+                u32::from_le_bytes(bytes) as u64
+            },
+            _ => bail!("invalid RTC footer size: {}", footer.len())
+        };
+
+        for (i, chunk) in footer[..40].chunks_exact(4).enumerate() {
+            let data = chunk[0] & Self::mask(i % 5);
+            if i < 5 {
+                self.registers[i] = data;
+            }
+            else {
+                self.latched[i - 5] = data;
+            }
+        }
+        self.cycles = 0;
+
+        self.advance_seconds(now.saturating_sub(timestamp));
+        Ok(())
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.registers);
+        writer.write_bytes(&self.latched);
+        writer.write_bool(self.latch_ready);
+        writer.write_u32(self.cycles as u32);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        reader.read_bytes(&mut self.registers)?;
+        reader.read_bytes(&mut self.latched)?;
+        self.latch_ready = reader.read_bool()?;
+        self.cycles = reader.read_u32()? as usize;
+        Ok(())
+    }
+}
+
+// ホストの現在時刻(UNIX時間、秒)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unix_time() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn unix_time() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ラッチしてから5つのレジスタを読む
+    fn latch_and_read(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| rtc.read(register))
+    }
+
+    fn set_time(rtc: &mut Rtc, time: [u8; 5]) {
+        for (i, data) in time.iter().enumerate() {
+            rtc.write(0x08 + i as u8, *data);
+        }
+    }
+
+    fn tick_seconds(rtc: &mut Rtc, seconds: usize) {
+        for _ in 0..seconds * CYCLES_PER_SECOND / 128 {
+            rtc.tick(128);
+        }
+    }
+
+    #[test]
+    fn latch_on_zero_then_one() {
+        let mut rtc = Rtc::default();
+        set_time(&mut rtc, [10, 0, 0, 0, 0]);
+        tick_seconds(&mut rtc, 1);
+
+        // ラッチするまでは書き込んだときの値が読める
+        assert_eq!(rtc.read(0x08), 10);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 10);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x02);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 10);
+
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 11);
+
+        // ラッチした後に進んだ時間は、次にラッチするまで見えない
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(rtc.read(0x08), 11);
+        assert_eq!(latch_and_read(&mut rtc)[0], 12);
+    }
+
+    #[test]
+    fn carry_to_days() {
+        let mut rtc = Rtc::default();
+        set_time(&mut rtc, [59, 59, 23, 0xFF, 0x00]);
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(latch_and_read(&mut rtc), [0, 0, 0, 0x00, DAY_HIGH_BIT]);
+
+        set_time(&mut rtc, [58, 59, 22, 0x00, 0x00]);
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(latch_and_read(&mut rtc), [59, 59, 22, 0x00, 0x00]);
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(latch_and_read(&mut rtc), [0, 0, 23, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn day_counter_overflow() {
+        let mut rtc = Rtc::default();
+        set_time(&mut rtc, [59, 59, 23, 0xFF, DAY_HIGH_BIT]);
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(latch_and_read(&mut rtc), [0, 0, 0, 0x00, CARRY_BIT]);
+
+        // 繰り上がりフラグは書き込んで消すまで残る
+        rtc.advance_seconds(SECONDS_PER_DAY * 3);
+        assert_eq!(latch_and_read(&mut rtc), [0, 0, 0, 0x03, CARRY_BIT]);
+        rtc.write(0x0C, 0x00);
+        assert_eq!(latch_and_read(&mut rtc)[4], 0x00);
+    }
+
+    #[test]
+    fn halt_stops_clock() {
+        let mut rtc = Rtc::default();
+        set_time(&mut rtc, [30, 20, 10, 0x05, HALT_BIT]);
+        tick_seconds(&mut rtc, 2);
+        rtc.advance_seconds(SECONDS_PER_DAY + 1);
+        assert_eq!(latch_and_read(&mut rtc), [30, 20, 10, 0x05, HALT_BIT]);
+
+        rtc.write(0x0C, 0x00);
+        tick_seconds(&mut rtc, 2);
+        assert_eq!(latch_and_read(&mut rtc), [32, 20, 10, 0x05, 0x00]);
+    }
+
+    #[test]
+    fn advance_by_wall_time_on_load() {
+        let mut rtc = Rtc::default();
+        set_time(&mut rtc, [1, 2, 3, 0x04, 0x00]);
+        let footer = rtc.footer(1_000_000);
+
+        // 1日と1時間1分1秒たってから読み込む
+        let mut loaded = Rtc::default();
+        loaded.load_footer(&footer, 1_000_000 + SECONDS_PER_DAY + 3661).unwrap();
+        assert_eq!(latch_and_read(&mut loaded), [2, 3, 4, 0x05, 0x00]);
+
+        // 保存した時刻より前に戻っていても進めない
+        let mut loaded = Rtc::default();
+        loaded.load_footer(&footer, 999_000).unwrap();
+        assert_eq!(latch_and_read(&mut loaded), [1, 2, 3, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut rtc = Rtc::default();
+        set_time(&mut rtc, [5, 6, 7, 0x08, DAY_HIGH_BIT]);
+        let footer = rtc.footer(0x1234_5678);
+        assert_eq!(footer.len(), FOOTER_SIZE);
+        assert_eq!(&footer[..4], &[5, 0, 0, 0]);
+        assert_eq!(&footer[40..], &0x1234_5678_u64.to_le_bytes());
+
+        let mut loaded = Rtc::default();
+        loaded.load_footer(&footer, 0x1234_5678).unwrap();
+        assert_eq!(loaded.footer(0x1234_5678), footer);
+
+        // UNIX時間が4byteの古い形式も読める
+        let mut loaded = Rtc::default();
+        loaded.load_footer(&footer[..FOOTER_SIZE_32], 0x1234_5678).unwrap();
+        assert_eq!(loaded.footer(0x1234_5678), footer);
+
+        assert!(Rtc::default().load_footer(&footer[..40], 0).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::rom::Rom;
+
+// セーブディレクトリが指定されなかった場合の保存先
+pub const DEFAULT_SAVE_DIR: &str = "saves";
+
+// ROMごとのセーブファイル名。ヘッダのタイトルとグローバルチェックサムから決める
+// タイトルが同じでもチェックサムが違えば別のファイルになる
+pub fn save_file_name(rom: &Rom) -> String {
+    let title: String = rom.title.iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| if c.is_ascii_alphanumeric() { c as char } else { '_' })
+        .collect();
+
+    let title = if title.is_empty() { "UNTITLED".to_string() } else { title };
+
+    format!("{}_{:02X}{:02X}.sav", title, rom.global_check_sum[0], rom.global_check_sum[1])
+}
+
+// バッテリーバックアップの保存先
+// ネイティブ版はファイル、Web版はLocalStorageに保存する
+pub trait SaveStorage {
+    // 保存されているデータを読み込む。まだ存在しない場合はNoneを返す
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>>;
+    fn write(&mut self, name: &str, data: &[u8]) -> Result<()>;
+}
+
+// 指定したディレクトリにセーブファイルとして保存する
+pub struct FileStorage {
+    dir: PathBuf
+}
+
+impl FileStorage {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf()
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl SaveStorage for FileStorage {
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        read_save_file(&self.path(name))
+    }
+
+    fn write(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        write_save_file(&self.path(name), data)
+    }
+}
+
+// セーブファイルを読み込む。まだ存在しない場合はNoneを返す
+pub fn read_save_file(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display()))
+    }
+}
+
+// 一時ファイルに書き出してからリネームする
+// 書き込み途中で落ちても、元のセーブファイルが中途半端な状態で残ることはない
+pub fn write_save_file(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    let tmp_path = path.with_extension("sav.tmp");
+    {
+        let mut file = File::create(&tmp_path).with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&tmp_path, path).with_context(|| format!("failed to rename {} to {}", tmp_path.display(), path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::{Mbc, Mbc5};
+    use crate::rom::CartridgeType;
+
+    // テストごとに別の一時ディレクトリを使う
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("game_boy_save_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn rom_with_title(title: &[u8], global_check_sum: [u8; 2]) -> Rom {
+        let mut rom = Rom { global_check_sum, ..Default::default() };
+        rom.title[..title.len()].copy_from_slice(title);
+        rom
+    }
+
+    #[test]
+    fn file_name_from_title_and_checksum() {
+        assert_eq!(save_file_name(&rom_with_title(b"POKEMON RED", [0x91, 0xE6])), "POKEMON_RED_91E6.sav");
+        // NUL以降は使わず、英数字以外は_にする
+        assert_eq!(save_file_name(&rom_with_title(b"A/B\0C", [0x00, 0x0A])), "A_B_000A.sav");
+        assert_eq!(save_file_name(&rom_with_title(b"", [0x12, 0x34])), "UNTITLED_1234.sav");
+    }
+
+    #[test]
+    fn atomic_write() {
+        let dir = temp_dir("write");
+        let path = dir.join("GAME_0000.sav");
+        assert_eq!(read_save_file(&path).unwrap(), None);
+
+        // ディレクトリがなければ作る
+        write_save_file(&path, &[1, 2, 3]).unwrap();
+        assert_eq!(read_save_file(&path).unwrap(), Some(vec![1, 2, 3]));
+
+        // 上書きしても一時ファイルは残らない
+        write_save_file(&path, &[4, 5]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![4, 5]);
+        assert!(!path.with_extension("sav.tmp").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mbc5_loads_save_file() {
+        let dir = temp_dir("mbc5");
+        let mut storage = FileStorage::new(&dir);
+        let data: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+        storage.write("MBC5_0000.sav", &data).unwrap();
+
+        let mut mbc = Mbc5 {
+            mbc_type: CartridgeType::Mbc5RamBattery,
+            rom: Rom { data: vec![0; 0x8000], ..Default::default() },
+            is_external_ram_enable: false,
+            rom_bank_number_low: 1,
+            rom_bank_number_high: false,
+            ram_bank_number: 0,
+            ram: vec![0; 0x2000],
+            dirty: false
+        };
+        assert!(mbc.has_battery());
+        mbc.load_save_data(&storage.read("MBC5_0000.sav").unwrap().unwrap()).unwrap();
+
+        // 読み込むだけでセーブファイルは書き換えない
+        assert!(!mbc.is_dirty());
+        assert_eq!(fs::read(storage.path("MBC5_0000.sav")).unwrap(), data);
+
+        mbc.write_registers(0x0000, 0x0A).unwrap();
+        assert_eq!(mbc.read_ram(0xA000).unwrap(), 0x00);
+        assert_eq!(mbc.read_ram(0xA123).unwrap(), 0x23);
+        assert_eq!(mbc.save_data(), data);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
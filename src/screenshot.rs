@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+// RGBAのピクセル列を画像ファイルとして書き出す。拡張子がpngならPNG、それ以外はPPM(P6)とする
+pub fn save(path: &Path, pixels: &[[u8; 4]], width: u32, height: u32) -> Result<()> {
+    let is_png = path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("png"))
+        .unwrap_or(false);
+
+    if is_png {
+        save_png(path, pixels, width, height)
+    }
+    else {
+        save_ppm(path, pixels, width, height)
+    }
+}
+
+pub fn save_png(path: &Path, pixels: &[[u8; 4]], width: u32, height: u32) -> Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pixels.concat())?;
+
+    Ok(())
+}
+
+pub fn save_ppm(path: &Path, pixels: &[[u8; 4]], width: u32, height: u32) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+
+    // PPMはアルファチャンネルを持たないのでRGBだけ書き出す
+    for pixel in pixels {
+        writer.write_all(&pixel[0..3])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,379 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use crate::state::{StateReader, StateWriter};
+
+// 内部クロック(8192Hz)で1bit送るのにかかるサイクル数
+const CYCLES_PER_BIT: usize = 512;
+
+// シリアル通信の相手
+pub trait SerialEndpoint {
+    // 内部クロックで転送を始めたときに呼ばれる。送信したバイトを渡し、相手から受信したバイトを返す
+    fn exchange(&mut self, data: u8) -> u8;
+
+    // 内部クロックで転送していない間、1bit分のサイクルごとに呼ばれる
+    // dataは外部クロックで転送を待っている場合だけ送信するバイトが入る
+    // 相手がクロックを供給して転送が行われた場合は、受信したバイトを返す
+    fn poll_external(&mut self, _data: Option<u8>) -> Option<u8> {
+        None
+    }
+
+    // プリンタが保存した印刷結果を取り出す。保存できなかったものはエラー
+    fn take_printouts(&mut self) -> Vec<Result<PathBuf>> {
+        Vec::new()
+    }
+}
+
+// 何もつながっていない状態。受信データは常に0xFF
+pub struct NullEndpoint;
+
+impl SerialEndpoint for NullEndpoint {
+    fn exchange(&mut self, _data: u8) -> u8 {
+        0xFF
+    }
+}
+
+// 送信したデータがそのまま返ってくる
+pub struct LoopbackEndpoint;
+
+impl SerialEndpoint for LoopbackEndpoint {
+    fn exchange(&mut self, data: u8) -> u8 {
+        data
+    }
+}
+
+// 送信されたデータをバッファに記録する。受信データは0xFF
+pub struct CaptureEndpoint {
+    buffer: Arc<Mutex<Vec<u8>>>
+}
+
+impl CaptureEndpoint {
+    pub fn new(buffer: Arc<Mutex<Vec<u8>>>) -> Self {
+        Self {
+            buffer
+        }
+    }
+}
+
+impl SerialEndpoint for CaptureEndpoint {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.buffer.lock().unwrap().push(data);
+        0xFF
+    }
+}
+
+// 巻き戻しで実行し直す間の接続相手。記録しておいた受信データを返し、相手には何も送らない
+struct ReplayEndpoint {
+    received: Option<u8>
+}
+
+impl SerialEndpoint for ReplayEndpoint {
+    fn exchange(&mut self, _data: u8) -> u8 {
+        self.received.take().unwrap_or(0xFF)
+    }
+
+    fn poll_external(&mut self, data: Option<u8>) -> Option<u8> {
+        data.and(self.received.take())
+    }
+}
+
+// 実行し直している間に退避しておく、本来の接続先と送信の記録
+type LiveLink = (Box<dyn SerialEndpoint + Send>, Option<Vec<u8>>);
+
+// シリアル通信ポート(SB/SC)
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // 転送中のバイトの残りbit数
+    remaining_bits: u8,
+    // 相手から受信したバイトのうち、まだSBにシフトインしていない部分
+    incoming: u8,
+    cycles: usize,
+    // 送信したバイトの記録。ずっと動かしていると増え続けるので、capture_outputを呼んだ場合だけ記録する
+    output: Option<Vec<u8>>,
+    endpoint: Box<dyn SerialEndpoint + Send>,
+    // 相手から受信したバイト。巻き戻しで実行し直すときのために記録する
+    received: Option<u8>,
+    live: Option<LiveLink>,
+    pub int_serial_flag: bool
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self {
+            sb: Default::default(),
+            sc: Default::default(),
+            remaining_bits: Default::default(),
+            incoming: Default::default(),
+            cycles: Default::default(),
+            output: Default::default(),
+            endpoint: Box::new(NullEndpoint),
+            received: Default::default(),
+            live: Default::default(),
+            int_serial_flag: Default::default()
+        }
+    }
+}
+
+impl Serial {
+    pub fn set_endpoint(&mut self, endpoint: Box<dyn SerialEndpoint + Send>) {
+        self.endpoint = endpoint;
+    }
+
+    // 接続先を外す。リセットしてもケーブルはつないだままにするために使う
+    pub fn take_endpoint(&mut self) -> Box<dyn SerialEndpoint + Send> {
+        std::mem::replace(&mut self.endpoint, Box::new(NullEndpoint))
+    }
+
+    pub fn take_printouts(&mut self) -> Vec<Result<PathBuf>> {
+        self.endpoint.take_printouts()
+    }
+
+    pub fn read_sb(&self) -> u8 {
+        self.sb
+    }
+
+    pub fn read_sc(&self) -> u8 {
+        // bit 1-6は未使用で常に1が読める
+        self.sc | 0x7E
+    }
+
+    pub fn write_sb(&mut self, data: u8) {
+        self.sb = data;
+    }
+
+    pub fn write_sc(&mut self, data: u8) {
+        self.sc = data;
+        self.cycles = 0;
+
+        if !self.is_transferring() {
+            self.remaining_bits = 0;
+            return;
+        }
+
+        self.remaining_bits = 8;
+
+        // 内部クロックの場合はこちらがクロックを出すので、ここで相手とバイトを交換する
+        // 受信したバイトは1bitずつSBにシフトインしていく
+        if self.is_internal_clock() {
+            self.record_output(self.sb);
+            self.incoming = self.endpoint.exchange(self.sb);
+            self.received = Some(self.incoming);
+        }
+    }
+
+    // 1サイクル分進める
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CYCLES_PER_BIT {
+            return;
+        }
+        self.cycles = 0;
+
+        if !self.is_transferring() {
+            // 転送していなくても、相手からの転送要求には応答できるようにしておく
+            self.endpoint.poll_external(None);
+        }
+        else if self.is_internal_clock() {
+            self.sb = (self.sb << 1) | (self.incoming >> 7);
+            self.incoming <<= 1;
+            self.remaining_bits -= 1;
+
+            if self.remaining_bits == 0 {
+                self.complete();
+            }
+        }
+        // 外部クロックの場合は相手がクロックを出すまで待つ
+        else if let Some(data) = self.endpoint.poll_external(Some(self.sb)) {
+            self.record_output(self.sb);
+            self.sb = data;
+            self.received = Some(data);
+            self.remaining_bits = 0;
+            self.complete();
+        }
+    }
+
+    fn complete(&mut self) {
+        self.sc &= 0x7F;
+        self.int_serial_flag = true;
+    }
+
+    fn is_transferring(&self) -> bool {
+        (self.sc & 0x80) == 0x80
+    }
+
+    fn is_internal_clock(&self) -> bool {
+        (self.sc & 0x01) == 0x01
+    }
+
+    // 前回取り出してから相手から受信したバイト
+    pub fn take_received(&mut self) -> Option<u8> {
+        self.received.take()
+    }
+
+    // 巻き戻しで実行し直す間は相手とやりとりせず、記録しておいた受信データを使う
+    // 送信したバイトも記録しない
+    pub fn start_replay(&mut self, received: Option<u8>) {
+        let endpoint = std::mem::replace(&mut self.endpoint, Box::new(ReplayEndpoint { received }));
+        self.live = Some((endpoint, self.output.take()));
+    }
+
+    pub fn finish_replay(&mut self) {
+        if let Some((endpoint, output)) = self.live.take() {
+            self.endpoint = endpoint;
+            self.output = output;
+        }
+        self.received = None;
+    }
+
+    // 送信したバイトの記録を始める。テストROMの結果をシリアル出力から読むのに使う
+    pub fn capture_output(&mut self) {
+        self.output.get_or_insert_with(Vec::new);
+    }
+
+    pub fn is_capturing_output(&self) -> bool {
+        self.output.is_some()
+    }
+
+    fn record_output(&mut self, data: u8) {
+        if let Some(output) = &mut self.output {
+            output.push(data);
+        }
+    }
+
+    // capture_outputを呼んでから送信されたバイト列
+    pub fn output(&self) -> &[u8] {
+        self.output.as_deref().unwrap_or(&[])
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.sb);
+        writer.write_u8(self.sc);
+        writer.write_u8(self.remaining_bits);
+        writer.write_u8(self.incoming);
+        writer.write_u32(self.cycles as u32);
+        writer.write_bool(self.int_serial_flag);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.sb = reader.read_u8()?;
+        self.sc = reader.read_u8()?;
+        self.remaining_bits = reader.read_u8()?;
+        self.incoming = reader.read_u8()?;
+        self.cycles = reader.read_u32()? as usize;
+        self.int_serial_flag = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::Cpu;
+    use crate::rom::build_test_rom;
+
+    // 受け取ったバイトを記録し、決まったバイトを返す
+    struct FixedEndpoint {
+        sent: Arc<Mutex<Vec<u8>>>,
+        reply: u8
+    }
+
+    impl SerialEndpoint for FixedEndpoint {
+        fn exchange(&mut self, data: u8) -> u8 {
+            self.sent.lock().unwrap().push(data);
+            self.reply
+        }
+    }
+
+    fn fixed_endpoint(reply: u8) -> (Box<FixedEndpoint>, Arc<Mutex<Vec<u8>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        (Box::new(FixedEndpoint { sent: sent.clone(), reply }), sent)
+    }
+
+    fn tick(serial: &mut Serial, cycles: usize) {
+        for _ in 0..cycles {
+            serial.tick();
+        }
+    }
+
+    #[test]
+    fn shift_timing() {
+        let mut serial = Serial::default();
+        let (endpoint, sent) = fixed_endpoint(0xA5);
+        serial.set_endpoint(endpoint);
+
+        serial.write_sb(0x42);
+        serial.write_sc(0x81);
+        assert_eq!(*sent.lock().unwrap(), vec![0x42]);
+
+        // 512サイクルごとに受信したバイトが上から1bitずつ入ってくる
+        tick(&mut serial, CYCLES_PER_BIT - 1);
+        assert_eq!(serial.read_sb(), 0x42);
+        tick(&mut serial, 1);
+        assert_eq!(serial.read_sb(), 0x85);
+
+        tick(&mut serial, CYCLES_PER_BIT * 7 - 1);
+        assert_eq!(serial.read_sc(), 0xFF);
+        assert!(!serial.int_serial_flag);
+
+        tick(&mut serial, 1);
+        assert_eq!(serial.read_sb(), 0xA5);
+        assert_eq!(serial.read_sc(), 0x7F);
+        assert!(serial.int_serial_flag);
+    }
+
+    #[test]
+    fn external_clock_waits_for_peer() {
+        let mut serial = Serial::default();
+        serial.write_sb(0x42);
+        serial.write_sc(0x80);
+
+        // 相手がクロックを出さなければ終わらない
+        tick(&mut serial, CYCLES_PER_BIT * 16);
+        assert_eq!(serial.read_sc(), 0xFE);
+        assert!(!serial.int_serial_flag);
+    }
+
+    #[test]
+    fn interrupt_flag_on_complete() {
+        // 0x0150からNOPが並ぶ32KBのROM
+        let rom = build_test_rom(b"SERIALTEST", 0x00, &[]);
+        let mut cpu = Cpu::new(Bus::new(&mut Cursor::new(rom), 48000, 2000).unwrap());
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.bus.int_flag = 0;
+
+        cpu.bus.write(0xFF01, 0x42).unwrap();
+        cpu.bus.write(0xFF02, 0x81).unwrap();
+
+        // 8bit分(4096サイクル)のNOPを実行し終えたときにIFのbit 3が立つ
+        for _ in 0..(CYCLES_PER_BIT * 8 / 4 - 1) {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.bus.int_flag & 0x08, 0);
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.int_flag & 0x08, 0x08);
+        assert_eq!(cpu.bus.read(0xFF01).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn output_only_when_capturing() {
+        let mut serial = Serial::default();
+        serial.write_sb(0x41);
+        serial.write_sc(0x81);
+        tick(&mut serial, CYCLES_PER_BIT * 8);
+        assert!(serial.output().is_empty());
+
+        serial.capture_output();
+        serial.write_sb(0x42);
+        serial.write_sc(0x81);
+        tick(&mut serial, CYCLES_PER_BIT * 8);
+        assert_eq!(serial.output(), &[0x42]);
+    }
+}
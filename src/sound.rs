@@ -1,7 +1,7 @@
 use anyhow::Result;
-use cpal;
 use dasp::{Signal, Sample, self as signal, ring_buffer, frame::Stereo};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::state::{StateReader, StateWriter};
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Ch1 {
@@ -100,6 +100,55 @@ impl Ch1 {
         Ok(())
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.sweep_period);
+        writer.write_u8(self.sweep_timer);
+        writer.write_bool(self.sweep_down);
+        writer.write_bool(self.calc_sweep_in_neg);
+        writer.write_u8(self.sweep_shift);
+        writer.write_bool(self.sweep_flag);
+        writer.write_u16(self.shadow_frequency);
+        writer.write_u8(self.length);
+        writer.write_u8(self.length_timer);
+        writer.write_bool(self.length_ticking);
+        writer.write_bool(self.stop_flag);
+        writer.write_u8(self.duty_pattern);
+        writer.write_u8(self.env_initial_volume);
+        writer.write_bool(self.env_up);
+        writer.write_u8(self.env_period);
+        writer.write_u8(self.env_timer);
+        writer.write_u16(self.frequency);
+        writer.write_u8(self.duty_position);
+        writer.write_u16(self.frequency_timer);
+        writer.write_u8(self.volume);
+        writer.write_bool(self.channel_on);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.sweep_period = reader.read_u8()?;
+        self.sweep_timer = reader.read_u8()?;
+        self.sweep_down = reader.read_bool()?;
+        self.calc_sweep_in_neg = reader.read_bool()?;
+        self.sweep_shift = reader.read_u8()?;
+        self.sweep_flag = reader.read_bool()?;
+        self.shadow_frequency = reader.read_u16()?;
+        self.length = reader.read_u8()?;
+        self.length_timer = reader.read_u8()?;
+        self.length_ticking = reader.read_bool()?;
+        self.stop_flag = reader.read_bool()?;
+        self.duty_pattern = reader.read_u8()?;
+        self.env_initial_volume = reader.read_u8()?;
+        self.env_up = reader.read_bool()?;
+        self.env_period = reader.read_u8()?;
+        self.env_timer = reader.read_u8()?;
+        self.frequency = reader.read_u16()?;
+        self.duty_position = reader.read_u8()?;
+        self.frequency_timer = reader.read_u16()?;
+        self.volume = reader.read_u8()?;
+        self.channel_on = reader.read_bool()?;
+        Ok(())
+    }
+
     fn dac_enable(&self) -> bool {
         return (self.env_initial_volume > 0) || self.env_up;
     }
@@ -310,6 +359,41 @@ impl Ch2 {
         Ok(())
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.length);
+        writer.write_u8(self.length_timer);
+        writer.write_bool(self.length_ticking);
+        writer.write_bool(self.stop_flag);
+        writer.write_u8(self.duty_pattern);
+        writer.write_u8(self.env_initial_volume);
+        writer.write_bool(self.env_up);
+        writer.write_u8(self.env_period);
+        writer.write_u8(self.env_timer);
+        writer.write_u16(self.frequency);
+        writer.write_u8(self.duty_position);
+        writer.write_u16(self.frequency_timer);
+        writer.write_u8(self.volume);
+        writer.write_bool(self.channel_on);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.length = reader.read_u8()?;
+        self.length_timer = reader.read_u8()?;
+        self.length_ticking = reader.read_bool()?;
+        self.stop_flag = reader.read_bool()?;
+        self.duty_pattern = reader.read_u8()?;
+        self.env_initial_volume = reader.read_u8()?;
+        self.env_up = reader.read_bool()?;
+        self.env_period = reader.read_u8()?;
+        self.env_timer = reader.read_u8()?;
+        self.frequency = reader.read_u16()?;
+        self.duty_position = reader.read_u8()?;
+        self.frequency_timer = reader.read_u16()?;
+        self.volume = reader.read_u8()?;
+        self.channel_on = reader.read_bool()?;
+        Ok(())
+    }
+
     fn dac_enable(&self) -> bool {
         return (self.env_initial_volume > 0) || self.env_up;
     }
@@ -471,6 +555,35 @@ impl Ch3 {
         Ok(())
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.channel_on);
+        writer.write_u8(self.length);
+        writer.write_u16(self.length_timer);
+        writer.write_bool(self.length_ticking);
+        writer.write_u8(self.volume);
+        writer.write_u16(self.frequency);
+        writer.write_u16(self.frequency_timer);
+        writer.write_u8(self.position);
+        writer.write_bytes(&self.wave_pattern_ram);
+        writer.write_bool(self.enable);
+        writer.write_bool(self.stop_flag);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.channel_on = reader.read_bool()?;
+        self.length = reader.read_u8()?;
+        self.length_timer = reader.read_u16()?;
+        self.length_ticking = reader.read_bool()?;
+        self.volume = reader.read_u8()?;
+        self.frequency = reader.read_u16()?;
+        self.frequency_timer = reader.read_u16()?;
+        self.position = reader.read_u8()?;
+        reader.read_bytes(&mut self.wave_pattern_ram)?;
+        self.enable = reader.read_bool()?;
+        self.stop_flag = reader.read_bool()?;
+        Ok(())
+    }
+
     fn dac_enable(&self) -> bool {
         return self.enable
     }
@@ -610,6 +723,45 @@ impl Ch4 {
         Ok(())
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.length);
+        writer.write_u8(self.length_timer);
+        writer.write_bool(self.length_ticking);
+        writer.write_bool(self.stop_flag);
+        writer.write_u8(self.env_initial_volume);
+        writer.write_bool(self.env_up);
+        writer.write_u8(self.env_period);
+        writer.write_u8(self.env_timer);
+        writer.write_u16(self.frequency_timer);
+        writer.write_u8(self.divisor);
+        writer.write_u8(self.divisor_code);
+        writer.write_u8(self.shift_amount);
+        writer.write_u16(self.lfsr);
+        writer.write_bool(self.counter_width);
+        writer.write_u8(self.volume);
+        writer.write_bool(self.channel_on);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.length = reader.read_u8()?;
+        self.length_timer = reader.read_u8()?;
+        self.length_ticking = reader.read_bool()?;
+        self.stop_flag = reader.read_bool()?;
+        self.env_initial_volume = reader.read_u8()?;
+        self.env_up = reader.read_bool()?;
+        self.env_period = reader.read_u8()?;
+        self.env_timer = reader.read_u8()?;
+        self.frequency_timer = reader.read_u16()?;
+        self.divisor = reader.read_u8()?;
+        self.divisor_code = reader.read_u8()?;
+        self.shift_amount = reader.read_u8()?;
+        self.lfsr = reader.read_u16()?;
+        self.counter_width = reader.read_bool()?;
+        self.volume = reader.read_u8()?;
+        self.channel_on = reader.read_bool()?;
+        Ok(())
+    }
+
     fn dac_enable(&self) -> bool {
         return (self.env_initial_volume > 0) || self.env_up;
     }
@@ -704,6 +856,27 @@ pub struct SoundControl {
     sound_on: bool
 }
 
+impl SoundControl {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.left_volume);
+        writer.write_u8(self.right_volume);
+        writer.write_u8(self.select_output);
+        writer.write_bool(self.vin_left);
+        writer.write_bool(self.vin_right);
+        writer.write_bool(self.sound_on);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.left_volume = reader.read_u8()?;
+        self.right_volume = reader.read_u8()?;
+        self.select_output = reader.read_u8()?;
+        self.vin_left = reader.read_bool()?;
+        self.vin_right = reader.read_bool()?;
+        self.sound_on = reader.read_bool()?;
+        Ok(())
+    }
+}
+
 pub struct Sound {
     ch1: Ch1,
     ch2: Ch2,
@@ -816,6 +989,30 @@ impl Sound {
         }
     }
 
+    // 出力待ちの音声サンプルはホスト側のバッファなので保存しない
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.ch1.save_state(writer);
+        self.ch2.save_state(writer);
+        self.ch3.save_state(writer);
+        self.ch4.save_state(writer);
+        writer.write_u64(self.current_cycle as u64);
+        writer.write_u8(self.frame_step);
+        writer.write_bool(self.prev_bit);
+        self.sound_control.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.ch1.load_state(reader)?;
+        self.ch2.load_state(reader)?;
+        self.ch3.load_state(reader)?;
+        self.ch4.load_state(reader)?;
+        self.current_cycle = reader.read_u64()? as usize;
+        self.frame_step = reader.read_u8()?;
+        self.prev_bit = reader.read_bool()?;
+        self.sound_control.load_state(reader)?;
+        Ok(())
+    }
+
     fn reset(&mut self) {
         let mut ch3 = Ch3::default();
         ch3.wave_pattern_ram = self.ch3.wave_pattern_ram;
@@ -0,0 +1,128 @@
+use anyhow::{bail, Result};
+
+// セーブステートの形式のバージョン。保存する内容を変えた場合は必ず上げること
+pub const STATE_VERSION: u32 = 6;
+
+// ファイル先頭のマジックナンバー
+pub const STATE_MAGIC: [u8; 4] = *b"GBST";
+
+// 各コンポーネントの状態をリトルエンディアンのバイト列として書き出す
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_u8(&mut self, data: u8) {
+        self.buf.push(data);
+    }
+
+    pub fn write_bool(&mut self, data: bool) {
+        self.buf.push(data as u8);
+    }
+
+    pub fn write_u16(&mut self, data: u16) {
+        self.buf.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, data: u32) {
+        self.buf.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, data: u64) {
+        self.buf.extend_from_slice(&data.to_le_bytes());
+    }
+
+    // 長さが固定のデータをそのまま書き出す
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    // 長さが変わりうるデータは先頭に長さを付けて書き出す
+    pub fn write_vec(&mut self, data: &[u8]) {
+        self.write_u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+// StateWriterで書き出したバイト列を先頭から読み出す
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.position < len {
+            bail!("save state is truncated at offset {}", self.position);
+        }
+
+        let ret = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(ret)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        buf.copy_from_slice(self.take(buf.len())?);
+        Ok(())
+    }
+
+    pub fn read_vec(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    // 固定長の領域に読み込む。長さが一致しない場合は別のカートリッジ構成の状態とみなす
+    pub fn read_vec_into(&mut self, buf: &mut [u8]) -> Result<()> {
+        let len = self.read_u32()? as usize;
+        if len != buf.len() {
+            bail!("save state has {} bytes of data where {} bytes are expected", len, buf.len());
+        }
+        self.read_bytes(buf)
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.position == self.data.len()
+    }
+}
@@ -1,3 +1,7 @@
+use anyhow::Result;
+
+use crate::state::{StateReader, StateWriter};
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Timer {
     div: u8,
@@ -10,18 +14,13 @@ pub struct Timer {
     current_cycle: u16,
     prev_and_result: bool,
     after_overflow_cycle: u8,
-    is_overflowing: bool,
-    pub is_stop: bool
+    is_overflowing: bool
 }
 
     
 
 impl Timer {
     pub fn tick(&mut self) {
-        if self.is_stop {
-            return;
-        }
-
         self.current_cycle = self.current_cycle.wrapping_add(1);
         self.div = ((self.current_cycle >> 8) & 0xFF) as u8;
 
@@ -65,6 +64,8 @@ impl Timer {
 
     pub fn write_div(&mut self, _: u8) {
         // Writing any value to this register resets it to $00.
+        // DIVは内部カウンタの上位8bitなので、カウンタごと0に戻す
+        self.current_cycle = 0;
         self.div = 0;
     }
 
@@ -88,4 +89,33 @@ impl Timer {
             3 | _ => self.clock_frequency_bit = 1 << 7
         }
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.div);
+        writer.write_u8(self.tima);
+        writer.write_u8(self.tma);
+        writer.write_u8(self.tac);
+        writer.write_bool(self.int_timer_flag);
+        writer.write_bool(self.int_timer_enable);
+        writer.write_u16(self.clock_frequency_bit);
+        writer.write_u16(self.current_cycle);
+        writer.write_bool(self.prev_and_result);
+        writer.write_u8(self.after_overflow_cycle);
+        writer.write_bool(self.is_overflowing);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.div = reader.read_u8()?;
+        self.tima = reader.read_u8()?;
+        self.tma = reader.read_u8()?;
+        self.tac = reader.read_u8()?;
+        self.int_timer_flag = reader.read_bool()?;
+        self.int_timer_enable = reader.read_bool()?;
+        self.clock_frequency_bit = reader.read_u16()?;
+        self.current_cycle = reader.read_u16()?;
+        self.prev_and_result = reader.read_bool()?;
+        self.after_overflow_cycle = reader.read_u8()?;
+        self.is_overflowing = reader.read_bool()?;
+        Ok(())
+    }
 }
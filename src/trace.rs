@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::bus::Bus;
+use crate::debugger::{parse_hex, Registers};
+
+// 書き出しのバッファ。1行が約70byteなので、1万命令分ほどまとめて書く
+const BUFFER_SIZE: usize = 1 << 20;
+
+// トレースを書き出す範囲
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceConfig {
+    // このアドレスの命令に来たら書き出しを始める
+    pub start: Option<u16>,
+    // このアドレスの命令に来たら書き出しをやめる。その命令は書き出さない
+    pub stop: Option<u16>,
+    // 書き出すフレームの範囲。0から数え、両端を含む
+    pub first_frame: Option<u64>,
+    pub last_frame: Option<u64>
+}
+
+impl TraceConfig {
+    // 引数から --trace <file>、--trace-start <addr>、--trace-stop <addr>、--trace-frames <first>-<last> を取り除いて、
+    // 出力先と範囲を返す。--traceの指定がなければNone
+    pub fn take_options(args: &mut Vec<String>) -> Result<Option<(PathBuf, Self)>> {
+        let path = take_option(args, "--trace")?.map(PathBuf::from);
+        let mut config = Self::default();
+
+        if let Some(address) = take_option(args, "--trace-start")? {
+            config.start = Some(parse_hex(&address)?);
+        }
+        if let Some(address) = take_option(args, "--trace-stop")? {
+            config.stop = Some(parse_hex(&address)?);
+        }
+        if let Some(frames) = take_option(args, "--trace-frames")? {
+            let (first, last) = frames.split_once('-').with_context(|| format!("invalid frame range: {}", frames))?;
+            let parse = |frame: &str| -> Result<Option<u64>> {
+                if frame.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(frame.parse().with_context(|| format!("invalid frame range: {}", frames))?))
+            };
+            config.first_frame = parse(first)?;
+            config.last_frame = parse(last)?;
+        }
+
+        match path {
+            Some(path) => Ok(Some((path, config))),
+            None if config != Self::default() => bail!("--trace-start, --trace-stop and --trace-frames require --trace <file>"),
+            None => Ok(None)
+        }
+    }
+
+    fn contains_frame(&self, frame: u64) -> bool {
+        self.first_frame.is_none_or(|first| first <= frame) && self.last_frame.is_none_or(|last| frame <= last)
+    }
+}
+
+// 引数から「オプション 値」の組を取り除いて、その値を返す
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>> {
+    let idx = match args.iter().position(|arg| arg == name) {
+        Some(idx) => idx,
+        None => return Ok(None)
+    };
+    if idx + 1 >= args.len() {
+        bail!("{} requires a value", name);
+    }
+    let value = args.remove(idx + 1);
+    args.remove(idx);
+    Ok(Some(value))
+}
+
+// 命令を実行する前のCPUの状態を、他のエミュレータのログと比べられる形式で書き出す
+// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+pub struct Tracer {
+    writer: Box<dyn Write + Send>,
+    config: TraceConfig,
+    // startのアドレスに来た(startの指定がなければ最初から)
+    started: bool,
+    // stopのアドレスに来た。これ以降は書き出さない
+    finished: bool,
+    // 実行を終えたフレームの数
+    frame: u64
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write + Send>, config: TraceConfig) -> Self {
+        Self {
+            started: config.start.is_none(),
+            writer,
+            config,
+            finished: Default::default(),
+            frame: Default::default()
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, config: TraceConfig) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Self::new(Box::new(BufWriter::with_capacity(BUFFER_SIZE, file)), config))
+    }
+
+    // 命令を実行する前に呼ぶ
+    pub fn log(&mut self, regs: &Registers, bus: &Bus) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        if self.config.stop == Some(regs.pc) {
+            self.finished = true;
+            return self.flush();
+        }
+        if !self.started {
+            if self.config.start != Some(regs.pc) {
+                return Ok(());
+            }
+            self.started = true;
+        }
+        if !self.config.contains_frame(self.frame) {
+            return Ok(());
+        }
+
+        let mem = |offset: u16| bus.peek(regs.pc.wrapping_add(offset)).unwrap_or(0xFF);
+        writeln!(
+            self.writer,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            regs.af >> 8, regs.af & 0xFF, regs.bc >> 8, regs.bc & 0xFF, regs.de >> 8, regs.de & 0xFF, regs.hl >> 8, regs.hl & 0xFF,
+            regs.sp, regs.pc, mem(0), mem(1), mem(2), mem(3)
+        )?;
+        Ok(())
+    }
+
+    // 1フレーム分の実行を終えたときに呼ぶ
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
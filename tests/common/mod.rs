@@ -0,0 +1,22 @@
+// 統合テストで共通に使う、テスト用のROMを作る関数
+// テストごとに使う関数が違うので、使わない関数があっても警告しない
+#![allow(dead_code)]
+
+use std::io::Cursor;
+
+use game_boy_rust::rom::build_test_rom;
+use game_boy_rust::GameBoy;
+
+// プログラムを指定したアドレスに置いた32KBのROMを作る。0x0150から実行する
+pub fn build_rom(title: &[u8], parts: &[(usize, &[u8])]) -> Vec<u8> {
+    build_rom_with_type(title, 0x00, parts)
+}
+
+// カートリッジの種類を指定してROMを作る
+pub fn build_rom_with_type(title: &[u8], cartridge_type: u8, parts: &[(usize, &[u8])]) -> Vec<u8> {
+    build_test_rom(title, cartridge_type, parts)
+}
+
+pub fn load_game_boy(rom: Vec<u8>) -> GameBoy {
+    GameBoy::new(&mut Cursor::new(rom), 48000, 2000).unwrap()
+}
@@ -0,0 +1,83 @@
+// Blarggのcpu_instrsテストROMをヘッドレスで動かし、シリアル出力で合否を判定する
+use std::fs::File;
+use std::io::BufReader;
+
+use game_boy_rust::GameBoy;
+
+// 一番時間のかかるROMでも実機で1分はかからないので、それを上限とする
+const MAX_FRAMES: usize = 60 * 60;
+
+fn run_test_rom(name: &str) {
+    let path = format!("rom/cpu_instrs/individual/{}.gb", name);
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let mut game_boy = GameBoy::new(&mut reader, 48000, 2000).unwrap();
+    game_boy.capture_serial_output();
+
+    let mut output = String::new();
+    for _ in 0..MAX_FRAMES {
+        game_boy.run_frame().unwrap();
+        output = String::from_utf8_lossy(game_boy.serial_output()).to_string();
+
+        if output.contains("Passed") || output.contains("Failed") {
+            break;
+        }
+    }
+
+    println!("{}", output);
+    assert!(output.contains("Passed"), "{} did not pass:\n{}", name, output);
+}
+
+#[test]
+fn special() {
+    run_test_rom("01-special");
+}
+
+#[test]
+fn interrupts() {
+    run_test_rom("02-interrupts");
+}
+
+#[test]
+fn op_sp_hl() {
+    run_test_rom("03-op sp,hl");
+}
+
+#[test]
+fn op_r_imm() {
+    run_test_rom("04-op r,imm");
+}
+
+#[test]
+fn op_rp() {
+    run_test_rom("05-op rp");
+}
+
+#[test]
+fn ld_r_r() {
+    run_test_rom("06-ld r,r");
+}
+
+#[test]
+fn jr_jp_call_ret_rst() {
+    run_test_rom("07-jr,jp,call,ret,rst");
+}
+
+#[test]
+fn misc_instrs() {
+    run_test_rom("08-misc instrs");
+}
+
+#[test]
+fn op_r_r() {
+    run_test_rom("09-op r,r");
+}
+
+#[test]
+fn bit_ops() {
+    run_test_rom("10-bit ops");
+}
+
+#[test]
+fn op_a_hl() {
+    run_test_rom("11-op a,(hl)");
+}
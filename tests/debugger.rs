@@ -0,0 +1,243 @@
+// デバッガのコマンドで止める、ステップ実行する、メモリやレジスタを読み書きできることを確認する
+use game_boy_rust::debugger::{execute, Command};
+use game_boy_rust::GameBoy;
+
+mod common;
+
+fn build_rom(parts: &[(usize, &[u8])]) -> GameBoy {
+    common::load_game_boy(common::build_rom(b"DEBUGGER", parts))
+}
+
+// 0x0150から呼び出しの入れ子になったプログラム
+fn build_game_boy() -> GameBoy {
+    build_rom(&[
+        (0x150, &[
+            0xCD, 0x60, 0x01, // CALL 0x0160
+            0x00,             // NOP
+            0x18, 0xFE        // JR -2
+        ]),
+        (0x160, &[
+            0x3E, 0x42,       // LD A, 0x42
+            0xCD, 0x70, 0x01, // CALL 0x0170
+            0xC9              // RET
+        ]),
+        (0x170, &[
+            0x04,             // INC B
+            0xC9              // RET
+        ])
+    ])
+}
+
+// 0xC000から順に書き込んでから、0xC002を読み続けるプログラム
+fn build_memory_game_boy() -> GameBoy {
+    build_rom(&[
+        (0x150, &[
+            0x21, 0x00, 0xC0, // LD HL, 0xC000
+            0x3E, 0x3C,       // LD A, 0x3C
+            0x22,             // LD (HL+), A
+            0x3C,             // INC A
+            0x22,             // LD (HL+), A
+            0x7E,             // LD A, (HL)
+            0x18, 0xFD        // JR -3
+        ])
+    ])
+}
+
+fn run(game_boy: &mut GameBoy, line: &str) -> String {
+    let command = Command::parse(line).unwrap();
+    execute(&mut game_boy.cpu, &command).unwrap()
+}
+
+#[test]
+fn breakpoint_stops_frame() {
+    let mut game_boy = build_game_boy();
+    assert_eq!(run(&mut game_boy, "break $0160"), "Breakpoint 1 at $0160");
+
+    game_boy.run_frame().unwrap();
+    assert!(game_boy.cpu.debugger.is_stopped());
+    assert_eq!(run(&mut game_boy, "p pc"), "$0160 (352)");
+
+    // 止まったことはデバッガのスレッドが一度だけ表示する
+    assert!(game_boy.cpu.debugger.take_report());
+    assert!(!game_boy.cpu.debugger.take_report());
+
+    // 止まっている間はフレームを進めない
+    game_boy.run_frame().unwrap();
+    assert_eq!(run(&mut game_boy, "p pc"), "$0160 (352)");
+
+    let backtrace = run(&mut game_boy, "bt");
+    assert_eq!(backtrace, "#0  $0160 in $0160\n#1  $0153");
+}
+
+#[test]
+fn step_next_and_finish() {
+    let mut game_boy = build_game_boy();
+    run(&mut game_boy, "b 160");
+    game_boy.run_frame().unwrap();
+
+    assert_eq!(run(&mut game_boy, "step"), "$0162: CALL $0170");
+    assert_eq!(run(&mut game_boy, "p a"), "$42 (66)");
+
+    // CALLの中では止まらずに、戻ってきたところで止まる
+    assert_eq!(run(&mut game_boy, "next"), "running");
+    game_boy.run_frame().unwrap();
+    assert!(game_boy.cpu.debugger.is_stopped());
+    assert_eq!(run(&mut game_boy, "p pc"), "$0165 (357)");
+    assert_eq!(run(&mut game_boy, "p b"), "$01 (1)");
+
+    // 0x0160の関数から戻ったところで止まる
+    assert_eq!(run(&mut game_boy, "finish"), "running until return to $0153");
+    game_boy.run_frame().unwrap();
+    assert_eq!(run(&mut game_boy, "p pc"), "$0153 (339)");
+    assert!(game_boy.cpu.debugger.call_stack().is_empty());
+}
+
+#[test]
+fn disabled_breakpoint_does_not_stop() {
+    let mut game_boy = build_game_boy();
+    run(&mut game_boy, "b $0170");
+    run(&mut game_boy, "disable 1");
+    assert_eq!(run(&mut game_boy, "info break"), "1    breakpoint        n $0170       INC B");
+
+    game_boy.run_frame().unwrap();
+    assert!(!game_boy.cpu.debugger.is_stopped());
+
+    run(&mut game_boy, "enable 1");
+    run(&mut game_boy, "delete 1");
+    assert_eq!(run(&mut game_boy, "info break"), "No breakpoints.");
+}
+
+#[test]
+fn continue_resumes_past_breakpoint() {
+    let mut game_boy = build_game_boy();
+    run(&mut game_boy, "b $0160");
+    game_boy.run_frame().unwrap();
+
+    assert_eq!(run(&mut game_boy, "c"), "running");
+    game_boy.run_frame().unwrap();
+    assert!(!game_boy.cpu.debugger.is_stopped());
+}
+
+#[test]
+fn memory_and_registers() {
+    let mut game_boy = build_game_boy();
+
+    assert_eq!(run(&mut game_boy, "w $C000 01 02 03 04"), "$C000: 01 02 03 04");
+    assert_eq!(run(&mut game_boy, "x/4xb $C000"), "$C000: 01 02 03 04");
+    assert_eq!(run(&mut game_boy, "x/2xh 0xC000"), "$C000: 0201 0403");
+    assert_eq!(run(&mut game_boy, "x/2db $C002"), "$C002: 3 4");
+    assert_eq!(run(&mut game_boy, "x/2i $0160"), "   $0160: 3E 42     LD A,$42\n   $0162: CD 70 01  CALL $0170");
+
+    run(&mut game_boy, "set hl $C001");
+    assert_eq!(run(&mut game_boy, "p h"), "$C0 (192)");
+    assert_eq!(run(&mut game_boy, "p l"), "$01 (1)");
+
+    // Fの下位4bitは書き込めない
+    run(&mut game_boy, "set f $FF");
+    assert_eq!(run(&mut game_boy, "p f"), "$F0 (240)");
+
+    assert!(Command::parse("x/4xb $G000").is_err());
+    assert!(Command::parse("set q 1").is_err());
+    assert!(execute(&mut game_boy.cpu, &Command::parse("set a 100").unwrap()).is_err());
+}
+
+#[test]
+fn disassemble_around_pc() {
+    let mut game_boy = build_game_boy();
+    run(&mut game_boy, "b $0165");
+    game_boy.run_frame().unwrap();
+
+    let listing = run(&mut game_boy, "disas");
+    let lines: Vec<&str> = listing.lines().collect();
+    // PCの前の3命令から表示する
+    assert_eq!(lines[1], "   $0160: 3E 42     LD A,$42");
+    assert_eq!(lines[3], "=> $0165: C9        RET");
+}
+
+#[test]
+fn watchpoint_stops_after_write() {
+    let mut game_boy = build_memory_game_boy();
+    assert_eq!(run(&mut game_boy, "watch $C001"), "Watchpoint 1 at $C001");
+
+    game_boy.run_frame().unwrap();
+    assert!(game_boy.cpu.debugger.is_stopped());
+    assert_eq!(game_boy.cpu.debugger.reason(), "watchpoint 1: write $C001 = $3D (was $00)");
+    // 書き込んだ命令の次で止まる
+    assert_eq!(run(&mut game_boy, "p pc"), "$0158 (344)");
+}
+
+#[test]
+fn conditional_watchpoint_on_range() {
+    let mut game_boy = build_memory_game_boy();
+    run(&mut game_boy, "watch $C000-$C00F if A == $3D");
+
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.debugger.reason(), "watchpoint 1: write $C001 = $3D (was $00)");
+    assert_eq!(
+        run(&mut game_boy, "info break"),
+        "1    watchpoint        y $C000-$C00F\n        stop only if A == $3D\n        already hit 1 time"
+    );
+}
+
+#[test]
+fn read_watchpoint_ignores_instruction_fetch() {
+    let mut game_boy = build_memory_game_boy();
+    run(&mut game_boy, "rwatch $0150-$0160");
+    run(&mut game_boy, "rwatch $C002");
+
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.debugger.reason(), "read watchpoint 2: read $C002 = $00");
+    assert_eq!(run(&mut game_boy, "p pc"), "$0159 (345)");
+}
+
+#[test]
+fn conditional_breakpoint_with_memory() {
+    let mut game_boy = build_memory_game_boy();
+    run(&mut game_boy, "break $0155-$0158 if A == $3D && [HL] == 0 && [$C000] != 0");
+
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.debugger.reason(), "breakpoint 1");
+    assert_eq!(run(&mut game_boy, "p pc"), "$0157 (343)");
+
+    // 条件を外すと範囲内のどの命令でも止まる
+    assert_eq!(run(&mut game_boy, "condition 1"), "Breakpoint 1 at $0155-$0158");
+    run(&mut game_boy, "c");
+    game_boy.run_frame().unwrap();
+    assert_eq!(run(&mut game_boy, "p pc"), "$0158 (344)");
+}
+
+#[test]
+fn ignore_counts_hits() {
+    let mut game_boy = build_memory_game_boy();
+    run(&mut game_boy, "b $0155-$0158");
+    assert_eq!(run(&mut game_boy, "ignore 1 2"), "Will ignore next 2 crossings of breakpoint 1.");
+
+    game_boy.run_frame().unwrap();
+    assert_eq!(run(&mut game_boy, "p pc"), "$0157 (343)");
+    assert_eq!(game_boy.cpu.debugger.breakpoints()[0].hits, 3);
+}
+
+#[test]
+fn invalid_conditions() {
+    assert!(Command::parse("break $0150 if A ==").is_err());
+    assert!(Command::parse("break $0150 if [HL == 0").is_err());
+    assert!(Command::parse("break $0150 if A = 0").is_err());
+    assert!(Command::parse("watch $C010-$C000").is_err());
+    assert!(Command::parse("step if A == 0").is_err());
+}
+
+#[test]
+fn illegal_opcode_stops_debugger() {
+    let mut game_boy = build_rom(&[
+        (0x150, &[
+            0x00,             // NOP
+            0xD3              // 未定義の命令
+        ])
+    ]);
+
+    assert!(game_boy.run_frame().is_err());
+    assert!(game_boy.cpu.debugger.is_stopped());
+    assert!(game_boy.cpu.debugger.reason().contains("0x0151"), "{}", game_boy.cpu.debugger.reason());
+    assert!(game_boy.cpu.debugger.take_report());
+    assert_eq!(run(&mut game_boy, "p pc"), "$0152 (338)");
+}
@@ -0,0 +1,58 @@
+// エミュレータのエラーが種類ごとにEmuErrorとして返され、リセットで復帰できることを確認する
+use std::io::Cursor;
+
+use game_boy_rust::cpu::CpuFault;
+use game_boy_rust::error::EmuError;
+use game_boy_rust::rom::CartridgeType;
+use game_boy_rust::GameBoy;
+
+mod common;
+
+fn build_rom(cartridge_type: u8, program: &[u8]) -> Vec<u8> {
+    common::build_rom_with_type(b"ERRORTEST", cartridge_type, &[(0x150, program)])
+}
+
+fn load(rom: Vec<u8>) -> Result<GameBoy, EmuError> {
+    GameBoy::new(&mut Cursor::new(rom), 48000, 2000)
+}
+
+#[test]
+fn broken_header() {
+    let mut rom = build_rom(0x00, &[]);
+    rom[0x14D] ^= 0xFF;
+
+    assert!(matches!(load(rom).err(), Some(EmuError::InvalidHeader(_))));
+}
+
+#[test]
+fn unsupported_mapper() {
+    let rom = build_rom(0xFC, &[]);
+
+    assert_eq!(load(rom).err(), Some(EmuError::UnsupportedMapper(CartridgeType::PocketCamera)));
+}
+
+#[test]
+fn illegal_opcode_then_reset() {
+    // NOP; 0xD3(未定義)
+    let rom = build_rom(0x00, &[0x00, 0xD3]);
+    let mut game_boy = load(rom).unwrap();
+
+    let fault = CpuFault { pc: 0x0151, opcode: 0xD3 };
+    assert_eq!(game_boy.run_frame(), Err(EmuError::CpuLocked(fault)));
+    // エラーで止まっても画面は描画できる
+    assert!(game_boy.cpu.sleep);
+
+    game_boy.reset().unwrap();
+    assert_eq!(game_boy.cpu.fault(), None);
+    assert_eq!(game_boy.run_frame(), Err(EmuError::CpuLocked(fault)));
+}
+
+#[test]
+fn broken_save_state() {
+    let mut game_boy = load(build_rom(0x00, &[0x18, 0xFE])).unwrap();
+    let mut state = game_boy.save_state();
+    state.truncate(state.len() - 1);
+
+    assert!(matches!(game_boy.load_state(&state), Err(EmuError::InvalidState(_))));
+    assert!(matches!(game_boy.load_state(b"nope"), Err(EmuError::InvalidState(_))));
+}
@@ -0,0 +1,234 @@
+// GDBのリモートシリアルプロトコルで、スクリプトのクライアントからレジスタとメモリを読み書きし、
+// ブレークポイントとウォッチポイント、ステップ実行、中断ができることを確認する
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use game_boy_rust::debugger::Register;
+use game_boy_rust::gdb::{self, GdbServer};
+use game_boy_rust::GameBoy;
+
+mod common;
+
+// 0x0150から関数を呼び出し、戻り値を0xC000に書き込むプログラム
+fn build_game_boy() -> GameBoy {
+    common::load_game_boy(common::build_rom(b"GDBTEST", &[
+        (0x150, &[
+            0xCD, 0x60, 0x01, // CALL 0x0160
+            0xEA, 0x00, 0xC0, // LD (0xC000), A
+            0x18, 0xFE        // JR -2
+        ]),
+        (0x160, &[
+            0x3E, 0x42,       // LD A, 0x42
+            0xC9              // RET
+        ])
+    ]))
+}
+
+// フロントエンドと同じように、別のスレッドでフレームを進め続ける
+struct Emulator {
+    game_boy: Arc<Mutex<GameBoy>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Emulator {
+    fn start() -> Self {
+        let mut game_boy = build_game_boy();
+        // --gdbを指定したときと同じく、GDBから再開するまで止めておく
+        game_boy.cpu.debugger.stop();
+        let game_boy = Arc::new(Mutex::new(game_boy));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let game_boy = game_boy.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    // フロントエンドと同じく、CPUがロックしても動かし続ける
+                    let _ = game_boy.lock().unwrap().run_frame();
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        Self { game_boy, running, thread: Some(thread) }
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Client {
+    stream: TcpStream
+}
+
+impl Client {
+    fn connect(emulator: &Emulator) -> Self {
+        let server = GdbServer::bind(0).unwrap();
+        let address = server.local_addr().unwrap();
+        server.spawn(emulator.game_boy.clone());
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        Self { stream }
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        self.stream.write_all(data).unwrap();
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, v| sum.wrapping_add(v));
+        self.send(format!("${}#{:02x}", data, checksum).as_bytes());
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    // 応答のパケットを読んで+を返す
+    fn read_reply(&mut self) -> String {
+        while self.read_byte() != b'$' {}
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte)
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let expected = data.iter().fold(0u8, |sum, v| sum.wrapping_add(*v));
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", expected));
+        self.send(b"+");
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send_packet(data);
+        assert_eq!(self.read_byte(), b'+');
+        self.read_reply()
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    let emulator = Emulator::start();
+    let mut client = Client::connect(&emulator);
+
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("p5"), "0001");
+
+    // BCに0x1234を書き込む
+    assert_eq!(client.request("P1=3412"), "OK");
+    assert_eq!(client.request("p1"), "3412");
+    assert_eq!(emulator.game_boy.lock().unwrap().cpu.register(Register::parse("b").unwrap()), 0x12);
+
+    let registers = client.request("g");
+    assert_eq!(registers.len(), 24);
+    assert_eq!(&registers[4..8], "3412");
+    assert_eq!(&registers[20..24], "0001");
+    let registers = format!("{}{}", &registers[..4], "cdab0000000000000001");
+    assert_eq!(client.request(&format!("G{}", registers)), "OK");
+    assert_eq!(client.request("p1"), "cdab");
+
+    assert_eq!(client.request("Mc000,3:010203"), "OK");
+    assert_eq!(client.request("mc000,3"), "010203");
+    assert_eq!(client.request("m0150,3"), "cd6001");
+
+    assert_eq!(client.request("p9"), "E01");
+    assert_eq!(client.request("Mc000,2:01"), "E01");
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+
+    // 空のパケットや先頭がASCIIでないパケットにも空で返し、接続を続ける
+    assert_eq!(client.request(""), "");
+    assert_eq!(client.request("\u{e9}1"), "");
+    assert_eq!(client.request("p5"), "0001");
+}
+
+#[test]
+fn breakpoint_step_and_watchpoint() {
+    let emulator = Emulator::start();
+    let mut client = Client::connect(&emulator);
+
+    assert_eq!(client.request("Z0,160,1"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p5"), "6001");
+
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p5"), "6201");
+    assert_eq!(client.request("p0")[2..], *"42");
+
+    // ブレークポイントを外して、書き込みのウォッチポイントで止める
+    assert_eq!(client.request("z0,160,1"), "OK");
+    assert_eq!(client.request("Z2,c000,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:c000;");
+    assert_eq!(client.request("p5"), "5601");
+    assert_eq!(client.request("mc000,1"), "42");
+
+    // 中断するまで実行を続ける
+    assert_eq!(client.request("z2,c000,1"), "OK");
+    client.send_packet("c");
+    assert_eq!(client.read_byte(), b'+');
+    thread::sleep(Duration::from_millis(50));
+    client.send(&[0x03]);
+    assert_eq!(client.read_reply(), "S02");
+    assert_eq!(client.request("p5"), "5601");
+
+    assert_eq!(client.request("D"), "OK");
+}
+
+#[test]
+fn locked_cpu_reports_sigill() {
+    let emulator = Emulator::start();
+    let mut client = Client::connect(&emulator);
+
+    // 0xC000に未定義の命令を置いてそこから実行する
+    assert_eq!(client.request("Mc000,1:d3"), "OK");
+    assert_eq!(client.request("P5=00c0"), "OK");
+    assert_eq!(client.request("s"), "S04");
+
+    // ロックした後は、何をしても同じように返す
+    assert_eq!(client.request("?"), "S04");
+    assert_eq!(client.request("s"), "S04");
+    assert_eq!(client.request("c"), "S04");
+}
+
+#[test]
+fn target_description_and_no_ack() {
+    let emulator = Emulator::start();
+    let mut client = Client::connect(&emulator);
+
+    assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+    let xml = client.request("qXfer:features:read:target.xml:0,1000");
+    assert!(xml.starts_with("l<?xml"));
+    assert!(xml.contains("name=\"pc\""));
+    assert!(client.request("qXfer:features:read:target.xml:0,10").starts_with('m'));
+
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    // 以降は+を返さない
+    client.send_packet("p5");
+    assert_eq!(client.read_reply(), "0001");
+}
+
+#[test]
+fn port_option() {
+    let mut args = vec!["--gdb".to_string(), "2345".to_string(), "--printer".to_string()];
+    assert_eq!(gdb::take_port_option(&mut args).unwrap(), Some(2345));
+    assert_eq!(args, vec!["--printer".to_string()]);
+
+    assert_eq!(gdb::take_port_option(&mut args).unwrap(), None);
+    assert!(gdb::take_port_option(&mut vec!["--gdb".to_string()]).is_err());
+    assert!(gdb::take_port_option(&mut vec!["--gdb".to_string(), "x".to_string()]).is_err());
+}
@@ -0,0 +1,52 @@
+// 命令表からのデコードと逆アセンブルの表記を確認する
+use game_boy_rust::instruction::{decode, Decoded, Instruction};
+
+#[test]
+fn disassemble_operands() {
+    let cases: [([u8; 3], &str); 12] = [
+        ([0x00, 0x00, 0x00], "NOP"),
+        ([0x2A, 0x00, 0x00], "LD A,(HL+)"),
+        ([0x32, 0x00, 0x00], "LD (HL-),A"),
+        ([0x21, 0x34, 0x12], "LD HL,$1234"),
+        ([0x08, 0x00, 0xC0], "LD ($C000),SP"),
+        ([0xE0, 0x44, 0x00], "LDH ($FF44),A"),
+        ([0xF8, 0xFE, 0x00], "LD HL,SP-2"),
+        ([0x20, 0xFA, 0x00], "JR NZ,-6"),
+        ([0xC4, 0x50, 0x01], "CALL NZ,$0150"),
+        ([0x8E, 0x00, 0x00], "ADC A,(HL)"),
+        ([0xCB, 0x7E, 0x00], "BIT 7,(HL)"),
+        ([0xD3, 0x00, 0x00], "ILLEGAL $D3")
+    ];
+
+    for (bytes, text) in cases {
+        assert_eq!(decode(bytes).instruction.to_string(), text);
+    }
+}
+
+#[test]
+fn length_and_cycles() {
+    // 条件付きの命令は分岐したときだけ長くなる
+    let jr = decode([0x38, 0x05, 0x00]);
+    assert_eq!((jr.length, jr.cycles, jr.branch_cycles), (2, 8, 12));
+
+    let ld = decode([0xFA, 0x00, 0xC0]);
+    assert_eq!((ld.length, ld.cycles, ld.branch_cycles), (3, 16, 16));
+
+    // (HL)を対象にするCB命令は読み書きの分だけ長い
+    assert_eq!(Decoded::lookup_cb(0x06).cycles, 16);
+    assert_eq!(Decoded::lookup_cb(0x46).cycles, 12);
+    assert_eq!(Decoded::lookup_cb(0x00).cycles, 8);
+}
+
+#[test]
+fn every_opcode_decodes() {
+    for opcode in 0..=0xFF_u8 {
+        let decoded = Decoded::lookup(opcode);
+        assert!((1..=3).contains(&decoded.length), "{:#04X}", opcode);
+        assert!(decoded.cycles <= decoded.branch_cycles, "{:#04X}", opcode);
+
+        let cb = Decoded::lookup_cb(opcode);
+        assert_eq!(cb.length, 2);
+        assert!(!matches!(cb.instruction, Instruction::Illegal(_)));
+    }
+}
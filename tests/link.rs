@@ -0,0 +1,92 @@
+// 二台のゲームボーイをローカルのTCPでつなぎ、シリアル転送でバイトを交換できることを確認する
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use game_boy_rust::link::TcpLinkEndpoint;
+use game_boy_rust::serial::SerialEndpoint;
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_FRAMES: usize = 600;
+
+// 内部クロックで0x42を送る。相手の準備ができておらず0xFFが返ってきた場合は送り直す
+// 受信したバイトを0xC000に書き、0xC001に1を書いて止まる
+const MASTER: [u8; 30] = [
+    0x3E, 0x42,       // LD A, 0x42
+    0xE0, 0x01,       // LDH (SB), A
+    0x3E, 0x81,       // LD A, 0x81
+    0xE0, 0x02,       // LDH (SC), A
+    0xF0, 0x02,       // LDH A, (SC)
+    0xCB, 0x7F,       // BIT 7, A
+    0x20, 0xFA,       // JR NZ, -6
+    0xF0, 0x01,       // LDH A, (SB)
+    0xFE, 0xFF,       // CP 0xFF
+    0x28, 0xEC,       // JR Z, -20
+    0xEA, 0x00, 0xC0, // LD (0xC000), A
+    0x3E, 0x01,       // LD A, 0x01
+    0xEA, 0x01, 0xC0, // LD (0xC001), A
+    0x18, 0xFE        // JR -2
+];
+
+// 外部クロックで0x99を送る
+const SLAVE: [u8; 26] = [
+    0x3E, 0x99,       // LD A, 0x99
+    0xE0, 0x01,       // LDH (SB), A
+    0x3E, 0x80,       // LD A, 0x80
+    0xE0, 0x02,       // LDH (SC), A
+    0xF0, 0x02,       // LDH A, (SC)
+    0xCB, 0x7F,       // BIT 7, A
+    0x20, 0xFA,       // JR NZ, -6
+    0xF0, 0x01,       // LDH A, (SB)
+    0xEA, 0x00, 0xC0, // LD (0xC000), A
+    0x3E, 0x01,       // LD A, 0x01
+    0xEA, 0x01, 0xC0, // LD (0xC001), A
+    0x18, 0xFE        // JR -2
+];
+
+// 転送が終わるまで動かし、受信したバイトを返す
+fn run_until_received(program: &[u8], stream: TcpStream) -> u8 {
+    let mut game_boy = common::load_game_boy(common::build_rom(b"LINKTEST", &[(0x150, program)]));
+    game_boy.set_serial_endpoint(Box::new(TcpLinkEndpoint::from_stream(stream, TIMEOUT).unwrap()));
+
+    for _ in 0..MAX_FRAMES {
+        game_boy.run_frame().unwrap();
+        if game_boy.cpu.bus.read(0xC001).unwrap() == 0x01 {
+            return game_boy.cpu.bus.read(0xC000).unwrap();
+        }
+    }
+    panic!("serial transfer did not complete");
+}
+
+#[test]
+fn exchange_byte_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let slave = thread::spawn(move || {
+        let stream = TcpStream::connect(address).unwrap();
+        run_until_received(&SLAVE, stream)
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let master_received = run_until_received(&MASTER, stream);
+    let slave_received = slave.join().unwrap();
+
+    assert_eq!(master_received, 0x99);
+    assert_eq!(slave_received, 0x42);
+}
+
+#[test]
+fn no_peer_receives_ff() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // 接続だけして何も応答しない相手
+    let _peer = TcpStream::connect(address).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    let mut endpoint = TcpLinkEndpoint::from_stream(stream, Duration::from_millis(100)).unwrap();
+    assert_eq!(endpoint.exchange(0x42), 0xFF);
+}
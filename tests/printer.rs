@@ -0,0 +1,104 @@
+// ポケットプリンタにパケットを送り、印刷結果がPNGとして保存されることを確認する
+use std::fs::{self, File};
+
+use game_boy_rust::printer::{decompress, Printer};
+use game_boy_rust::serial::SerialEndpoint;
+
+// パケットを送り、最後の2byteに対するプリンタの応答(0x81とステータス)を返す
+fn send_packet(printer: &mut Printer, command: u8, compression: bool, data: &[u8]) -> (u8, u8) {
+    let mut packet = vec![0x88, 0x33, command, compression as u8, data.len() as u8, (data.len() >> 8) as u8];
+    packet.extend_from_slice(data);
+
+    let checksum = packet[2..].iter().fold(0_u16, |sum, v| sum.wrapping_add(*v as u16));
+    packet.push(checksum as u8);
+    packet.push((checksum >> 8) as u8);
+
+    for v in packet {
+        assert_eq!(printer.exchange(v), 0x00);
+    }
+
+    let alive = printer.exchange(0x00);
+    let status = printer.exchange(0x00);
+    (alive, status)
+}
+
+#[test]
+fn decompress_rle() {
+    // 0x81: 次の1byteを3回、0x01: 続く2byteをそのまま
+    assert_eq!(decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34]), vec![0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+}
+
+#[test]
+fn print_to_png() {
+    let dir = std::env::temp_dir().join(format!("game_boy_printer_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let mut printer = Printer::new(&dir);
+
+    let (alive, status) = send_packet(&mut printer, 0x01, false, &[]);
+    assert_eq!((alive, status), (0x81, 0x00));
+
+    // 黒一色のタイル行を2行分(640byte = 129byte x 4 + 124byte)、圧縮して送る
+    let compressed = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFA, 0xFF];
+    let (_, status) = send_packet(&mut printer, 0x04, true, &compressed);
+    assert_eq!(status & 0x08, 0x08);
+
+    // 空のデータパケットはデータの終わり
+    send_packet(&mut printer, 0x04, false, &[]);
+
+    // 1枚、前後の余白1、標準のパレット
+    let (_, status) = send_packet(&mut printer, 0x02, false, &[0x01, 0x11, 0xE4, 0x40]);
+    assert_eq!(status & 0x02, 0x02);
+
+    let path = dir.join("print_0001.png");
+    let printouts: Vec<_> = printer.take_printouts().into_iter().map(|printout| printout.unwrap()).collect();
+    assert_eq!(printouts, vec![path.clone()]);
+    assert!(printer.take_printouts().is_empty());
+
+    let decoder = png::Decoder::new(File::open(&path).unwrap());
+    let reader = decoder.read_info().unwrap();
+    let info = reader.info();
+    assert_eq!(info.width, 160);
+    // 余白8ピクセル + 画像16ピクセル + 余白8ピクセル
+    assert_eq!(info.height, 32);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn print_without_margin_after_idle() {
+    let dir = std::env::temp_dir().join(format!("game_boy_printer_idle_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let mut printer = Printer::new(&dir);
+
+    // 白一色のタイル行を1行分送り、後ろの余白なしで2回印刷する
+    for _ in 0..2 {
+        send_packet(&mut printer, 0x01, false, &[]);
+        send_packet(&mut printer, 0x04, true, &[0xFF, 0x00, 0xFF, 0x00, 0xBC, 0x00]);
+        send_packet(&mut printer, 0x04, false, &[]);
+        send_packet(&mut printer, 0x02, false, &[0x01, 0x00, 0xE4, 0x40]);
+        printer.poll_external(None);
+    }
+    assert!(printer.take_printouts().is_empty());
+
+    // 次のデータが来ないまま約1秒たったら、そこまでを1枚として保存する
+    for _ in 0..8192 {
+        printer.poll_external(None);
+    }
+    let printouts = printer.take_printouts();
+    assert_eq!(printouts.len(), 1);
+    let path = printouts.into_iter().next().unwrap().unwrap();
+    assert_eq!(path, dir.join("print_0001.png"));
+
+    let decoder = png::Decoder::new(File::open(&path).unwrap());
+    let reader = decoder.read_info().unwrap();
+    // 8ピクセルの行を2回分つなげる
+    assert_eq!(reader.info().height, 16);
+
+    // 紙がなければ保存しない
+    for _ in 0..8192 {
+        printer.poll_external(None);
+    }
+    assert!(printer.take_printouts().is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
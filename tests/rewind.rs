@@ -0,0 +1,287 @@
+// 巻き戻しのバッファからスナップショットを復元し、入力を再現しながら実行し直して前の命令に戻れることを確認する
+use std::sync::{Arc, Mutex};
+
+use game_boy_rust::debugger::{execute, Command};
+use game_boy_rust::joypad::Button;
+use game_boy_rust::rewind::{apply, diff};
+use game_boy_rust::serial::{CaptureEndpoint, SerialEndpoint};
+use game_boy_rust::GameBoy;
+
+mod common;
+
+// 0x0150からプログラム、0x0160から関数を置いたROMを作る
+fn build_rom(program: &[u8], function: &[u8]) -> GameBoy {
+    common::load_game_boy(common::build_rom(b"REWINDTEST", &[(0x150, program), (0x160, function)]))
+}
+
+// 関数の中でBを増やし、戻ってきたらAを0xC000に書き込み続ける
+fn build_counter() -> GameBoy {
+    build_rom(
+        &[
+            0xCD, 0x60, 0x01, // CALL 0x0160
+            0xEA, 0x00, 0xC0, // LD (0xC000), A
+            0x18, 0xF8        // JR -8
+        ],
+        &[
+            0x04,             // INC B
+            0x78,             // LD A, B
+            0xC9              // RET
+        ]
+    )
+}
+
+// ボタンを選んで、P1を0xC000に書き込み続ける
+fn build_joypad_reader() -> GameBoy {
+    build_rom(
+        &[
+            0x3E, 0x10,       // LD A, 0x10
+            0xE0, 0x00,       // LDH (0x00), A
+            0xF0, 0x00,       // LDH A, (0x00)
+            0xEA, 0x00, 0xC0, // LD (0xC000), A
+            0x18, 0xF9        // JR -7
+        ],
+        &[]
+    )
+}
+
+// Bを増やしながら、内部クロックでBを送り続ける
+fn build_link_sender() -> GameBoy {
+    build_rom(
+        &[
+            0x04,             // INC B
+            0x78,             // LD A, B
+            0xE0, 0x01,       // LDH (0x01), A
+            0x3E, 0x81,       // LD A, 0x81
+            0xE0, 0x02,       // LDH (0x02), A
+            0x18, 0xF6        // JR -10
+        ],
+        &[]
+    )
+}
+
+// 転送するたびに違うバイトを返す相手
+struct CountingEndpoint {
+    count: u8
+}
+
+impl SerialEndpoint for CountingEndpoint {
+    fn exchange(&mut self, _data: u8) -> u8 {
+        self.count = self.count.wrapping_add(1);
+        self.count
+    }
+}
+
+fn run(game_boy: &mut GameBoy, line: &str) -> String {
+    let command = Command::parse(line).unwrap();
+    execute(&mut game_boy.cpu, &command).unwrap()
+}
+
+fn run_err(game_boy: &mut GameBoy, line: &str) -> String {
+    let command = Command::parse(line).unwrap();
+    execute(&mut game_boy.cpu, &command).unwrap_err().to_string()
+}
+
+fn b(game_boy: &GameBoy) -> u16 {
+    game_boy.cpu.registers().bc >> 8
+}
+
+#[test]
+fn delta_round_trip() {
+    let base: Vec<u8> = (0..300).map(|i| (i * 7) as u8).collect();
+    let mut target = base.clone();
+    target[3] ^= 0xFF;
+    target[200..260].fill(0x55);
+    target.extend_from_slice(&[1, 2, 3]);
+
+    let delta = diff(&base, &target);
+    assert!(delta.len() < target.len() / 2);
+    assert_eq!(apply(&base, &delta).unwrap(), target);
+
+    // 短くなる場合も戻せる
+    assert_eq!(apply(&target, &diff(&target, &base)).unwrap(), base);
+    assert!(apply(&base, &delta[..delta.len() - 1]).is_err());
+}
+
+#[test]
+fn reverse_step_restores_exact_state() {
+    let mut game_boy = build_counter();
+    game_boy.enable_rewind(1, 10);
+    for _ in 0..3 {
+        game_boy.run_frame().unwrap();
+    }
+
+    run(&mut game_boy, "b $0153");
+    run(&mut game_boy, "c");
+    game_boy.run_frame().unwrap();
+    run(&mut game_boy, "delete 1");
+
+    let mut states = vec![(game_boy.cpu.steps(), game_boy.save_state())];
+    for _ in 0..4 {
+        run(&mut game_boy, "step");
+        states.push((game_boy.cpu.steps(), game_boy.save_state()));
+    }
+
+    assert_eq!(run(&mut game_boy, "rs"), "$0160: INC B");
+    assert_eq!((game_boy.cpu.steps(), game_boy.save_state()), states[3]);
+    run(&mut game_boy, "reverse-step 3");
+    assert_eq!((game_boy.cpu.steps(), game_boy.save_state()), states[0]);
+    assert!(game_boy.cpu.debugger.is_stopped());
+
+    // 戻った所から実行し直しても同じ状態になる
+    run(&mut game_boy, "step 4");
+    assert_eq!((game_boy.cpu.steps(), game_boy.save_state()), states[4]);
+}
+
+#[test]
+fn replays_recorded_input() {
+    let mut game_boy = build_joypad_reader();
+    game_boy.enable_rewind(2, 10);
+    for _ in 0..4 {
+        game_boy.run_frame().unwrap();
+    }
+
+    // 4フレーム目の後のスナップショットから、ボタンを押した後の5フレーム目の終わりへ戻る
+    game_boy.set_button(Button::A, true);
+    game_boy.run_frame().unwrap();
+    let pressed = (game_boy.cpu.steps(), game_boy.save_state());
+    assert_eq!(game_boy.cpu.bus.peek(0xC000).unwrap(), 0xDE);
+
+    game_boy.set_button(Button::A, false);
+    for _ in 0..3 {
+        game_boy.run_frame().unwrap();
+    }
+    assert_eq!(game_boy.cpu.bus.peek(0xC000).unwrap(), 0xDF);
+
+    assert!(game_boy.cpu.rewind_to(pressed.0).unwrap());
+    assert_eq!((game_boy.cpu.steps(), game_boy.save_state()), pressed);
+    assert_eq!(game_boy.cpu.bus.peek(0xC000).unwrap(), 0xDE);
+
+    // ボタンは今押されている状態に戻る
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.bus.peek(0xC000).unwrap(), 0xDF);
+}
+
+#[test]
+fn reverse_continue_to_breakpoint_and_watchpoint() {
+    let mut game_boy = build_counter();
+    game_boy.enable_rewind(1, 10);
+    for _ in 0..3 {
+        game_boy.run_frame().unwrap();
+    }
+    let count = b(&game_boy);
+
+    run(&mut game_boy, "b $0160");
+    assert_eq!(run(&mut game_boy, "rc"), "breakpoint 1 at $0160: INC B");
+    let before = b(&game_boy);
+    assert!(before == count || before == count.wrapping_sub(1) & 0xFF);
+    run(&mut game_boy, "rc");
+    assert_eq!(b(&game_boy), before.wrapping_sub(1) & 0xFF);
+    // ヒット数は数えない
+    assert_eq!(game_boy.cpu.debugger.breakpoints()[0].hits, 0);
+
+    run(&mut game_boy, "delete 1");
+    run(&mut game_boy, "watch $C000");
+    let reason = run(&mut game_boy, "reverse-continue");
+    assert!(reason.starts_with("watchpoint 2: write $C000"), "{}", reason);
+    assert_eq!(game_boy.cpu.registers().pc, 0x0156);
+    assert_eq!(game_boy.cpu.bus.peek(0xC000).unwrap() as u16, b(&game_boy));
+
+    // 前に進めると、戻った所から実行を続ける
+    run(&mut game_boy, "c");
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.registers().pc, 0x0156);
+    assert_eq!(game_boy.cpu.bus.peek(0xC000).unwrap() as u16, before);
+}
+
+#[test]
+fn reverse_continue_stops_at_oldest_snapshot() {
+    let mut game_boy = build_counter();
+    assert_eq!(run_err(&mut game_boy, "rs"), "rewind is not enabled");
+
+    game_boy.enable_rewind(1, 3);
+    assert_eq!(run_err(&mut game_boy, "rc"), "no earlier state in the rewind buffer");
+    for _ in 0..6 {
+        game_boy.run_frame().unwrap();
+    }
+    assert_eq!(game_boy.cpu.rewind.len(), 3);
+    let oldest = game_boy.cpu.rewind.oldest_steps().unwrap();
+
+    run(&mut game_boy, "b $0100");
+    assert!(run(&mut game_boy, "rc").starts_with("reached the oldest rewind point at"));
+    assert_eq!(game_boy.cpu.steps(), oldest);
+    assert_eq!(run_err(&mut game_boy, "rs"), "no earlier state in the rewind buffer");
+}
+
+#[test]
+fn hold_to_rewind() {
+    let mut game_boy = build_counter();
+    game_boy.enable_rewind(2, 4);
+    for _ in 0..9 {
+        game_boy.run_frame().unwrap();
+    }
+    let latest = game_boy.cpu.steps();
+
+    game_boy.set_rewinding(true);
+    game_boy.run_frame().unwrap();
+    let first = game_boy.cpu.steps();
+    assert!(first < latest);
+    game_boy.run_frame().unwrap();
+    assert!(game_boy.cpu.steps() < first);
+
+    // 最も古いスナップショットで止まる
+    for _ in 0..4 {
+        game_boy.run_frame().unwrap();
+    }
+    assert_eq!(Some(game_boy.cpu.steps()), game_boy.cpu.rewind.oldest_steps());
+    assert!(!game_boy.rewind().unwrap());
+
+    // 離すとそこから実行を続け、新しいスナップショットを取り直す
+    game_boy.set_rewinding(false);
+    game_boy.run_frame().unwrap();
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.rewind.len(), 2);
+}
+
+#[test]
+fn reverse_step_does_not_exchange_again() {
+    let mut game_boy = build_link_sender();
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    game_boy.set_serial_endpoint(Box::new(CaptureEndpoint::new(sent.clone())));
+    game_boy.capture_serial_output();
+    game_boy.enable_rewind(1, 10);
+    for _ in 0..3 {
+        game_boy.run_frame().unwrap();
+    }
+
+    let count = sent.lock().unwrap().len();
+    let output = game_boy.serial_output().to_vec();
+    assert!(count > 0);
+    assert_eq!(output.len(), count);
+
+    // 実行し直している間は相手に送らず、送信の記録にも加えない
+    run(&mut game_boy, "reverse-step 20");
+    assert_eq!(sent.lock().unwrap().len(), count);
+    assert_eq!(game_boy.serial_output(), &output[..]);
+}
+
+#[test]
+fn replays_received_link_data() {
+    let mut game_boy = build_link_sender();
+    game_boy.set_serial_endpoint(Box::new(CountingEndpoint { count: 0 }));
+    game_boy.enable_rewind(1, 10);
+    for _ in 0..3 {
+        game_boy.run_frame().unwrap();
+    }
+
+    let mut states = vec![(game_boy.cpu.steps(), game_boy.save_state())];
+    for _ in 0..20 {
+        run(&mut game_boy, "step");
+        states.push((game_boy.cpu.steps(), game_boy.save_state()));
+    }
+
+    // 受信したバイトがSBにシフトインされる途中の状態まで同じになる
+    for expected in states.iter().rev().skip(1) {
+        run(&mut game_boy, "rs");
+        assert_eq!((game_boy.cpu.steps(), game_boy.save_state()), *expected);
+    }
+}
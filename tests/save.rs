@@ -0,0 +1,119 @@
+// バッテリーバックアップが、RAMが書き換えられた場合だけ一定フレーム後と一時停止、終了時に書き出されることを確認する
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use game_boy_rust::save::SaveStorage;
+use game_boy_rust::GameBoy;
+
+mod common;
+
+// 書き出したファイル名とデータ
+type Writes = Vec<(String, Vec<u8>)>;
+
+// 書き出されたデータを順に残しておくメモリ上の保存先
+#[derive(Clone, Default)]
+struct MemoryStorage {
+    writes: Arc<Mutex<Writes>>
+}
+
+impl MemoryStorage {
+    fn write_count(&self) -> usize {
+        self.writes.lock().unwrap().len()
+    }
+}
+
+impl SaveStorage for MemoryStorage {
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let writes = self.writes.lock().unwrap();
+        Ok(writes.iter().rev().find(|(n, _)| n == name).map(|(_, data)| data.clone()))
+    }
+
+    fn write(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        self.writes.lock().unwrap().push((name.to_string(), data.to_vec()));
+        Ok(())
+    }
+}
+
+// MBC1+RAM+BATTERYで8KBのRAMを持つカートリッジ
+// 最初にRAMの先頭へ0x42を1回だけ書き込み、あとは何もしない
+fn build_game_boy(storage: &MemoryStorage) -> GameBoy {
+    let mut game_boy = common::load_game_boy(common::build_rom_with_type(b"SAVETEST", 0x03, &[
+        // RAMサイズ: 8KB
+        (0x149, &[0x02]),
+        (0x150, &[
+            0x3E, 0x0A,       // LD A, 0x0A
+            0xEA, 0x00, 0x00, // LD (0x0000), A
+            0x3E, 0x42,       // LD A, 0x42
+            0xEA, 0x00, 0xA0, // LD (0xA000), A
+            0x18, 0xFE        // JR -2
+        ])
+    ]));
+    game_boy.set_save_storage(Box::new(storage.clone())).unwrap();
+    game_boy
+}
+
+#[test]
+fn flush_after_frames_only_when_dirty() {
+    let storage = MemoryStorage::default();
+    let mut game_boy = build_game_boy(&storage);
+
+    game_boy.run_frame().unwrap();
+    assert!(game_boy.cpu.bus.mbc.is_dirty());
+
+    // 書き換えから60フレームで書き出す
+    for _ in 0..58 {
+        game_boy.run_frame().unwrap();
+    }
+    assert_eq!(storage.write_count(), 0);
+    game_boy.run_frame().unwrap();
+    assert_eq!(storage.write_count(), 1);
+    assert!(!game_boy.cpu.bus.mbc.is_dirty());
+
+    let (name, data) = storage.writes.lock().unwrap()[0].clone();
+    assert_eq!(name, game_boy.save_file_name());
+    assert_eq!(data.len(), 0x2000);
+    assert_eq!(data[0], 0x42);
+
+    // その後は書き換えられないので書き出さない
+    for _ in 0..120 {
+        game_boy.run_frame().unwrap();
+    }
+    assert_eq!(storage.write_count(), 1);
+}
+
+#[test]
+fn flush_on_pause() {
+    let storage = MemoryStorage::default();
+    let mut game_boy = build_game_boy(&storage);
+
+    game_boy.run_frame().unwrap();
+    game_boy.pause().unwrap();
+    assert_eq!(storage.write_count(), 1);
+    assert!(!game_boy.cpu.bus.mbc.is_dirty());
+
+    // 書き換えられていなければ、もう一度止めても書き出さない
+    game_boy.resume();
+    game_boy.pause().unwrap();
+    assert_eq!(storage.write_count(), 1);
+}
+
+#[test]
+fn flush_on_exit() {
+    let storage = MemoryStorage::default();
+    let mut game_boy = build_game_boy(&storage);
+
+    // 終了時はフロントエンドがflush_save_fileを呼ぶ
+    game_boy.flush_save_file().unwrap();
+    assert_eq!(storage.write_count(), 0);
+
+    game_boy.run_frame().unwrap();
+    game_boy.flush_save_file().unwrap();
+    assert_eq!(storage.write_count(), 1);
+    game_boy.flush_save_file().unwrap();
+    assert_eq!(storage.write_count(), 1);
+
+    // 書き出したものを次に起動したときに読み込む
+    let game_boy = build_game_boy(&storage);
+    assert_eq!(game_boy.cpu.bus.mbc.save_data()[0], 0x42);
+    assert!(!game_boy.cpu.bus.mbc.is_dirty());
+}
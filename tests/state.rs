@@ -0,0 +1,74 @@
+// セーブステートを読み込むと書き出した時点のレジスタとメモリに戻り、形式が合わないものは状態を変えずに拒否することを確認する
+use game_boy_rust::error::EmuError;
+use game_boy_rust::GameBoy;
+
+mod common;
+
+// Bを増やしながら、DIVの値を0xC100からの256byteに順に書き込み続ける
+fn build_game_boy() -> GameBoy {
+    common::load_game_boy(common::build_rom(b"STATETEST", &[
+        (0x150, &[
+            0x21, 0x00, 0xC1, // LD HL, 0xC100
+            0xF0, 0x04,       // LDH A, (DIV)
+            0x77,             // LD (HL), A
+            0x2C,             // INC L
+            0x04,             // INC B
+            0x18, 0xF9        // JR -7
+        ])
+    ]))
+}
+
+fn memory(game_boy: &GameBoy) -> Vec<u8> {
+    (0xC000..0xE000).map(|address| game_boy.cpu.bus.peek(address).unwrap()).collect()
+}
+
+#[test]
+fn round_trip() {
+    let mut game_boy = build_game_boy();
+    game_boy.run_frame().unwrap();
+
+    let state = game_boy.save_state();
+    let registers = game_boy.cpu.registers();
+    let ram = memory(&game_boy);
+
+    game_boy.run_frame().unwrap();
+    let next_registers = game_boy.cpu.registers();
+    let next_ram = memory(&game_boy);
+    assert_ne!(next_registers, registers);
+    assert_ne!(next_ram, ram);
+
+    game_boy.load_state(&state).unwrap();
+    assert_eq!(game_boy.cpu.registers(), registers);
+    assert_eq!(memory(&game_boy), ram);
+    assert_eq!(game_boy.save_state(), state);
+
+    // 読み込んだ所から同じように実行が進む
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.registers(), next_registers);
+    assert_eq!(memory(&game_boy), next_ram);
+}
+
+#[test]
+fn rejects_wrong_magic_and_version() {
+    let mut game_boy = build_game_boy();
+    game_boy.run_frame().unwrap();
+    let state = game_boy.save_state();
+
+    game_boy.run_frame().unwrap();
+    let current = game_boy.save_state();
+
+    let mut wrong_magic = state.clone();
+    wrong_magic[0] ^= 0xFF;
+    assert!(matches!(game_boy.load_state(&wrong_magic), Err(EmuError::InvalidState(_))));
+    assert_eq!(game_boy.save_state(), current);
+
+    // バージョンはマジックナンバーの後の4byte
+    let mut wrong_version = state.clone();
+    wrong_version[4] = wrong_version[4].wrapping_add(1);
+    assert!(matches!(game_boy.load_state(&wrong_version), Err(EmuError::InvalidState(_))));
+    assert_eq!(game_boy.save_state(), current);
+
+    // 途中で切れているものも拒否する
+    assert!(matches!(game_boy.load_state(&state[..state.len() - 1]), Err(EmuError::InvalidState(_))));
+    assert_eq!(game_boy.save_state(), current);
+}
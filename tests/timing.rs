@@ -0,0 +1,31 @@
+// 命令の途中のメモリアクセスが、その時点までサイクルを進めた状態を読むことを確認する
+mod common;
+
+// DIVをリセットしてからnop_count個のNOPを実行し、LD A, (0xFF04)で読んだDIVを返す
+fn read_div_after_nops(nop_count: usize) -> u8 {
+    let mut program = vec![
+        0xAF,       // XOR A
+        0xE0, 0x04  // LDH (DIV), A
+    ];
+    program.extend(vec![0x00; nop_count]);
+    program.extend_from_slice(&[
+        0xFA, 0x04, 0xFF, // LD A, (0xFF04)
+        0xEA, 0x00, 0xC0, // LD (0xC000), A
+        0x3E, 0x01,       // LD A, 0x01
+        0xEA, 0x01, 0xC0, // LD (0xC001), A
+        0x18, 0xFE        // JR -2
+    ]);
+
+    let mut game_boy = common::load_game_boy(common::build_rom(b"TIMINGTEST", &[(0x150, &program)]));
+    game_boy.run_frame().unwrap();
+    assert_eq!(game_boy.cpu.bus.read(0xC001).unwrap(), 0x01);
+    game_boy.cpu.bus.read(0xC000).unwrap()
+}
+
+#[test]
+fn read_sees_cycles_before_access() {
+    // LD A, (a16)の読み込みは命令の4マシンサイクル目なので、
+    // DIVのリセットから 4 * NOPの数 + 16 サイクル後に行われる。DIVは256サイクルで1増える
+    assert_eq!(read_div_after_nops(59), 0x00);
+    assert_eq!(read_div_after_nops(60), 0x01);
+}
@@ -0,0 +1,91 @@
+// 命令のトレースが他のエミュレータと比べられる形式で書き出され、アドレスとフレームで範囲を絞れることを確認する
+use std::fs;
+use std::path::PathBuf;
+
+use game_boy_rust::trace::TraceConfig;
+use game_boy_rust::GameBoy;
+
+mod common;
+
+// 0x0150から、Aに0x12を入れてBを増やし続けるプログラム
+fn build_game_boy() -> GameBoy {
+    common::load_game_boy(common::build_rom(b"TRACETEST", &[
+        (0x150, &[
+            0x3E, 0x12, // LD A, 0x12
+            0x04,       // INC B
+            0x18, 0xFD  // JR -3
+        ])
+    ]))
+}
+
+fn trace_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("game_boy_trace_test_{}_{}.log", name, std::process::id()))
+}
+
+// 指定したフレーム数だけ実行して、書き出したトレースを返す
+fn run_trace(name: &str, config: TraceConfig, frames: usize) -> Vec<String> {
+    let path = trace_path(name);
+    let mut game_boy = build_game_boy();
+    game_boy.start_trace(&path, config).unwrap();
+    for _ in 0..frames {
+        game_boy.run_frame().unwrap();
+    }
+    game_boy.stop_trace().unwrap();
+
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    log.lines().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn trace_format() {
+    let lines = run_trace("format", TraceConfig::default(), 1);
+    assert_eq!(lines[..6], [
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01",
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00",
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:3E,12,04,18",
+        "A:12 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:04,18,FD,00",
+        "A:12 F:10 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0153 PCMEM:18,FD,00,00",
+        "A:12 F:10 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:04,18,FD,00"
+    ]);
+}
+
+#[test]
+fn start_and_stop_address() {
+    let config = TraceConfig { start: Some(0x0150), stop: Some(0x0153), ..Default::default() };
+    let lines = run_trace("address", config, 1);
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("PC:0150 PCMEM:3E,12,04,18"));
+    assert!(lines[1].ends_with("PC:0152 PCMEM:04,18,FD,00"));
+}
+
+#[test]
+fn frame_range() {
+    let all = run_trace("all", TraceConfig::default(), 3);
+    let config = TraceConfig { first_frame: Some(1), last_frame: Some(1), ..Default::default() };
+    let second = run_trace("second", config, 3);
+
+    assert!(!second.is_empty());
+    assert!(second.len() < all.len() / 2);
+    assert!(!second[0].contains("PC:0100"));
+    // 2フレーム目の行は全体のトレースの途中にそのまま含まれる
+    let offset = all.iter().position(|line| *line == second[0]).unwrap();
+    assert_eq!(all[offset..offset + second.len()], second[..]);
+}
+
+#[test]
+fn trace_options() {
+    let mut args: Vec<String> = ["--printer", "--trace", "out.log", "--trace-start", "$0150", "--trace-frames", "2-"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let (path, config) = TraceConfig::take_options(&mut args).unwrap().unwrap();
+    assert_eq!(path, PathBuf::from("out.log"));
+    assert_eq!(config, TraceConfig { start: Some(0x0150), first_frame: Some(2), ..Default::default() });
+    assert_eq!(args, vec!["--printer".to_string()]);
+
+    assert_eq!(TraceConfig::take_options(&mut args).unwrap(), None);
+    assert!(TraceConfig::take_options(&mut vec!["--trace-stop".to_string(), "150".to_string()]).is_err());
+    assert!(TraceConfig::take_options(&mut vec!["--trace".to_string()]).is_err());
+    assert!(TraceConfig::take_options(&mut vec!["--trace".to_string(), "a".to_string(), "--trace-frames".to_string(), "x".to_string()]).is_err());
+}